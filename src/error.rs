@@ -18,7 +18,8 @@ pub type NixUriResult<T> = Result<T, NixUriError>;
 /// relationship between fields (rather than a single value's literal shape)
 /// surface as named variants such as `FieldConflict`, `MissingScheme`, or
 /// `TooManyIndirectSegments`. `ServoUrl` wraps the upstream
-/// `url::ParseError` for tarball- and HTTP-style URLs.
+/// `url::ParseError` for tarball- and HTTP-style URLs; `Io` wraps the
+/// filesystem errors of the few operations that consult the disk.
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum NixUriError {
@@ -66,6 +67,38 @@ pub enum NixUriError {
     /// Wraps `url::ParseError` for tarball- and HTTP-style URLs.
     #[error("URL parsing error: {0}")]
     ServoUrl(#[from] url::ParseError),
+    /// [`crate::FlakeRef::from_local_dir`] found no flake at or above
+    /// `path`.
+    #[error("path `{}` is not part of a flake: {reason}", path.display())]
+    NotAFlake {
+        path: std::path::PathBuf,
+        reason: crate::NotAFlakeReason,
+    },
+    /// A [`crate::Resolver`] could not lock `input`, or its answer
+    /// contradicts a `rev` or `narHash` the input already pins.
+    #[error("cannot lock `{input}`: {reason}")]
+    Resolve { input: String, reason: String },
+    /// The git repository at `path` could not be read: missing or
+    /// corrupt objects, an unknown ref, or an unsupported format. Raised
+    /// by the `local-git` feature's repository inspection.
+    #[error("git repository `{}`: {reason}", path.display())]
+    Git {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+    /// A `flake.lock` input path does not lead to a node: an input that
+    /// is not declared, a reference to a missing node, or a `follows`
+    /// cycle. `input` is the `/`-separated path from the root.
+    #[error("lock file input `{input}`: {reason}")]
+    Lock { input: String, reason: String },
+    /// A filesystem operation on `path` failed (e.g. canonicalising a
+    /// local flake path that does not exist).
+    #[error("I/O error on `{}`: {source}", path.display())]
+    Io {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
 }
 
 /// What the parser was looking for when it failed.
@@ -4,21 +4,52 @@ use serde::{Deserialize, Serialize};
 
 use crate::error::{NixUriError, UnsupportedReason};
 
+mod attrs;
 pub(crate) mod encoding;
+pub use attrs::{Attr, Attrs};
 mod fr_type;
 pub use fr_type::FlakeRefType;
+mod immutable;
+pub use immutable::immutable_link;
+mod keys;
+pub use keys::{KeyType, PublicKey};
+mod local;
+pub use local::NotAFlakeReason;
+#[cfg(feature = "local-git")]
+mod local_git;
+#[cfg(feature = "local-git")]
+pub use local_git::{LocalGitResolver, LocalGitState};
 pub(crate) mod location_params;
 pub(crate) use location_params::LocationParamKeys;
 pub use location_params::LocationParameters;
 mod transport_layer;
 pub use transport_layer::TransportLayer;
+mod flakehub;
+pub use flakehub::{FlakeHubRef, FlakeHubVersion};
 mod forge;
 pub use forge::{GitForge, GitForgePlatform};
+#[cfg(feature = "forge-api")]
+mod forge_api;
+#[cfg(feature = "forge-api")]
+pub use forge_api::ForgeResolver;
+pub(crate) mod parse_options;
+pub use parse_options::{ParseOptions, SchemeHandler};
+mod paths;
+pub use paths::normalize_dir;
+mod ref_name;
+pub use ref_name::{Channel, ChannelFamily, ChannelRelease, PullNamespace, QualifiedRef, RefName};
+mod resolve;
+pub use resolve::{LockedAttrs, MemoryResolver, Resolver, lock};
+mod rev;
+pub use rev::{Rev, RevKind};
 mod resource_url;
 pub use resource_url::{ResourceType, ResourceUrl};
 #[cfg(test)]
 mod proptest;
+mod update;
+pub use update::{Candidate, UpdatePolicy, UpdateProposal, UpdateReason};
 pub(crate) mod validators;
+pub use validators::{RefFormatViolation, check_ref_format};
 
 /// Names where a ref or rev is rendered in a `FlakeRef`.
 ///
@@ -188,6 +219,18 @@ impl FlakeRef {
         }
     }
 
+    /// Typed `FlakeHub` view for `https://flakehub.com/f/<org>/<project>/<version>.tar.gz`
+    /// tarballs and the immutable `https://api.flakehub.com/f/pinned/...`
+    /// URLs they resolve to. Returns `None` for every other kind and URL shape,
+    /// including `FlakeHub` URLs whose version segment is not a semver
+    /// requirement.
+    pub fn flakehub(&self) -> Option<FlakeHubRef> {
+        match self.kind() {
+            FlakeRefType::Resource(res) => FlakeHubRef::from_resource(res),
+            _ => None,
+        }
+    }
+
     /// The `ref_` (branch or tag name) for kinds that carry one, borrowed.
     pub fn ref_(&self) -> Option<&str> {
         match self.kind() {
@@ -209,6 +252,26 @@ impl FlakeRef {
         }
     }
 
+    /// The `rev` as a typed [`Rev`]; `None` when no rev is set or the slot
+    /// holds something that is not hex (only reachable through the
+    /// unchecked `set_rev` mutators).
+    pub fn typed_rev(&self) -> Option<Rev> {
+        self.rev().and_then(|r| Rev::parse(r).ok())
+    }
+
+    /// `true` when the `rev` is an abbreviated prefix (accepted only under
+    /// [`ParseOptions::with_short_revs`]) that still has to be resolved
+    /// against the repository before the input identifies one commit.
+    pub fn has_unresolved_rev(&self) -> bool {
+        self.typed_rev().is_some_and(|r| r.is_abbreviated())
+    }
+
+    /// The `ref_` classified into a [`RefName`] (semver tag, channel
+    /// branch, fully qualified ref, or other); `None` when no ref is set.
+    pub fn ref_name(&self) -> Option<RefName> {
+        self.ref_().map(RefName::parse)
+    }
+
     /// Whichever of `rev` / `ref_` is set, preferring `rev` when pinned;
     /// the typical "what does this ref resolve to?" answer. Returns
     /// borrowed `&str`; callers wanting an owned `String` use
@@ -548,7 +611,13 @@ impl FlakeRef {
                 if let Some(pk) = self.params.public_key.as_deref() {
                     entries.push(("publicKey", pk));
                 }
-                if let Some(pks) = self.params.public_keys.as_deref() {
+                // Re-encode a well-formed key list the way Nix writes it;
+                // anything unparseable is passed through as given.
+                let public_keys = self.params.public_keys.as_deref().map(|pks| {
+                    PublicKey::parse_list(pks)
+                        .map_or_else(|_| pks.to_string(), |keys| PublicKey::render_list(&keys))
+                });
+                if let Some(pks) = public_keys.as_deref() {
                     entries.push(("publicKeys", pks));
                 }
                 entries.sort_by(|a, b| a.0.cmp(b.0));
@@ -664,6 +733,22 @@ impl Display for FlakeRef {
     }
 }
 
+impl FlakeRef {
+    /// Parse `input` under `options`.
+    ///
+    /// Scheme aliases ([`ParseOptions::with_alias`]) and custom
+    /// [`SchemeHandler`]s ([`ParseOptions::with_handler`]) decide how the
+    /// input is read; the remaining options tighten or relax what is
+    /// accepted: [`ParseOptions::with_strict_refs`],
+    /// [`ParseOptions::with_short_revs`] and
+    /// [`ParseOptions::with_normalized_dirs`]. With
+    /// `ParseOptions::default()` this is identical to
+    /// [`std::str::FromStr`].
+    pub fn parse_with(input: &str, options: &ParseOptions) -> Result<Self, NixUriError> {
+        crate::parser::parse_nix_uri_with(input, options)
+    }
+}
+
 impl TryFrom<&str> for FlakeRef {
     type Error = NixUriError;
 
@@ -0,0 +1,307 @@
+//! Nix's attribute-set form of a flake ref.
+//!
+//! `flake.lock` nodes (`original`, `locked`) and registry entries (`from`,
+//! `to`) spell inputs as attribute sets rather than URLs:
+//! `{ "type": "github", "owner": "NixOS", "repo": "nixpkgs" }`. These
+//! conversions map that form onto [`FlakeRef`] and back.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{
+        FlakeRef, FlakeRefType, RefLocation,
+        encoding::{encode_path_segment, encode_query},
+    },
+};
+
+/// A fetcher attribute set, keyed by attribute name. Sorted, like the
+/// JSON Nix writes.
+pub type Attrs = BTreeMap<String, Attr>;
+
+/// One attribute value. Nix's fetcher attributes are strings, unsigned
+/// integers (`lastModified`, `revCount`) or booleans (`submodules`, ...).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Attr {
+    Bool(bool),
+    Int(u64),
+    String(String),
+}
+
+/// Attributes whose value is an integer.
+const INT_ATTRS: &[&str] = &["lastModified", "revCount"];
+
+/// Attributes whose value is a boolean.
+const BOOL_ATTRS: &[&str] = &[
+    "allRefs",
+    "exportIgnore",
+    "lfs",
+    "shallow",
+    "submodules",
+    "verifyCommit",
+];
+
+/// Attributes that make up the URL rather than its query string.
+const LOCATION_ATTRS: &[&str] = &["id", "owner", "path", "repo", "type", "url"];
+
+impl FlakeRef {
+    /// Build a ref from its attribute-set form.
+    ///
+    /// `type` selects the input kind (`indirect`, `github`, `gitlab`,
+    /// `sourcehut`, `git`, `hg`, `tarball`, `file`, `path`); the remaining
+    /// attributes become the ref's parameters. Attributes this crate has
+    /// no slot for are kept as arbitrary parameters, and Nix-internal ones
+    /// (`__final`) are dropped. A missing or mistyped attribute surfaces
+    /// [`NixUriError::InvalidValue`] naming it; the assembled ref is then
+    /// validated like a parsed one.
+    pub fn from_attrs(attrs: &Attrs) -> NixUriResult<Self> {
+        let kind = required(attrs, "type")?;
+        let mut uri = match kind {
+            "indirect" => format!("flake:{}", required(attrs, "id")?),
+            "github" | "gitlab" | "sourcehut" => format!(
+                "{kind}:{}/{}",
+                encode_path_segment(required(attrs, "owner")?),
+                required(attrs, "repo")?
+            ),
+            "git" | "hg" | "tarball" | "file" => {
+                let url = required(attrs, "url")?;
+                if url.starts_with('/') {
+                    format!("{kind}+file://{url}")
+                } else {
+                    format!("{kind}+{url}")
+                }
+            }
+            "path" => format!("path:{}", required(attrs, "path")?),
+            other => {
+                return Err(NixUriError::InvalidValue {
+                    field: "type",
+                    reason: format!("unknown input type `{other}`"),
+                });
+            }
+        };
+
+        let mut separator = if uri.contains('?') { '&' } else { '?' };
+        for (key, value) in attrs {
+            if LOCATION_ATTRS.contains(&key.as_str()) || key.starts_with("__") {
+                continue;
+            }
+            let value = match value {
+                Attr::Int(n) if !BOOL_ATTRS.contains(&key.as_str()) => n.to_string(),
+                Attr::Bool(b) if !INT_ATTRS.contains(&key.as_str()) => {
+                    if *b { "1" } else { "0" }.to_string()
+                }
+                Attr::String(s)
+                    if !INT_ATTRS.contains(&key.as_str())
+                        && !BOOL_ATTRS.contains(&key.as_str()) =>
+                {
+                    s.clone()
+                }
+                _ => {
+                    let field = INT_ATTRS
+                        .iter()
+                        .chain(BOOL_ATTRS)
+                        .find(|name| **name == key)
+                        .copied()
+                        .unwrap_or("attrs");
+                    return Err(NixUriError::InvalidValue {
+                        field,
+                        reason: format!("attribute `{key}` has the wrong type"),
+                    });
+                }
+            };
+            uri.push(separator);
+            uri.push_str(&encode_query(key));
+            uri.push('=');
+            uri.push_str(&encode_query(&value));
+            separator = '&';
+        }
+
+        let mut flake_ref: Self = uri.parse()?;
+        // Nix renders forge and indirect refs with the ref or rev as a path
+        // segment; a ref containing `/` has to stay in the query.
+        if matches!(
+            flake_ref.kind(),
+            FlakeRefType::GitForge(_) | FlakeRefType::Indirect { .. }
+        ) && !flake_ref.ref_().is_some_and(|r| r.contains('/'))
+        {
+            flake_ref.set_ref_location(RefLocation::PathComponent);
+        }
+        Ok(flake_ref)
+    }
+
+    /// The attribute-set form of this ref, as `flake.lock` and registry
+    /// files spell it. The inverse of [`FlakeRef::from_attrs`]; the
+    /// `#fragment` has no attribute and is not included.
+    pub fn to_attrs(&self) -> Attrs {
+        let mut attrs = Attrs::new();
+        match self.kind() {
+            FlakeRefType::Indirect { id, .. } => {
+                attrs.insert("type".into(), Attr::String("indirect".into()));
+                attrs.insert("id".into(), Attr::String(id.clone()));
+            }
+            FlakeRefType::GitForge(forge) => {
+                attrs.insert("type".into(), Attr::String(forge.platform.to_string()));
+                attrs.insert("owner".into(), Attr::String(forge.owner.clone()));
+                attrs.insert("repo".into(), Attr::String(forge.repo.clone()));
+            }
+            FlakeRefType::Resource(res) => {
+                // Unrecognised query parameters belong to the fetched URL.
+                let mut url = match &res.transport_type {
+                    Some(transport) => format!("{transport}://{}", res.location),
+                    None => res.location.clone(),
+                };
+                for (i, (key, value)) in self.params().arbitrary().iter().enumerate() {
+                    url.push(if i == 0 { '?' } else { '&' });
+                    url.push_str(&encode_query(key));
+                    url.push('=');
+                    url.push_str(&encode_query(value));
+                }
+                attrs.insert("type".into(), Attr::String(res.res_type.to_string()));
+                attrs.insert("url".into(), Attr::String(url));
+            }
+            FlakeRefType::Path { path, .. } => {
+                attrs.insert("type".into(), Attr::String("path".into()));
+                attrs.insert("path".into(), Attr::String(path.clone()));
+            }
+        }
+        if let Some(ref_) = self.ref_() {
+            attrs.insert("ref".into(), Attr::String(ref_.into()));
+        }
+        if let Some(rev) = self.rev() {
+            attrs.insert("rev".into(), Attr::String(rev.into()));
+        }
+
+        let mut entries = self.params().entries();
+        if matches!(self.kind(), FlakeRefType::Resource(_)) {
+            // `entries` lists the arbitrary parameters last; they went into
+            // `url` above.
+            entries.truncate(entries.len() - self.params().arbitrary().len());
+        }
+        for (key, value) in entries {
+            let attr = if INT_ATTRS.contains(&key) {
+                value
+                    .parse()
+                    .map_or_else(|_| Attr::String(value.into()), Attr::Int)
+            } else if BOOL_ATTRS.contains(&key) {
+                Attr::Bool(value == "1")
+            } else {
+                Attr::String(value.into())
+            };
+            attrs.insert(key.into(), attr);
+        }
+        attrs
+    }
+}
+
+fn required<'a>(attrs: &'a Attrs, name: &'static str) -> NixUriResult<&'a str> {
+    match attrs.get(name) {
+        Some(Attr::String(value)) => Ok(value),
+        Some(_) => Err(NixUriError::InvalidValue {
+            field: name,
+            reason: format!("attribute `{name}` must be a string"),
+        }),
+        None => Err(NixUriError::InvalidValue {
+            field: name,
+            reason: format!("missing attribute `{name}`"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+
+    const SHA: &str = "b2df4e4e80e04cbb33a350f87717f4bd6140d298";
+
+    fn attrs(json: &str) -> Attrs {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[rstest]
+    #[case(r#"{"type":"indirect","id":"nixpkgs"}"#, "flake:nixpkgs")]
+    #[case(
+        r#"{"type":"indirect","id":"nixpkgs","ref":"nixos-24.05"}"#,
+        "flake:nixpkgs/nixos-24.05"
+    )]
+    #[case(
+        r#"{"type":"github","owner":"NixOS","repo":"nixpkgs","ref":"nixos-unstable"}"#,
+        "github:NixOS/nixpkgs/nixos-unstable"
+    )]
+    #[case(
+        r#"{"type":"github","owner":"o","repo":"r","ref":"release/1.0"}"#,
+        "github:o/r?ref=release/1.0"
+    )]
+    #[case(
+        r#"{"type":"gitlab","owner":"group/sub","repo":"r","host":"gitlab.example"}"#,
+        "gitlab:group%2Fsub/r?host=gitlab.example"
+    )]
+    #[case(
+        r#"{"type":"git","url":"https://example.com/r","ref":"main","submodules":true,"dir":"sub"}"#,
+        "git+https://example.com/r?dir=sub&ref=main&submodules=1"
+    )]
+    #[case(r#"{"type":"git","url":"file:///srv/repo"}"#, "git+file:///srv/repo")]
+    #[case(
+        r#"{"type":"hg","url":"https://example.com/r"}"#,
+        "hg+https://example.com/r"
+    )]
+    #[case(
+        r#"{"type":"tarball","url":"https://example.com/x.tar.gz?channel=stable"}"#,
+        "https://example.com/x.tar.gz?channel=stable"
+    )]
+    #[case(r#"{"type":"path","path":"./sub"}"#, "path:./sub")]
+    fn from_attrs_renders(#[case] json: &str, #[case] expected: &str) {
+        let flake_ref = FlakeRef::from_attrs(&attrs(json)).unwrap();
+        assert_eq!(flake_ref.to_string(), expected);
+        assert_eq!(flake_ref.to_attrs(), attrs(json));
+    }
+
+    #[test]
+    fn locked_node_round_trips() {
+        let json = format!(
+            r#"{{"lastModified":1700000000,"narHash":"sha256-AAAA","owner":"NixOS","repo":"nixpkgs","rev":"{SHA}","type":"github"}}"#
+        );
+        let flake_ref = FlakeRef::from_attrs(&attrs(&json)).unwrap();
+        assert_eq!(
+            flake_ref.to_string(),
+            format!("github:NixOS/nixpkgs/{SHA}?lastModified=1700000000&narHash=sha256-AAAA")
+        );
+        assert_eq!(serde_json::to_string(&flake_ref.to_attrs()).unwrap(), json);
+    }
+
+    #[test]
+    fn bare_git_path_becomes_file_url() {
+        let flake_ref =
+            FlakeRef::from_attrs(&attrs(r#"{"type":"git","url":"/srv/repo"}"#)).unwrap();
+        assert_eq!(flake_ref.to_string(), "git+file:///srv/repo");
+    }
+
+    #[test]
+    fn internal_attrs_are_dropped() {
+        let flake_ref =
+            FlakeRef::from_attrs(&attrs(r#"{"type":"path","path":"/p","__final":true}"#)).unwrap();
+        assert_eq!(flake_ref.to_string(), "path:/p");
+    }
+
+    #[rstest]
+    #[case(r#"{"id":"nixpkgs"}"#, "type")]
+    #[case(r#"{"type":"github","owner":"o"}"#, "repo")]
+    #[case(r#"{"type":"darcs","url":"x"}"#, "type")]
+    #[case(
+        r#"{"type":"github","owner":"o","repo":"r","revCount":"5"}"#,
+        "revCount"
+    )]
+    #[case(r#"{"type":"git","url":"https://h/r","submodules":1}"#, "submodules")]
+    #[case(r#"{"type":"github","owner":1,"repo":"r"}"#, "owner")]
+    fn from_attrs_rejects(#[case] json: &str, #[case] expected: &str) {
+        assert_matches!(
+            FlakeRef::from_attrs(&attrs(json)),
+            Err(NixUriError::InvalidValue { field, .. }) => assert_eq!(field, expected)
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Typed view over `FlakeHub` tarball references.
+//!
+//! `FlakeHub` publishes flakes at
+//! `https://flakehub.com/f/<org>/<project>/<version-req>.tar.gz`, and
+//! resolves those to immutable
+//! `https://api.flakehub.com/f/pinned/<org>/<project>/<version>/<id>/source.tar.gz`
+//! URLs. The parser
+//! classifies those as an ordinary `Resource(Tarball)` (they are one, as far
+//! as Nix is concerned); [`crate::FlakeRef::flakehub`] recognises the shape
+//! and projects it onto [`FlakeHubRef`] so tooling can treat the org,
+//! project, and version requirement as data rather than URL text.
+
+use std::fmt::Display;
+
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use semver::{Version, VersionReq};
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{
+        FlakeRef, FlakeRefType, ResourceType, ResourceUrl, TransportLayer, encoding::decode_percent,
+    },
+};
+
+/// Canonical `FlakeHub` host; the only one [`crate::FlakeRef::flakehub`]
+/// recognises.
+pub(crate) const FLAKEHUB_HOST: &str = "flakehub.com";
+/// Host and path prefix of the immutable URLs `FlakeHub` pins to.
+const FLAKEHUB_PINNED: &str = "api.flakehub.com/f/pinned/";
+
+/// Bytes that must be escaped inside the version segment. Ranges such as
+/// `>=0.1, <0.3` carry characters a URL path cannot hold raw; `*`, `=` and
+/// `.` stay literal because `FlakeHub` documents them that way.
+const VERSION_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b',')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'^');
+/// The version of a pinned URL additionally escapes the `+` of build
+/// metadata, as `FlakeHub` does.
+const VERSION_SEGMENT_PINNED: &AsciiSet = &VERSION_SEGMENT.add(b'+');
+
+/// The version requirement segment of a `FlakeHub` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FlakeHubVersion {
+    /// `*`: the newest published release.
+    Any,
+    /// `=x.y.z`: exactly one release.
+    Exact(Version),
+    /// Any other semver requirement (`0.1.*`, `0.1`, `>=0.1, <0.3`).
+    /// `raw` keeps the spelling from the URL so `Display` round-trips it;
+    /// `semver` renders some requirements differently (`0.1` as `^0.1`).
+    Requirement { raw: String, req: VersionReq },
+}
+
+impl FlakeHubVersion {
+    /// Parse a version segment (already percent-decoded, `.tar.gz`
+    /// stripped). Surfaces [`NixUriError::InvalidValue`] with field
+    /// `version` when the segment is not a semver requirement.
+    pub fn parse(raw: &str) -> NixUriResult<Self> {
+        let invalid = |reason: String| NixUriError::InvalidValue {
+            field: "version",
+            reason,
+        };
+        if raw == "*" {
+            return Ok(Self::Any);
+        }
+        if let Some(exact) = raw.strip_prefix('=') {
+            if let Ok(version) = Version::parse(exact.trim()) {
+                return Ok(Self::Exact(version));
+            }
+        }
+        let req = VersionReq::parse(raw).map_err(|e| invalid(e.to_string()))?;
+        Ok(Self::Requirement {
+            raw: raw.to_string(),
+            req,
+        })
+    }
+
+    /// Whether `version` satisfies this requirement.
+    pub fn matches(&self, version: &Version) -> bool {
+        match self {
+            // `*` follows semver's wildcard rule: pre-releases only match
+            // when asked for explicitly.
+            Self::Any => version.pre.is_empty(),
+            Self::Exact(v) => v == version,
+            Self::Requirement { req, .. } => req.matches(version),
+        }
+    }
+}
+
+impl Display for FlakeHubVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => f.write_str("*"),
+            Self::Exact(v) => write!(f, "={v}"),
+            Self::Requirement { raw, .. } => f.write_str(raw),
+        }
+    }
+}
+
+/// A `FlakeHub` flake: `https://flakehub.com/f/<org>/<project>/<version>.tar.gz`,
+/// or an immutable
+/// `https://api.flakehub.com/f/pinned/<org>/<project>/<version>/<id>/source.tar.gz`
+/// URL (whose version is always [`FlakeHubVersion::Exact`]).
+///
+/// Returned by [`crate::FlakeRef::flakehub`]; convert back with
+/// [`Self::to_flake_ref`] after editing (e.g. bumping `version`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FlakeHubRef {
+    pub org: String,
+    pub project: String,
+    pub version: FlakeHubVersion,
+    /// For a pinned URL, the path after the version (`<id>/source.tar.gz`
+    /// or `<id>.tar.gz`), kept verbatim.
+    pub pinned: Option<String>,
+}
+
+impl FlakeHubRef {
+    /// Construct a `FlakeHub` reference from its three components.
+    pub fn new(
+        org: impl Into<String>,
+        project: impl Into<String>,
+        version: FlakeHubVersion,
+    ) -> Self {
+        Self {
+            org: org.into(),
+            project: project.into(),
+            version,
+            pinned: None,
+        }
+    }
+
+    /// Consuming builder that swaps the version requirement. The pin
+    /// belongs to the old version, so the result is an unpinned
+    /// `flakehub.com/f/` reference.
+    pub fn with_version(mut self, version: FlakeHubVersion) -> Self {
+        self.version = version;
+        self.pinned = None;
+        self
+    }
+
+    /// Render as the `Resource(Tarball)` `FlakeRef` the parser produces for
+    /// the same URL. Query parameters and fragment are not part of the
+    /// view; callers carrying them over set them on the result.
+    pub fn to_flake_ref(&self) -> FlakeRef {
+        let location = match (&self.pinned, &self.version) {
+            (Some(pinned), FlakeHubVersion::Exact(version)) => {
+                let version = version.to_string();
+                let version = utf8_percent_encode(&version, VERSION_SEGMENT_PINNED);
+                format!(
+                    "{FLAKEHUB_PINNED}{}/{}/{version}/{pinned}",
+                    self.org, self.project
+                )
+            }
+            _ => {
+                let version = self.version.to_string();
+                let version = utf8_percent_encode(&version, VERSION_SEGMENT);
+                format!(
+                    "{FLAKEHUB_HOST}/f/{}/{}/{version}.tar.gz",
+                    self.org, self.project
+                )
+            }
+        };
+        FlakeRef::new(FlakeRefType::Resource(ResourceUrl::new(
+            ResourceType::Tarball,
+            location,
+            Some(TransportLayer::Https),
+        )))
+    }
+
+    /// Recognise the `FlakeHub` shapes on a parsed resource. `None` for
+    /// any other host or path layout, and for version segments that are
+    /// not a semver requirement (or, pinned, a version); those stay
+    /// ordinary tarballs.
+    pub(crate) fn from_resource(res: &ResourceUrl) -> Option<Self> {
+        if !matches!(res.res_type, ResourceType::Tarball)
+            || !matches!(res.transport_type, Some(TransportLayer::Https))
+        {
+            return None;
+        }
+        if let Some(path) = res.location.strip_prefix(FLAKEHUB_PINNED) {
+            return Self::from_pinned_path(path);
+        }
+        let path = res
+            .location
+            .strip_prefix(FLAKEHUB_HOST)?
+            .strip_prefix("/f/")?;
+        let mut segments = path.split('/');
+        let (Some(org), Some(project), Some(version), None) = (
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) else {
+            return None;
+        };
+        if org.is_empty() || project.is_empty() {
+            return None;
+        }
+        let version = decode_percent(version.strip_suffix(".tar.gz")?).ok()?;
+        Some(Self {
+            org: org.to_string(),
+            project: project.to_string(),
+            version: FlakeHubVersion::parse(&version).ok()?,
+            pinned: None,
+        })
+    }
+
+    /// `<org>/<project>/<version>/<id>/source.tar.gz` (or `<id>.tar.gz`).
+    fn from_pinned_path(path: &str) -> Option<Self> {
+        let mut segments = path.splitn(4, '/');
+        let (Some(org), Some(project), Some(version), Some(pinned)) = (
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) else {
+            return None;
+        };
+        let shaped = match pinned.split_once('/') {
+            Some((id, "source.tar.gz")) => !id.is_empty(),
+            Some(_) => false,
+            None => pinned.len() > ".tar.gz".len() && pinned.ends_with(".tar.gz"),
+        };
+        if org.is_empty() || project.is_empty() || !shaped {
+            return None;
+        }
+        let version = Version::parse(&decode_percent(version).ok()?).ok()?;
+        Some(Self {
+            org: org.to_string(),
+            project: project.to_string(),
+            version: FlakeHubVersion::Exact(version),
+            pinned: Some(pinned.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+
+    fn flakehub(uri: &str) -> Option<FlakeHubRef> {
+        uri.parse::<FlakeRef>().unwrap().flakehub()
+    }
+
+    #[test]
+    fn wildcard_any() {
+        let fh = flakehub("https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz").unwrap();
+        assert_eq!(fh.org, "NixOS");
+        assert_eq!(fh.project, "nixpkgs");
+        assert_eq!(fh.version, FlakeHubVersion::Any);
+    }
+
+    #[test]
+    fn minor_wildcard() {
+        let fh = flakehub("https://flakehub.com/f/NixOS/nixpkgs/0.1.*.tar.gz").unwrap();
+        assert_matches!(&fh.version, FlakeHubVersion::Requirement { raw, .. } => assert_eq!(raw, "0.1.*"));
+        assert!(fh.version.matches(&Version::new(0, 1, 9)));
+        assert!(!fh.version.matches(&Version::new(0, 2, 0)));
+    }
+
+    #[test]
+    fn exact_version() {
+        let fh = flakehub("https://flakehub.com/f/NixOS/nixpkgs/=0.2305.490.tar.gz").unwrap();
+        assert_eq!(
+            fh.version,
+            FlakeHubVersion::Exact(Version::new(0, 2305, 490))
+        );
+        assert!(fh.version.matches(&Version::new(0, 2305, 490)));
+        assert!(!fh.version.matches(&Version::new(0, 2305, 491)));
+    }
+
+    #[test]
+    fn encoded_range() {
+        let uri = "https://flakehub.com/f/DeterminateSystems/fh/%3E=0.1%2C%20%3C0.3.tar.gz";
+        let fh = flakehub(uri).unwrap();
+        assert_eq!(fh.version.to_string(), ">=0.1, <0.3");
+        assert!(fh.version.matches(&Version::new(0, 2, 5)));
+        assert_eq!(fh.to_flake_ref().to_string(), uri);
+    }
+
+    #[rstest]
+    #[case("https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz")]
+    #[case("https://flakehub.com/f/NixOS/nixpkgs/0.1.*.tar.gz")]
+    #[case("https://flakehub.com/f/NixOS/nixpkgs/0.1.tar.gz")]
+    #[case("https://flakehub.com/f/NixOS/nixpkgs/=0.2305.490.tar.gz")]
+    fn to_flake_ref_round_trips(#[case] uri: &str) {
+        let parsed: FlakeRef = uri.parse().unwrap();
+        assert_eq!(parsed.flakehub().unwrap().to_flake_ref(), parsed);
+    }
+
+    #[test]
+    fn with_version_rewrites_url() {
+        let fh = flakehub("https://flakehub.com/f/NixOS/nixpkgs/0.1.*.tar.gz")
+            .unwrap()
+            .with_version(FlakeHubVersion::parse("0.2405.*").unwrap());
+        assert_eq!(
+            fh.to_flake_ref().to_string(),
+            "https://flakehub.com/f/NixOS/nixpkgs/0.2405.*.tar.gz"
+        );
+    }
+
+    #[rstest]
+    #[case("https://example.com/f/NixOS/nixpkgs/*.tar.gz")]
+    #[case("https://flakehub.com/f/NixOS/*.tar.gz")]
+    #[case("https://flakehub.com/f/NixOS/nixpkgs/extra/*.tar.gz")]
+    #[case("https://flakehub.com/f/NixOS/nixpkgs/not-a-version.tar.gz")]
+    #[case("https://flakehub.com/flake/NixOS/nixpkgs/*.tar.gz")]
+    #[case("http://flakehub.com/f/NixOS/nixpkgs/*.tar.gz")]
+    #[case("github:NixOS/nixpkgs")]
+    fn non_flakehub_shapes_are_none(#[case] uri: &str) {
+        assert_eq!(flakehub(uri), None);
+    }
+
+    #[rstest]
+    #[case(
+        "https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2311.1/018c/source.tar.gz",
+        "0.2311.1",
+        "018c/source.tar.gz"
+    )]
+    #[case(
+        "https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2311.554738%2Brev-abc/018c.tar.gz",
+        "0.2311.554738+rev-abc",
+        "018c.tar.gz"
+    )]
+    fn pinned_urls(#[case] uri: &str, #[case] version: &str, #[case] pinned: &str) {
+        let fh = flakehub(uri).unwrap();
+        assert_eq!((fh.org.as_str(), fh.project.as_str()), ("NixOS", "nixpkgs"));
+        assert_eq!(
+            fh.version,
+            FlakeHubVersion::Exact(Version::parse(version).unwrap())
+        );
+        assert_eq!(fh.pinned.as_deref(), Some(pinned));
+        assert_eq!(fh.to_flake_ref().to_string(), uri);
+        assert_eq!(
+            fh.with_version(FlakeHubVersion::Any)
+                .to_flake_ref()
+                .to_string(),
+            "https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz"
+        );
+    }
+
+    #[rstest]
+    #[case("https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2311.1.tar.gz")]
+    #[case("https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2311.*/018c.tar.gz")]
+    #[case("https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2311.1/018c/other.tar.gz")]
+    #[case("https://api.flakehub.com/f/NixOS/nixpkgs/0.2311.1/018c.tar.gz")]
+    fn malformed_pinned_urls_are_none(#[case] uri: &str) {
+        assert_eq!(flakehub(uri), None);
+    }
+
+    #[test]
+    fn invalid_requirement_rejected() {
+        assert_matches!(
+            FlakeHubVersion::parse("latest"),
+            Err(NixUriError::InvalidValue {
+                field: "version",
+                ..
+            })
+        );
+    }
+}
@@ -0,0 +1,441 @@
+//! Locking forge refs through the forges' REST APIs.
+//!
+//! [`ForgeResolver`] answers `github:` / `gitlab:` / `sourcehut:` refs the
+//! way Nix's git-archive fetchers do: a branch or tag (or `HEAD`) is
+//! looked up to its commit, and the commit's committer date becomes
+//! `lastModified`. Requests go through an [`HttpClient`], so the caller
+//! picks the HTTP stack and tests replay recorded responses. Requires the
+//! `forge-api` feature.
+
+use std::collections::HashMap;
+
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde_json::Value;
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{FlakeRef, FlakeRefType, GitForge, GitForgePlatform, LockedAttrs, Resolver, Rev},
+    http::{HttpClient, HttpRequest, HttpResponse},
+    time::parse_rfc3339,
+};
+
+/// Everything but RFC 3986 unreserved characters, so a GitLab project
+/// path (`group/sub/repo`) becomes a single path segment.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// A [`Resolver`] for forge refs backed by the GitHub, GitLab and
+/// `SourceHut` HTTP APIs.
+///
+/// Hosts default to the public instances; a `?host=` on the ref selects a
+/// self-hosted one (GitHub Enterprise's API lives under `/api/v3`).
+/// `SourceHut` has no commit-date endpoint, so its refs lock without
+/// `lastModified`. No `narHash` is computed: that needs the tarball.
+#[derive(Debug, Clone)]
+pub struct ForgeResolver<C> {
+    client: C,
+    tokens: HashMap<String, String>,
+    api_bases: HashMap<String, String>,
+}
+
+impl<C: HttpClient> ForgeResolver<C> {
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            tokens: HashMap::new(),
+            api_bases: HashMap::new(),
+        }
+    }
+
+    /// Authenticate requests to `host` with `token`. GitHub and `SourceHut`
+    /// tokens are sent as-is; GitLab tokens follow Nix's `access-tokens`
+    /// convention (`PAT:<token>` or `OAuth2:<token>`, a bare token being
+    /// read as a personal access token).
+    pub fn with_token(mut self, host: impl Into<String>, token: impl Into<String>) -> Self {
+        self.tokens.insert(host.into(), token.into());
+        self
+    }
+
+    /// Read tokens from Nix's `access-tokens` setting: whitespace-separated
+    /// `host=token` pairs. Malformed entries are skipped, as Nix does.
+    pub fn with_access_tokens(mut self, setting: &str) -> Self {
+        for entry in setting.split_whitespace() {
+            if let Some((host, token)) = entry.split_once('=') {
+                if !host.is_empty() && !token.is_empty() {
+                    self.tokens.insert(host.into(), token.into());
+                }
+            }
+        }
+        self
+    }
+
+    /// Send `host`'s API requests to `base` instead (no trailing slash):
+    /// a mirror, a proxy, or a local stand-in server.
+    pub fn with_api_base(mut self, host: impl Into<String>, base: impl Into<String>) -> Self {
+        self.api_bases.insert(host.into(), base.into());
+        self
+    }
+
+    fn get(&self, flake_ref: &FlakeRef, host: &str, url: String) -> NixUriResult<HttpResponse> {
+        let mut request = HttpRequest::new(url);
+        if let Some(token) = self.tokens.get(host) {
+            request = match platform_of(flake_ref) {
+                Some(GitForgePlatform::GitLab) => match token.split_once(':') {
+                    Some(("OAuth2", token)) => {
+                        request.with_header("Authorization", format!("Bearer {token}"))
+                    }
+                    Some(("PAT", token)) => request.with_header("PRIVATE-TOKEN", token),
+                    _ => request.with_header("PRIVATE-TOKEN", token.as_str()),
+                },
+                Some(GitForgePlatform::GitHub) => {
+                    request.with_header("Authorization", format!("token {token}"))
+                }
+                _ => request.with_header("Authorization", format!("Bearer {token}")),
+            };
+        }
+        let response = self.client.get(&request)?;
+        if response.is_success() {
+            return Ok(response);
+        }
+        let reason = match response.status {
+            401 | 403 => format!(
+                "{} refused access (HTTP {}); check the access token or rate limit",
+                request.url, response.status
+            ),
+            404 => format!("{} not found", request.url),
+            status => format!("HTTP {status} from {}", request.url),
+        };
+        Err(resolve_error(flake_ref, reason))
+    }
+
+    fn api_base(&self, host: &str, default: impl FnOnce() -> String) -> String {
+        self.api_bases.get(host).cloned().unwrap_or_else(default)
+    }
+
+    fn github(
+        &self,
+        flake_ref: &FlakeRef,
+        forge: &GitForge,
+        host: &str,
+    ) -> NixUriResult<LockedAttrs> {
+        let base = self.api_base(host, || {
+            if host == "github.com" {
+                "https://api.github.com".into()
+            } else {
+                format!("https://{host}/api/v3")
+            }
+        });
+        let url = format!(
+            "{base}/repos/{}/{}/commits/{}",
+            forge.owner,
+            forge.repo,
+            target(forge)
+        );
+        let json = parse_json(flake_ref, &self.get(flake_ref, host, url)?)?;
+        commit_attrs(
+            flake_ref,
+            json.get("sha"),
+            json.pointer("/commit/committer/date"),
+        )
+    }
+
+    fn gitlab(
+        &self,
+        flake_ref: &FlakeRef,
+        forge: &GitForge,
+        host: &str,
+    ) -> NixUriResult<LockedAttrs> {
+        let base = self.api_base(host, || format!("https://{host}/api/v4"));
+        let project =
+            utf8_percent_encode(&format!("{}/{}", forge.owner, forge.repo), SEGMENT).to_string();
+        let commits = format!("{base}/projects/{project}/repository/commits");
+        let json = if let Some(rev) = &forge.rev {
+            parse_json(
+                flake_ref,
+                &self.get(flake_ref, host, format!("{commits}/{rev}"))?,
+            )?
+        } else {
+            let url = format!(
+                "{commits}?ref_name={}&per_page=1",
+                utf8_percent_encode(target(forge), SEGMENT)
+            );
+            let list = parse_json(flake_ref, &self.get(flake_ref, host, url)?)?;
+            list.get(0).cloned().ok_or_else(|| {
+                resolve_error(flake_ref, format!("no commits on `{}`", target(forge)))
+            })?
+        };
+        commit_attrs(flake_ref, json.get("id"), json.get("committed_date"))
+    }
+
+    fn sourcehut(
+        &self,
+        flake_ref: &FlakeRef,
+        forge: &GitForge,
+        host: &str,
+    ) -> NixUriResult<LockedAttrs> {
+        if let Some(rev) = &forge.rev {
+            let rev = Rev::parse(rev)?;
+            if rev.is_abbreviated() {
+                return Err(resolve_error(
+                    flake_ref,
+                    "SourceHut cannot expand an abbreviated rev".into(),
+                ));
+            }
+            return Ok(LockedAttrs::new().with_rev(rev));
+        }
+        let base = self.api_base(host, || format!("https://{host}"));
+        let repo_url = format!("{base}/{}/{}", forge.owner, forge.repo);
+        let wanted = match &forge.ref_ {
+            Some(name) => name.clone(),
+            None => {
+                let head = self.get(flake_ref, host, format!("{repo_url}/HEAD"))?;
+                String::from_utf8_lossy(&head.body)
+                    .trim()
+                    .strip_prefix("ref: ")
+                    .map(str::to_string)
+                    .ok_or_else(|| resolve_error(flake_ref, "unexpected HEAD response".into()))?
+            }
+        };
+        let refs = self.get(flake_ref, host, format!("{repo_url}/info/refs"))?;
+        let refs = String::from_utf8_lossy(&refs.body);
+        let rev = find_advertised_ref(&refs, &wanted)
+            .ok_or_else(|| resolve_error(flake_ref, format!("ref `{wanted}` not found")))?;
+        Ok(LockedAttrs::new().with_rev(Rev::parse(rev)?))
+    }
+}
+
+impl<C: HttpClient> Resolver for ForgeResolver<C> {
+    fn resolve(&self, flake_ref: &FlakeRef) -> NixUriResult<LockedAttrs> {
+        let FlakeRefType::GitForge(forge) = flake_ref.kind() else {
+            return Err(resolve_error(flake_ref, "not a forge ref".into()));
+        };
+        let host = flake_ref.domain().unwrap_or_default().to_string();
+        match forge.platform {
+            GitForgePlatform::GitHub => self.github(flake_ref, forge, &host),
+            GitForgePlatform::GitLab => self.gitlab(flake_ref, forge, &host),
+            GitForgePlatform::SourceHut => self.sourcehut(flake_ref, forge, &host),
+        }
+    }
+}
+
+/// What to look up: the pinned rev, the ref, or `HEAD`.
+fn target(forge: &GitForge) -> &str {
+    forge
+        .rev
+        .as_deref()
+        .or(forge.ref_.as_deref())
+        .unwrap_or("HEAD")
+}
+
+fn platform_of(flake_ref: &FlakeRef) -> Option<GitForgePlatform> {
+    match flake_ref.kind() {
+        FlakeRefType::GitForge(forge) => Some(forge.platform.clone()),
+        _ => None,
+    }
+}
+
+fn parse_json(flake_ref: &FlakeRef, response: &HttpResponse) -> NixUriResult<Value> {
+    serde_json::from_slice(&response.body)
+        .map_err(|e| resolve_error(flake_ref, format!("malformed API response: {e}")))
+}
+
+fn commit_attrs(
+    flake_ref: &FlakeRef,
+    sha: Option<&Value>,
+    date: Option<&Value>,
+) -> NixUriResult<LockedAttrs> {
+    let sha = sha
+        .and_then(Value::as_str)
+        .ok_or_else(|| resolve_error(flake_ref, "API response has no commit id".into()))?;
+    let mut attrs = LockedAttrs::new().with_rev(Rev::parse(sha)?);
+    if let Some(date) = date.and_then(Value::as_str) {
+        let seconds = parse_rfc3339(date)
+            .ok_or_else(|| resolve_error(flake_ref, format!("unparseable commit date `{date}`")))?;
+        attrs = attrs.with_last_modified(seconds);
+    }
+    Ok(attrs)
+}
+
+/// The commit `wanted` names in a dumb-protocol `info/refs` listing
+/// (`<sha>\t<ref>` lines). Short names match under `refs/heads/` then
+/// `refs/tags/`; an annotated tag's peeled `^{}` line wins.
+fn find_advertised_ref<'a>(listing: &'a str, wanted: &str) -> Option<&'a str> {
+    let candidates = [
+        wanted.to_string(),
+        format!("refs/heads/{wanted}"),
+        format!("refs/tags/{wanted}"),
+    ];
+    candidates.iter().find_map(|name| {
+        let peeled = format!("{name}^{{}}");
+        let mut direct = None;
+        for line in listing.lines() {
+            let (sha, ref_name) = line.split_once('\t')?;
+            if ref_name == peeled {
+                return Some(sha);
+            }
+            if ref_name == name {
+                direct = Some(sha);
+            }
+        }
+        direct
+    })
+}
+
+fn resolve_error(flake_ref: &FlakeRef, reason: String) -> NixUriError {
+    NixUriError::Resolve {
+        input: flake_ref.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+    use crate::{http::recorded::Recorded, lock};
+
+    const SHA: &str = "b2df4e4e80e04cbb33a350f87717f4bd6140d298";
+
+    fn parse(input: &str) -> FlakeRef {
+        input.parse().unwrap()
+    }
+
+    fn github_commit() -> String {
+        format!(r#"{{"sha":"{SHA}","commit":{{"committer":{{"date":"2023-11-14T22:13:20Z"}}}}}}"#)
+    }
+
+    #[test]
+    fn github_branch_locks_to_commit() {
+        let http = Recorded::default().json(
+            "https://api.github.com/repos/nixos/nixpkgs/commits/nixos-unstable",
+            &github_commit(),
+        );
+        let resolver = ForgeResolver::new(&http).with_token("github.com", "ghp_secret");
+        let locked = lock(&parse("github:nixos/nixpkgs/nixos-unstable"), &resolver).unwrap();
+        assert_eq!(
+            locked.to_string(),
+            format!("github:nixos/nixpkgs/{SHA}?lastModified=1700000000")
+        );
+        let requests = http.requests();
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some("token ghp_secret")
+        );
+    }
+
+    #[test]
+    fn github_enterprise_and_head() {
+        let http = Recorded::default().json(
+            "https://git.corp.example/api/v3/repos/o/r/commits/HEAD",
+            &github_commit(),
+        );
+        let resolver = ForgeResolver::new(&http);
+        let attrs = resolver
+            .resolve(&parse("github:o/r?host=git.corp.example"))
+            .unwrap();
+        assert_eq!(attrs.rev.unwrap().as_str(), SHA);
+        // No token configured for the host: no credentials are sent.
+        assert!(http.requests()[0].header("authorization").is_none());
+    }
+
+    #[test]
+    fn gitlab_subgroup_ref_and_pat() {
+        let http = Recorded::default().json(
+            "https://gitlab.com/api/v4/projects/group%2Fsub%2Frepo/repository/commits?ref_name=release%2F1.0&per_page=1",
+            &format!(r#"[{{"id":"{SHA}","committed_date":"2023-11-15T00:13:20.000+02:00"}}]"#),
+        );
+        let resolver =
+            ForgeResolver::new(&http).with_access_tokens("gitlab.com=PAT:glpat-x github.com=y");
+        let attrs = resolver
+            .resolve(&parse("gitlab:group%2Fsub/repo?ref=release/1.0"))
+            .unwrap();
+        assert_eq!(attrs.rev.unwrap().as_str(), SHA);
+        assert_eq!(attrs.last_modified, Some(1_700_000_000));
+        assert_eq!(http.requests()[0].header("PRIVATE-TOKEN"), Some("glpat-x"));
+    }
+
+    #[test]
+    fn gitlab_rev_uses_commit_endpoint() {
+        let http = Recorded::default().json(
+            &format!("https://gitlab.com/api/v4/projects/o%2Fr/repository/commits/{SHA}"),
+            &format!(r#"{{"id":"{SHA}","committed_date":"2023-11-14T22:13:20Z"}}"#),
+        );
+        let resolver = ForgeResolver::new(&http).with_token("gitlab.com", "OAuth2:tok");
+        let attrs = resolver
+            .resolve(&parse(&format!("gitlab:o/r/{SHA}")))
+            .unwrap();
+        assert_eq!(attrs.last_modified, Some(1_700_000_000));
+        assert_eq!(
+            http.requests()[0].header("Authorization"),
+            Some("Bearer tok")
+        );
+    }
+
+    #[test]
+    fn sourcehut_follows_head_and_peels_tags() {
+        let tag_object = "1111111111111111111111111111111111111111";
+        let listing = format!(
+            "{SHA}\trefs/heads/master\n{tag_object}\trefs/tags/v1\n{SHA}\trefs/tags/v1^{{}}\n"
+        );
+        let http = Recorded::default()
+            .with(
+                "https://git.sr.ht/~o/r/HEAD",
+                HttpResponse::new(200, "ref: refs/heads/master\n"),
+            )
+            .with(
+                "https://git.sr.ht/~o/r/info/refs",
+                HttpResponse::new(200, listing),
+            );
+        let resolver = ForgeResolver::new(&http);
+        let head = resolver.resolve(&parse("sourcehut:~o/r")).unwrap();
+        assert_eq!(head.rev.unwrap().as_str(), SHA);
+        assert_eq!(head.last_modified, None);
+        let tag = resolver.resolve(&parse("sourcehut:~o/r/v1")).unwrap();
+        assert_eq!(tag.rev.unwrap().as_str(), SHA);
+    }
+
+    #[test]
+    fn api_base_override_points_at_stand_in() {
+        let http = Recorded::default().json(
+            "http://127.0.0.1:8080/repos/o/r/commits/main",
+            &github_commit(),
+        );
+        let resolver =
+            ForgeResolver::new(&http).with_api_base("github.com", "http://127.0.0.1:8080");
+        assert!(resolver.resolve(&parse("github:o/r/main")).is_ok());
+    }
+
+    #[test]
+    fn http_failures_surface_as_resolve_errors() {
+        let http = Recorded::default()
+            .with(
+                "https://api.github.com/repos/o/private/commits/HEAD",
+                HttpResponse::new(403, "rate limited"),
+            )
+            .with(
+                "https://api.github.com/repos/o/garbage/commits/HEAD",
+                HttpResponse::new(200, "<html>"),
+            );
+        let resolver = ForgeResolver::new(&http);
+        assert_matches!(
+            resolver.resolve(&parse("github:o/private")),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("403"))
+        );
+        assert_matches!(
+            resolver.resolve(&parse("github:o/missing")),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("not found"))
+        );
+        assert_matches!(
+            resolver.resolve(&parse("github:o/garbage")),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("malformed"))
+        );
+        assert_matches!(
+            resolver.resolve(&parse("git+https://example.com/r")),
+            Err(NixUriError::Resolve { .. })
+        );
+    }
+}
@@ -14,6 +14,7 @@ use crate::{
         RefLocation, TransportLayer,
         encoding::decode_percent,
         forge::{GitForge, validate_owner_repo},
+        parse_options::ParseOptions,
         validators::{looks_like_rev, validated_ref_name},
     },
     parser::parse_transport_type,
@@ -65,7 +66,7 @@ pub enum FlakeRefType {
     /// `path:///abs` parses. To preserve the internal byte-for-byte
     /// round-trip the empty-authority form is stored verbatim (leading
     /// `//` kept on `path`); the slash-collapse normalisation Nix
-    /// performs at Display time is intentionally deferred.
+    /// performs is opt-in via [`crate::FlakeRef::normalize_path`].
     Path { path: String, rev: Option<String> },
 }
 
@@ -224,6 +225,14 @@ impl FlakeRefType {
     /// classifying a URI's kind.
     #[allow(dead_code)]
     pub(crate) fn parse_type(input: &str) -> NixUriResult<Self> {
+        Self::parse_type_with(input, &ParseOptions::default())
+    }
+
+    /// [`Self::parse_type`] with caller-supplied [`ParseOptions`]: a
+    /// scheme the built-in dispatch does not recognise is handed to the
+    /// registered [`crate::SchemeHandler`] before surfacing
+    /// [`UnsupportedReason::UriType`].
+    pub(crate) fn parse_type_with(input: &str, options: &ParseOptions) -> NixUriResult<Self> {
         let (_, maybe_explicit_type) = run_partial(
             input,
             input,
@@ -281,7 +290,7 @@ impl FlakeRefType {
                     // any `//` prefix outright. The body is stored
                     // verbatim (leading slashes preserved) so Display
                     // round-trips; the slash-collapse normalisation
-                    // Nix performs is intentionally deferred.
+                    // Nix performs is `FlakeRef::normalize_path`.
                     if let Some(after) = rest_input.strip_prefix("//") {
                         let host_end = after.find('/').unwrap_or(after.len());
                         if !after[..host_end].is_empty() {
@@ -420,6 +429,8 @@ impl FlakeRefType {
                             None,
                         ));
                         Ok(flake_ref_type)
+                    } else if let Some(handler) = options.handler(flake_ref_type_str) {
+                        handler.parse(rest_input)
                     } else {
                         Err(NixUriError::Unsupported(UnsupportedReason::UriType {
                             ty: flake_ref_type_str.into(),
@@ -0,0 +1,273 @@
+//! Nix's lockable HTTP tarball protocol.
+//!
+//! A server that can pin a mutable tarball URL answers with
+//! `Link: <url>; rel="immutable"`, where `<url>` is a tarball that never
+//! changes and usually carries `rev`, `revCount` and `lastModified` in its
+//! query. `FlakeHub` serves its `*.tar.gz` version requirements this way.
+
+use url::Url;
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{FlakeRef, FlakeRefType, ResourceType, TransportLayer, encoding::encode_query},
+    http::{HttpClient, HttpRequest},
+};
+
+/// The target of the first `rel="immutable"` link in a `Link` header
+/// value (RFC 8288), as written: possibly relative to the request URL.
+///
+/// Several comma-separated links and space-separated relation types are
+/// understood, `rel` may be quoted or bare, and relation types compare
+/// case-insensitively. `None` when no link is marked immutable or the
+/// value does not parse.
+pub fn immutable_link(header: &str) -> Option<&str> {
+    let mut rest = header;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        let target_end = rest.strip_prefix('<')?.find('>')?;
+        let target = &rest[1..=target_end];
+        rest = &rest[target_end + 2..];
+
+        let mut immutable = false;
+        loop {
+            rest = rest.trim_start();
+            let Some(param) = rest.strip_prefix(';') else {
+                break;
+            };
+            let param = param.trim_start();
+            let name_end = param
+                .find(|c: char| c == '=' || c == ';' || c == ',' || c.is_ascii_whitespace())
+                .unwrap_or(param.len());
+            let name = &param[..name_end];
+            rest = param[name_end..].trim_start();
+            let mut value = "";
+            if let Some(after_eq) = rest.strip_prefix('=') {
+                let after_eq = after_eq.trim_start();
+                if let Some(quoted) = after_eq.strip_prefix('"') {
+                    let close = quoted.find('"')?;
+                    value = &quoted[..close];
+                    rest = &quoted[close + 1..];
+                } else {
+                    let end = after_eq.find([';', ',']).unwrap_or(after_eq.len());
+                    value = after_eq[..end].trim_end();
+                    rest = &after_eq[end..];
+                }
+            }
+            if name.eq_ignore_ascii_case("rel")
+                && value
+                    .split_ascii_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("immutable"))
+            {
+                immutable = true;
+            }
+        }
+
+        if immutable {
+            return Some(target);
+        }
+        if !rest.starts_with(',') {
+            return None;
+        }
+    }
+}
+
+impl FlakeRef {
+    /// The immutable form of an HTTP(S) tarball ref, by Nix's lockable
+    /// tarball protocol: fetch the tarball URL through `client` and follow
+    /// its `Link: <url>; rel="immutable"` header.
+    ///
+    /// The returned ref is parsed from the linked URL (so its `rev`,
+    /// `revCount` and `lastModified` come from there) and keeps this ref's
+    /// `dir`, attribute fragment and, when the link has none, `narHash`.
+    /// It is not yet locked in the [`FlakeRef::is_locked`] sense; that needs
+    /// the `narHash` of the downloaded tree. `Ok(None)` when the server does
+    /// not speak the protocol. Nix honours the header on any response of a
+    /// redirect chain, so clients that follow redirects should keep it.
+    ///
+    /// Refs other than `http(s)` tarballs surface
+    /// [`NixUriError::InvalidValue`]; transport failures, error statuses and
+    /// links that are not tarball URLs surface [`NixUriError::Resolve`].
+    pub fn to_immutable_tarball(&self, client: &dyn HttpClient) -> NixUriResult<Option<Self>> {
+        let url = self.tarball_fetch_url()?;
+        let fail = |reason: String| NixUriError::Resolve {
+            input: self.to_string(),
+            reason,
+        };
+        let response = client.get(&HttpRequest::new(url.as_str()))?;
+        if !response.is_success() {
+            return Err(fail(format!("HTTP {} from {url}", response.status)));
+        }
+        let Some(target) = response
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("link"))
+            .find_map(|(_, value)| immutable_link(value))
+        else {
+            return Ok(None);
+        };
+        let target = Url::parse(&url)
+            .and_then(|base| base.join(target))
+            .map_err(|e| fail(format!("invalid immutable link `{target}`: {e}")))?;
+
+        let mut immutable: Self = target
+            .as_str()
+            .parse()
+            .map_err(|e| fail(format!("invalid immutable link `{target}`: {e}")))?;
+        if !is_tarball(&immutable) {
+            return Err(fail(format!(
+                "immutable link `{target}` is not a tarball URL"
+            )));
+        }
+        if immutable.params().dir_value().is_none() {
+            immutable.set_dir(self.params().dir_value().map(str::to_string));
+        }
+        if immutable.params().nar_hash_value().is_none() {
+            immutable.set_nar_hash(self.params().nar_hash_value().map(str::to_string));
+        }
+        if immutable.fragment().is_none() {
+            immutable.set_fragment(self.fragment().map(str::to_string));
+        }
+        Ok(Some(immutable))
+    }
+
+    /// The URL a tarball ref downloads: the location plus the query
+    /// parameters that are not flake attributes.
+    fn tarball_fetch_url(&self) -> NixUriResult<String> {
+        let scheme = match self.kind() {
+            FlakeRefType::Resource(res) if is_tarball(self) => match res.transport_type {
+                Some(TransportLayer::Https) => Some(("https", &res.location)),
+                Some(TransportLayer::Http) => Some(("http", &res.location)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((scheme, location)) = scheme else {
+            return Err(NixUriError::InvalidValue {
+                field: "url",
+                reason: format!("`{self}` is not an HTTP(S) tarball ref"),
+            });
+        };
+        let mut url = format!("{scheme}://{location}");
+        for (i, (key, value)) in self.params().arbitrary().iter().enumerate() {
+            url.push(if i == 0 { '?' } else { '&' });
+            url.push_str(&encode_query(key));
+            url.push('=');
+            url.push_str(&encode_query(value));
+        }
+        Ok(url)
+    }
+}
+
+fn is_tarball(flake_ref: &FlakeRef) -> bool {
+    matches!(
+        flake_ref.kind(),
+        FlakeRefType::Resource(res) if res.res_type == ResourceType::Tarball
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::http::{HttpResponse, recorded::Recorded};
+
+    const SHA: &str = "b2df4e4e80e04cbb33a350f87717f4bd6140d298";
+
+    #[rstest]
+    #[case(r#"<https://h/a.tar.gz>; rel="immutable""#, Some("https://h/a.tar.gz"))]
+    #[case("<https://h/a.tar.gz>;rel=immutable", Some("https://h/a.tar.gz"))]
+    #[case(
+        r#"<https://h/a.tar.gz>; rel="preload IMMUTABLE""#,
+        Some("https://h/a.tar.gz")
+    )]
+    #[case(
+        r#"<https://h/next>; rel="next", </pinned.tar.gz>; title="a, b"; rel="immutable""#,
+        Some("/pinned.tar.gz")
+    )]
+    #[case(r#"<https://h/a.tar.gz>; rel="next""#, None)]
+    #[case(r#"<https://h/a.tar.gz>; rel="immutable-ish""#, None)]
+    #[case("https://h/a.tar.gz; rel=immutable", None)]
+    #[case(r#"<https://h/a.tar.gz; rel="immutable""#, None)]
+    #[case("", None)]
+    fn parses_link_header(#[case] header: &str, #[case] expected: Option<&str>) {
+        assert_eq!(immutable_link(header), expected);
+    }
+
+    #[test]
+    fn flakehub_request_follows_link() {
+        let link = format!(
+            "<https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2311.1/018c/source.tar.gz?rev={SHA}&revCount=554738&lastModified=1700000000>; rel=\"immutable\""
+        );
+        let http = Recorded::default().with(
+            "https://flakehub.com/f/NixOS/nixpkgs/0.2311.*.tar.gz",
+            HttpResponse::new(200, "").with_header("Link", link),
+        );
+        let flake_ref: FlakeRef =
+            "https://flakehub.com/f/NixOS/nixpkgs/0.2311.*.tar.gz?dir=lib#hello"
+                .parse()
+                .unwrap();
+        let immutable = flake_ref.to_immutable_tarball(&http).unwrap().unwrap();
+        assert_eq!(
+            immutable.to_string(),
+            format!(
+                "https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2311.1/018c/source.tar.gz?dir=lib&lastModified=1700000000&rev={SHA}&revCount=554738#hello"
+            )
+        );
+        assert_eq!(immutable.rev(), Some(SHA));
+    }
+
+    #[test]
+    fn relative_link_and_url_query_params() {
+        let http = Recorded::default().with(
+            "https://artifacts.example/latest.tar.gz?channel=stable",
+            HttpResponse::new(200, "")
+                .with_header("link", "</builds/42.tar.gz?lastModified=7>; rel=immutable"),
+        );
+        let flake_ref: FlakeRef =
+            "https://artifacts.example/latest.tar.gz?channel=stable&narHash=sha256-AAAA"
+                .parse()
+                .unwrap();
+        let immutable = flake_ref.to_immutable_tarball(&http).unwrap().unwrap();
+        assert_eq!(
+            immutable.to_string(),
+            "https://artifacts.example/builds/42.tar.gz?lastModified=7&narHash=sha256-AAAA"
+        );
+    }
+
+    #[test]
+    fn servers_without_the_protocol_and_failures() {
+        let http = Recorded::default()
+            .with("https://h/plain.tar.gz", HttpResponse::new(200, ""))
+            .with(
+                "https://h/to-file.tar.gz",
+                HttpResponse::new(200, "").with_header("Link", "<https://h/blob>; rel=immutable"),
+            );
+        let parse = |s: &str| s.parse::<FlakeRef>().unwrap();
+        assert_eq!(
+            parse("https://h/plain.tar.gz")
+                .to_immutable_tarball(&http)
+                .unwrap(),
+            None
+        );
+        assert_matches!(
+            parse("https://h/to-file.tar.gz").to_immutable_tarball(&http),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("not a tarball"))
+        );
+        assert_matches!(
+            parse("https://h/missing.tar.gz").to_immutable_tarball(&http),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("404"))
+        );
+        for input in [
+            "github:o/r",
+            "git+https://h/r",
+            "tarball+file:///tmp/a.tar.gz",
+        ] {
+            assert_matches!(
+                parse(input).to_immutable_tarball(&http),
+                Err(NixUriError::InvalidValue { field: "url", .. })
+            );
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Typed commit-verification keys.
+//!
+//! Git inputs with `verifyCommit=1` carry their trusted keys in three query
+//! parameters: `keytype` + `publicKey` for a single key, and `publicKeys`
+//! for a JSON list of `{"type", "key"}` objects. [`KeyType`] and
+//! [`PublicKey`] are the typed forms; [`LocationParameters::verification_keys`]
+//! merges the three parameters the way Nix's git fetcher does.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::LocationParameters,
+};
+
+/// A signature key type accepted by Nix's git fetcher.
+///
+/// Parses both Nix's spelling (`ssh-ed25519`, `ssh-ecdsa-sk`, ...) and the
+/// OpenSSH algorithm names Nix translates them into (`ecdsa-sha2-nistp256`,
+/// `sk-ssh-ed25519@openssh.com`, ...). Renders Nix's spelling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum KeyType {
+    /// `ssh-dsa` (OpenSSH `ssh-dss`).
+    Dsa,
+    /// `ssh-ecdsa` (OpenSSH `ecdsa-sha2-nistp256`).
+    Ecdsa,
+    /// `ssh-ecdsa-sk` (OpenSSH `sk-ecdsa-sha2-nistp256@openssh.com`).
+    EcdsaSk,
+    /// `ssh-ed25519`; Nix's default when no type is given.
+    #[default]
+    Ed25519,
+    /// `ssh-ed25519-sk` (OpenSSH `sk-ssh-ed25519@openssh.com`).
+    Ed25519Sk,
+    /// `ssh-rsa`.
+    Rsa,
+}
+
+impl KeyType {
+    /// Nix's spelling, as written in `keytype=` and `publicKeys`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Dsa => "ssh-dsa",
+            Self::Ecdsa => "ssh-ecdsa",
+            Self::EcdsaSk => "ssh-ecdsa-sk",
+            Self::Ed25519 => "ssh-ed25519",
+            Self::Ed25519Sk => "ssh-ed25519-sk",
+            Self::Rsa => "ssh-rsa",
+        }
+    }
+
+    /// The OpenSSH algorithm name, as written in an `allowed_signers` file.
+    pub fn openssh_name(self) -> &'static str {
+        match self {
+            Self::Dsa => "ssh-dss",
+            Self::Ecdsa => "ecdsa-sha2-nistp256",
+            Self::EcdsaSk => "sk-ecdsa-sha2-nistp256@openssh.com",
+            Self::Ed25519 => "ssh-ed25519",
+            Self::Ed25519Sk => "sk-ssh-ed25519@openssh.com",
+            Self::Rsa => "ssh-rsa",
+        }
+    }
+
+    const ALL: [Self; 6] = [
+        Self::Dsa,
+        Self::Ecdsa,
+        Self::EcdsaSk,
+        Self::Ed25519,
+        Self::Ed25519Sk,
+        Self::Rsa,
+    ];
+}
+
+impl std::str::FromStr for KeyType {
+    type Err = NixUriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kt| kt.as_str() == s || kt.openssh_name() == s)
+            .ok_or_else(|| NixUriError::InvalidValue {
+                field: "keytype",
+                reason: format!(
+                    "unknown key type `{s}`; expected one of ssh-dsa, ssh-ecdsa, \
+                     ssh-ecdsa-sk, ssh-ed25519, ssh-ed25519-sk, ssh-rsa"
+                ),
+            })
+    }
+}
+
+impl Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for KeyType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// One trusted signing key: its type and base64 key blob.
+///
+/// Serialises as Nix's `publicKeys` element, `{"key": ..., "type": ...}`;
+/// a missing `type` defaults to `ssh-ed25519` as in Nix.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct PublicKey {
+    pub key: String,
+    #[serde(rename = "type", default)]
+    pub key_type: KeyType,
+}
+
+impl PublicKey {
+    pub fn new(key_type: KeyType, key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            key_type,
+        }
+    }
+
+    /// Parse a `publicKeys` value: a JSON list of key objects. Surfaces
+    /// [`NixUriError::InvalidValue`] with field `publicKeys` for malformed
+    /// JSON, unknown key types, or key blobs that are not base64.
+    pub fn parse_list(json: &str) -> NixUriResult<Vec<Self>> {
+        let keys: Vec<Self> =
+            serde_json::from_str(json).map_err(|e| NixUriError::InvalidValue {
+                field: "publicKeys",
+                reason: e.to_string(),
+            })?;
+        for key in &keys {
+            validate_key_blob("publicKeys", &key.key)?;
+        }
+        Ok(keys)
+    }
+
+    /// Render a key list the way Nix encodes `publicKeys`: compact JSON,
+    /// object keys in alphabetical order.
+    pub fn render_list(keys: &[Self]) -> String {
+        keys.iter()
+            .map(|k| serde_json::json!({ "key": k.key, "type": k.key_type.as_str() }))
+            .collect::<serde_json::Value>()
+            .to_string()
+    }
+}
+
+/// A key blob must be non-empty base64 (standard alphabet, optional `=`
+/// padding at the end only).
+fn validate_key_blob(field: &'static str, key: &str) -> NixUriResult<()> {
+    let body = key.trim_end_matches('=');
+    let valid = !body.is_empty()
+        && key.len() - body.len() <= 2
+        && body
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/'));
+    if valid {
+        Ok(())
+    } else {
+        Err(NixUriError::InvalidValue {
+            field,
+            reason: "expected a base64-encoded public key".to_string(),
+        })
+    }
+}
+
+impl LocationParameters {
+    /// The typed `keytype`, when set.
+    pub fn key_type(&self) -> NixUriResult<Option<KeyType>> {
+        self.keytype.as_deref().map(str::parse).transpose()
+    }
+
+    /// Every trusted key, merged as Nix's git fetcher does: `publicKey`
+    /// (typed by `keytype`, defaulting to `ssh-ed25519`) first, followed
+    /// by the `publicKeys` list.
+    pub fn verification_keys(&self) -> NixUriResult<Vec<PublicKey>> {
+        let mut keys = Vec::new();
+        if let Some(key) = self.public_key.as_deref() {
+            validate_key_blob("publicKey", key)?;
+            keys.push(PublicKey::new(self.key_type()?.unwrap_or_default(), key));
+        }
+        if let Some(list) = self.public_keys.as_deref() {
+            keys.extend(PublicKey::parse_list(list)?);
+        }
+        Ok(keys)
+    }
+
+    /// Replace `publicKeys` with `keys` in Nix's encoding; an empty list
+    /// clears the parameter.
+    pub fn set_public_key_list(&mut self, keys: &[PublicKey]) {
+        self.public_keys = (!keys.is_empty()).then(|| PublicKey::render_list(keys));
+    }
+
+    /// Parse-time check run when `verifyCommit=1`: every key parameter
+    /// that is present must be well formed.
+    pub(crate) fn validate_verification_keys(&self) -> NixUriResult<()> {
+        if self.verify_commit == Some(true) {
+            self.verification_keys()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::FlakeRef;
+
+    const ED: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIDxbMhGMCZ/jT5Xp3aBzQIxBv5iP3pqlvZQ6tdXd2b1M";
+
+    #[rstest]
+    #[case("ssh-ed25519", KeyType::Ed25519)]
+    #[case("ssh-rsa", KeyType::Rsa)]
+    #[case("ssh-ecdsa", KeyType::Ecdsa)]
+    #[case("ecdsa-sha2-nistp256", KeyType::Ecdsa)]
+    #[case("ssh-ed25519-sk", KeyType::Ed25519Sk)]
+    #[case("sk-ssh-ed25519@openssh.com", KeyType::Ed25519Sk)]
+    #[case("sk-ecdsa-sha2-nistp256@openssh.com", KeyType::EcdsaSk)]
+    #[case("ssh-dss", KeyType::Dsa)]
+    fn key_type_spellings(#[case] input: &str, #[case] expected: KeyType) {
+        assert_eq!(input.parse::<KeyType>().unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("ed25519")]
+    #[case("ecdsa-sha2-nistp384")]
+    #[case("")]
+    fn unknown_key_type(#[case] input: &str) {
+        assert_matches!(
+            input.parse::<KeyType>(),
+            Err(NixUriError::InvalidValue {
+                field: "keytype",
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn parse_list_defaults_type() {
+        let keys = PublicKey::parse_list(&format!(
+            r#"[{{"key":"{ED}"}},{{"type":"ssh-rsa","key":"AAAA"}}]"#
+        ))
+        .unwrap();
+        assert_eq!(
+            keys,
+            [
+                PublicKey::new(KeyType::Ed25519, ED),
+                PublicKey::new(KeyType::Rsa, "AAAA"),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("k1.k2")]
+    #[case(r#"{"key":"AAAA"}"#)]
+    #[case(r#"[{"type":"nope","key":"AAAA"}]"#)]
+    #[case(r#"[{"type":"ssh-rsa","key":"not base64!"}]"#)]
+    #[case(r#"[{"type":"ssh-rsa","key":"AAAA","extra":1}]"#)]
+    fn parse_list_rejects(#[case] input: &str) {
+        assert_matches!(
+            PublicKey::parse_list(input),
+            Err(NixUriError::InvalidValue {
+                field: "publicKeys",
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn render_list_matches_nix_encoding() {
+        let keys = [PublicKey::new(KeyType::Ed25519Sk, "AAAA")];
+        assert_eq!(
+            PublicKey::render_list(&keys),
+            r#"[{"key":"AAAA","type":"ssh-ed25519-sk"}]"#
+        );
+    }
+
+    #[test]
+    fn verification_keys_merge_single_and_list() {
+        let mut params = LocationParameters::default();
+        params.set_keytype(Some("ssh-rsa".into()));
+        params.set_public_key(Some("AAAA".into()));
+        params.set_public_key_list(&[PublicKey::new(KeyType::Ed25519, ED)]);
+        assert_eq!(
+            params.verification_keys().unwrap(),
+            [
+                PublicKey::new(KeyType::Rsa, "AAAA"),
+                PublicKey::new(KeyType::Ed25519, ED),
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_commit_validates_at_parse_time() {
+        for uri in [
+            "git+https://example.com/r?verifyCommit=1&keytype=dsa&publicKey=AAAA",
+            "git+https://example.com/r?verifyCommit=1&publicKey=not%20base64",
+            "git+https://example.com/r?verifyCommit=1&publicKeys=k1.k2",
+        ] {
+            assert_matches!(
+                uri.parse::<FlakeRef>(),
+                Err(NixUriError::InvalidValue { .. }),
+                "{uri}"
+            );
+        }
+    }
+
+    #[test]
+    fn keys_without_verify_commit_stay_unchecked() {
+        let parsed: FlakeRef = "git+https://example.com/r?publicKeys=k1.k2"
+            .parse()
+            .unwrap();
+        assert_eq!(parsed.params().public_keys.as_deref(), Some("k1.k2"));
+        assert!(parsed.params().verification_keys().is_err());
+    }
+
+    #[test]
+    fn canonical_string_renders_nix_json() {
+        let uri = "git+https://example.com/r?verifyCommit=1&publicKeys=\
+                   %5B%7B%22type%22%3A%22ssh-rsa%22%2C%20%22key%22%3A%22AAAA%22%7D%5D";
+        let parsed: FlakeRef = uri.parse().unwrap();
+        assert_eq!(
+            parsed.params().verification_keys().unwrap(),
+            [PublicKey::new(KeyType::Rsa, "AAAA")]
+        );
+        assert_eq!(
+            parsed.to_canonical_string(),
+            "git+https://example.com/r?publicKeys=\
+             %5B%7B%22key%22:%22AAAA%22%2C%22type%22:%22ssh-rsa%22%7D%5D&verifyCommit=1"
+        );
+    }
+}
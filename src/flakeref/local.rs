@@ -0,0 +1,264 @@
+//! Flake ref auto-detection for a local directory (`nix build .`).
+//!
+//! Mirrors the resolution Nix performs when handed a bare path: search
+//! upward for `flake.nix`, then look for an enclosing git repository. A
+//! flake inside a repository becomes `git+file://<repo>?dir=<sub>`; one
+//! outside any repository becomes `path:<dir>`. The scan only stats paths;
+//! it never runs git or touches the network.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{FlakeRef, FlakeRefType, ResourceType, ResourceUrl, TransportLayer, paths},
+};
+
+/// Why [`FlakeRef::from_local_dir`] found no flake.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NotAFlakeReason {
+    /// Neither the directory nor its parents (up to the enclosing git
+    /// repository root, or `/`) contain a `flake.nix`.
+    NoFlakeNix,
+    /// The upward search reached a filesystem boundary (a mount point)
+    /// before finding `flake.nix`.
+    FilesystemBoundary,
+    /// The path exists but is not a directory.
+    NotADirectory,
+}
+
+impl fmt::Display for NotAFlakeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoFlakeNix => {
+                f.write_str("neither it nor its parent directories contain a 'flake.nix' file")
+            }
+            Self::FilesystemBoundary => {
+                f.write_str("a filesystem boundary was reached before finding 'flake.nix'")
+            }
+            Self::NotADirectory => f.write_str("it is not a directory"),
+        }
+    }
+}
+
+impl FlakeRef {
+    /// Resolve the flake a local directory belongs to, as `nix build <dir>`
+    /// does:
+    ///
+    /// 1. If `dir` has no `flake.nix`, walk up its parents until one does.
+    ///    The search stops with [`NotAFlakeReason::NoFlakeNix`] at a
+    ///    directory containing `.git` (a repository root) or at `/`, and
+    ///    with [`NotAFlakeReason::FilesystemBoundary`] when it would cross
+    ///    onto another device.
+    /// 2. From the flake directory, walk up looking for `.git`. If found,
+    ///    the result is `git+file://<repo root>` with `dir=<flake path
+    ///    inside the repo>` when the flake is not at the root, and
+    ///    `shallow=1` when the repository is a shallow clone.
+    /// 3. Otherwise the result is `path:<flake dir>`.
+    ///
+    /// A relative `dir` is taken relative to the current directory. The
+    /// path is normalised lexically, as Nix does, so symlinks are not
+    /// resolved. Filesystem errors surface as [`NixUriError::Io`]; a
+    /// missing flake as [`NixUriError::NotAFlake`].
+    pub fn from_local_dir(dir: impl AsRef<Path>) -> NixUriResult<Self> {
+        let dir = absolute(dir.as_ref())?;
+        let flake_dir = find_flake_dir(&dir)?;
+        let flake_dir_str = utf8(&flake_dir)?;
+
+        let mut subdir: Vec<&str> = Vec::new();
+        let mut root = flake_dir.as_path();
+        loop {
+            if root.join(".git").exists() {
+                let mut flake_ref = Self::new(FlakeRefType::Resource(ResourceUrl::new(
+                    ResourceType::Git,
+                    utf8(root)?.to_string(),
+                    Some(TransportLayer::File),
+                )));
+                if !subdir.is_empty() {
+                    subdir.reverse();
+                    flake_ref.set_dir(Some(subdir.join("/")));
+                }
+                if root.join(".git/shallow").exists() {
+                    flake_ref.set_shallow(true);
+                }
+                return Ok(flake_ref);
+            }
+            let (Some(parent), Some(name)) = (root.parent(), root.file_name()) else {
+                break;
+            };
+            subdir.push(name.to_str().ok_or_else(|| not_utf8(root))?);
+            root = parent;
+        }
+        Ok(Self::new(FlakeRefType::Path {
+            path: flake_dir_str.to_string(),
+            rev: None,
+        }))
+    }
+}
+
+/// Step 1 of [`FlakeRef::from_local_dir`]: the nearest directory at or
+/// above `start` that holds a `flake.nix`.
+fn find_flake_dir(start: &Path) -> NixUriResult<PathBuf> {
+    let io = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| NixUriError::Io { path, source }
+    };
+    let metadata = std::fs::metadata(start).map_err(io(start))?;
+    if !metadata.is_dir() {
+        return Err(not_a_flake(start, NotAFlakeReason::NotADirectory));
+    }
+    let device = device_of(&metadata);
+    let mut current = start;
+    loop {
+        if current.join("flake.nix").exists() {
+            return Ok(current.to_path_buf());
+        }
+        if current.join(".git").exists() {
+            return Err(not_a_flake(start, NotAFlakeReason::NoFlakeNix));
+        }
+        let Some(parent) = current.parent() else {
+            return Err(not_a_flake(start, NotAFlakeReason::NoFlakeNix));
+        };
+        let parent_metadata = std::fs::metadata(parent).map_err(io(parent))?;
+        if device_of(&parent_metadata) != device {
+            return Err(not_a_flake(start, NotAFlakeReason::FilesystemBoundary));
+        }
+        current = parent;
+    }
+}
+
+#[cfg(unix)]
+fn device_of(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Absolute, lexically normalised form of `dir`.
+fn absolute(dir: &Path) -> NixUriResult<PathBuf> {
+    let joined = if dir.is_absolute() {
+        dir.to_path_buf()
+    } else {
+        let cwd = std::env::current_dir().map_err(|source| NixUriError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        cwd.join(dir)
+    };
+    Ok(PathBuf::from(paths::normalize_local(utf8(&joined)?)))
+}
+
+fn utf8(path: &Path) -> NixUriResult<&str> {
+    path.to_str().ok_or_else(|| not_utf8(path))
+}
+
+fn not_utf8(path: &Path) -> NixUriError {
+    NixUriError::InvalidValue {
+        field: "path",
+        reason: format!("`{}` is not valid UTF-8", path.display()),
+    }
+}
+
+fn not_a_flake(path: &Path, reason: NotAFlakeReason) -> NixUriError {
+    NixUriError::NotAFlake {
+        path: path.to_path_buf(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+    use crate::test_support::Scratch;
+
+    #[test]
+    fn flake_at_git_root() {
+        let s = Scratch::new("local-git-root");
+        s.mkdir("repo/.git");
+        s.touch("repo/flake.nix");
+        let found = FlakeRef::from_local_dir(s.join("repo")).unwrap();
+        assert_eq!(
+            found.to_string(),
+            format!("git+file://{}/repo", s.display())
+        );
+    }
+
+    #[test]
+    fn subflake_in_git_repo_gets_dir() {
+        let s = Scratch::new("local-git-sub");
+        s.mkdir("repo/.git");
+        s.touch("repo/nix/app/flake.nix");
+        let inner = s.mkdir("repo/nix/app/src/deep");
+        let found = FlakeRef::from_local_dir(inner).unwrap();
+        assert_eq!(
+            found.to_string(),
+            format!("git+file://{}/repo?dir=nix/app", s.display())
+        );
+    }
+
+    #[test]
+    fn shallow_clone_marked() {
+        let s = Scratch::new("local-git-shallow");
+        s.touch("repo/.git/shallow");
+        s.touch("repo/flake.nix");
+        let found = FlakeRef::from_local_dir(s.join("repo")).unwrap();
+        assert_eq!(found.params().shallow, Some(true));
+    }
+
+    #[test]
+    fn flake_outside_git_is_path() {
+        let s = Scratch::new("local-plain");
+        s.touch("proj/flake.nix");
+        let inner = s.mkdir("proj/sub");
+        let found = FlakeRef::from_local_dir(&inner).unwrap();
+        assert_eq!(found.to_string(), format!("path:{}/proj", s.display()));
+        let dotted = FlakeRef::from_local_dir(inner.join("../sub/.")).unwrap();
+        assert_eq!(dotted, found);
+    }
+
+    #[test]
+    fn search_stops_at_git_root() {
+        let s = Scratch::new("local-stop");
+        s.touch("flake.nix");
+        s.mkdir("repo/.git");
+        let inner = s.mkdir("repo/sub");
+        assert_matches!(
+            FlakeRef::from_local_dir(inner),
+            Err(NixUriError::NotAFlake {
+                reason: NotAFlakeReason::NoFlakeNix,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn file_is_not_a_directory() {
+        let s = Scratch::new("local-file");
+        s.touch("flake.nix");
+        assert_matches!(
+            FlakeRef::from_local_dir(s.join("flake.nix")),
+            Err(NixUriError::NotAFlake {
+                reason: NotAFlakeReason::NotADirectory,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn missing_dir_is_io_error() {
+        let s = Scratch::new("local-missing");
+        assert_matches!(
+            FlakeRef::from_local_dir(s.join("nope")),
+            Err(NixUriError::Io { .. })
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Locking `git+file:` refs by reading the repository on disk.
+//!
+//! Fills in what `nix flake lock` records for a local git input (`rev`,
+//! `revCount`, `lastModified`, and the ref that was followed) without the
+//! git CLI, so local inputs can be locked in CI without Nix. Requires the
+//! `local-git` feature.
+
+use std::path::Path;
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{FlakeRef, FlakeRefType, LockedAttrs, Resolver, ResourceType, Rev, TransportLayer},
+    git::{ObjectId, Repository},
+};
+
+/// Abbreviation Nix uses for `dirtyShortRev` and `shortRev`.
+const SHORT_REV_LEN: usize = 7;
+
+/// What [`FlakeRef::inspect_local_git`] read from a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LocalGitState {
+    /// The commit the ref selects: its pinned `rev`, its `ref`, or `HEAD`.
+    pub rev: Rev,
+    /// The fully qualified ref that was followed (`refs/heads/main`);
+    /// `None` for a pinned `rev` or a detached `HEAD`.
+    pub ref_: Option<String>,
+    /// Commits reachable from [`Self::rev`], as `git rev-list --count`
+    /// reports; `None` in a shallow clone whose history is cut off.
+    pub rev_count: Option<u64>,
+    /// Committer timestamp of [`Self::rev`], in seconds since the epoch.
+    pub last_modified: u64,
+    /// `true` when the work tree or index has uncommitted changes to
+    /// tracked files. Only checked when the ref names neither a `ref` nor
+    /// a `rev`, since only then does Nix fetch the work tree.
+    pub dirty: bool,
+}
+
+impl LocalGitState {
+    /// `<rev>-dirty`, as Nix records `dirtyRev` for a dirty work tree.
+    pub fn dirty_rev(&self) -> Option<String> {
+        self.dirty.then(|| format!("{}-dirty", self.rev))
+    }
+}
+
+/// A [`Resolver`] that locks `git+file:` refs from the repository on
+/// disk via [`FlakeRef::inspect_local_git`]. Refuses a dirty work tree,
+/// which has no commit to pin. Does not compute `narHash`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalGitResolver;
+
+impl Resolver for LocalGitResolver {
+    fn resolve(&self, flake_ref: &FlakeRef) -> NixUriResult<LockedAttrs> {
+        let state = flake_ref.inspect_local_git()?;
+        if state.dirty {
+            return Err(NixUriError::Resolve {
+                input: flake_ref.to_string(),
+                reason: "work tree has uncommitted changes".into(),
+            });
+        }
+        let mut attrs = LockedAttrs::new()
+            .with_rev(state.rev)
+            .with_ref(state.ref_)
+            .with_last_modified(state.last_modified);
+        if let Some(rev_count) = state.rev_count {
+            attrs = attrs.with_rev_count(rev_count);
+        }
+        Ok(attrs)
+    }
+}
+
+impl FlakeRef {
+    /// Read the local repository behind a `git+file:` ref:
+    ///
+    /// - a pinned `rev` is looked up (abbreviated revs are resolved and
+    ///   must be unambiguous);
+    /// - otherwise `ref` is resolved as `git rev-parse` would (`main`,
+    ///   `refs/heads/main`, `v1.0`, ...), falling back to `HEAD`;
+    /// - annotated tags are peeled to their commit, history is walked for
+    ///   `revCount`, and, for a plain work-tree ref, the index and work
+    ///   tree are compared against the commit.
+    ///
+    /// Reads `.git/HEAD`, loose refs, `packed-refs`, loose objects and
+    /// packfiles directly. Other kinds surface
+    /// [`NixUriError::InvalidValue`]; repository problems
+    /// [`NixUriError::Git`] or [`NixUriError::Io`].
+    pub fn inspect_local_git(&self) -> NixUriResult<LocalGitState> {
+        let path = self.local_git_path()?;
+        let repo = Repository::open(Path::new(path))?;
+
+        let (target, ref_) = if let Some(rev) = self.rev() {
+            let rev = Rev::parse(rev)?;
+            let id = if rev.is_abbreviated() {
+                repo.resolve_prefix(rev.as_str())?
+            } else {
+                ObjectId::from_hex(rev.as_str()).ok_or_else(|| NixUriError::InvalidValue {
+                    field: "rev",
+                    reason: "SHA-256 revs are not supported for local git inspection".into(),
+                })?
+            };
+            (id, None)
+        } else if let Some(name) = self.ref_() {
+            let resolved = repo.resolve_named(name)?;
+            (resolved.id, resolved.name)
+        } else {
+            let head = repo.head()?;
+            (head.id, head.name)
+        };
+
+        let (commit_id, commit) = repo.commit(target)?;
+        let dirty = self.rev().is_none() && self.ref_().is_none() && repo.is_dirty(commit_id)?;
+        Ok(LocalGitState {
+            rev: Rev::parse(&commit_id.to_hex())?,
+            ref_,
+            rev_count: repo.rev_count(commit_id)?,
+            last_modified: commit.committer_time,
+            dirty,
+        })
+    }
+
+    /// The locked form of a `git+file:` ref, as `nix flake lock` writes
+    /// it: `rev`, `revCount` and `lastModified` set, and the followed
+    /// `ref` recorded when the input named none. Existing parameters
+    /// (`dir`, `submodules`, ...) and the fragment are kept.
+    ///
+    /// A dirty work tree cannot be pinned to a commit: like Nix, the
+    /// result then carries `dirtyRev` / `dirtyShortRev` and
+    /// `lastModified` instead of `rev`, and stays unlocked.
+    pub fn lock_local_git(&self) -> NixUriResult<Self> {
+        let state = self.inspect_local_git()?;
+        let mut locked = self.clone();
+        locked.set_last_modified(Some(state.last_modified.to_string()));
+        if let Some(dirty_rev) = state.dirty_rev() {
+            locked.set_rev_count(None);
+            locked.params.add_arbitrary(("dirtyRev".into(), dirty_rev));
+            locked.params.add_arbitrary((
+                "dirtyShortRev".into(),
+                format!("{}-dirty", state.rev.abbrev(SHORT_REV_LEN)),
+            ));
+            return Ok(locked);
+        }
+        if locked.ref_().is_none() {
+            locked.set_ref(state.ref_);
+        }
+        locked.set_rev(Some(state.rev.to_string()));
+        locked.set_rev_count(state.rev_count.map(|count| count.to_string()));
+        Ok(locked)
+    }
+
+    fn local_git_path(&self) -> NixUriResult<&str> {
+        match self.kind() {
+            FlakeRefType::Resource(res)
+                if matches!(res.res_type, ResourceType::Git)
+                    && matches!(res.transport_type, Some(TransportLayer::File)) =>
+            {
+                Ok(&res.location)
+            }
+            _ => Err(NixUriError::InvalidValue {
+                field: "url",
+                reason: format!("`{self}` is not a `git+file:` ref"),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+    use crate::git::fixture::TestRepo;
+
+    fn flake_ref(repo: &TestRepo, query: &str) -> FlakeRef {
+        format!("git+file://{}{query}", repo.root().display())
+            .parse()
+            .unwrap()
+    }
+
+    fn history(repo: &TestRepo) -> (ObjectId, ObjectId) {
+        let first = repo.commit(&[("flake.nix", "{}")], &[], 1_700_000_000);
+        let second = repo.commit(&[("flake.nix", "{ }")], &[first], 1_700_000_100);
+        repo.set_ref("refs/heads/main", second);
+        (first, second)
+    }
+
+    #[test]
+    fn locks_head() {
+        let repo = TestRepo::new("lock-head");
+        let (_, head) = history(&repo);
+        let locked = flake_ref(&repo, "?dir=sub").lock_local_git().unwrap();
+        assert_eq!(
+            locked.to_string(),
+            format!(
+                "git+file://{}?dir=sub&lastModified=1700000100&ref=refs/heads/main&rev={head}&revCount=2",
+                repo.root().display()
+            )
+        );
+        assert!(locked.is_pinned_to_rev());
+    }
+
+    #[test]
+    fn follows_named_ref() {
+        let repo = TestRepo::new("lock-ref");
+        let (first, _) = history(&repo);
+        repo.set_ref("refs/heads/stable", first);
+        let state = flake_ref(&repo, "?ref=stable").inspect_local_git().unwrap();
+        assert_eq!(state.rev.as_str(), first.to_hex());
+        assert_eq!(state.ref_.as_deref(), Some("refs/heads/stable"));
+        assert_eq!(state.rev_count, Some(1));
+        assert_eq!(state.last_modified, 1_700_000_000);
+
+        // The ref as written is kept in the locked form.
+        let locked = flake_ref(&repo, "?ref=stable").lock_local_git().unwrap();
+        assert_eq!(locked.ref_(), Some("stable"));
+    }
+
+    #[test]
+    fn resolves_pinned_and_abbreviated_revs() {
+        let repo = TestRepo::new("lock-rev");
+        let (first, _) = history(&repo);
+        let pinned = flake_ref(&repo, &format!("?rev={first}"));
+        assert_eq!(
+            pinned.inspect_local_git().unwrap().rev.as_str(),
+            first.to_hex()
+        );
+
+        let options = crate::ParseOptions::new().with_short_revs(true);
+        let short = FlakeRef::parse_with(
+            &format!(
+                "git+file://{}?rev={}",
+                repo.root().display(),
+                &first.to_hex()[..8]
+            ),
+            &options,
+        )
+        .unwrap();
+        let locked = short.lock_local_git().unwrap();
+        assert_eq!(locked.rev(), Some(first.to_hex().as_str()));
+        assert!(!locked.has_unresolved_rev());
+    }
+
+    #[test]
+    fn dirty_work_tree_stays_unlocked() {
+        let repo = TestRepo::new("lock-dirty");
+        let (_, head) = history(&repo);
+        std::fs::write(repo.path("flake.nix"), "{ x = 1; }").unwrap();
+
+        let state = flake_ref(&repo, "").inspect_local_git().unwrap();
+        assert!(state.dirty);
+        assert_eq!(state.dirty_rev(), Some(format!("{head}-dirty")));
+
+        let locked = flake_ref(&repo, "").lock_local_git().unwrap();
+        assert_eq!(locked.rev(), None);
+        let rendered = locked.to_string();
+        assert!(rendered.contains(&format!("dirtyRev={head}-dirty")));
+        assert!(rendered.contains(&format!("dirtyShortRev={}-dirty", &head.to_hex()[..7])));
+
+        // Naming a ref fetches the commit, not the work tree.
+        assert!(
+            !flake_ref(&repo, "?ref=main")
+                .inspect_local_git()
+                .unwrap()
+                .dirty
+        );
+    }
+
+    #[test]
+    fn resolver_locks_clean_trees_only() {
+        let repo = TestRepo::new("lock-resolver");
+        let (_, head) = history(&repo);
+        let locked = crate::lock(&flake_ref(&repo, ""), &LocalGitResolver).unwrap();
+        assert_eq!(locked, flake_ref(&repo, "").lock_local_git().unwrap());
+        assert_eq!(locked.rev(), Some(head.to_hex().as_str()));
+
+        std::fs::write(repo.path("flake.nix"), "dirty").unwrap();
+        assert_matches!(
+            crate::lock(&flake_ref(&repo, ""), &LocalGitResolver),
+            Err(NixUriError::Resolve { .. })
+        );
+    }
+
+    #[test]
+    fn rejects_other_kinds() {
+        for input in ["github:o/r", "path:/tmp/x", "git+https://example.com/r"] {
+            let parsed: FlakeRef = input.parse().unwrap();
+            assert_matches!(
+                parsed.inspect_local_git(),
+                Err(NixUriError::InvalidValue { field: "url", .. })
+            );
+        }
+    }
+
+    #[test]
+    fn missing_repository_is_a_git_error() {
+        let repo = TestRepo::new("lock-missing");
+        std::fs::remove_dir_all(repo.git_dir()).unwrap();
+        assert_matches!(
+            flake_ref(&repo, "").inspect_local_git(),
+            Err(NixUriError::Git { .. })
+        );
+    }
+}
@@ -64,14 +64,15 @@ pub struct LocationParameters {
     /// Verify the commit signature against the configured key set.
     #[serde(rename = "verifyCommit")]
     pub verify_commit: Option<bool>,
-    /// Signature key type (e.g. `ssh-ed25519`). Stored as a free-form
-    /// string because Nix does not enumerate valid values.
+    /// Signature key type for `publicKey` (e.g. `ssh-ed25519`). Stored as
+    /// written so `Display` round-trips; [`Self::key_type`] is the typed
+    /// view.
     pub keytype: Option<String>,
-    /// Public key bytes used to verify commit signatures.
+    /// Base64 public key used to verify commit signatures.
     #[serde(rename = "publicKey")]
     pub public_key: Option<String>,
-    /// Multi-key bag (Nix uses a single string with platform-specific
-    /// delimiters).
+    /// JSON list of `{"type", "key"}` objects, stored as written;
+    /// [`Self::verification_keys`] parses it together with `publicKey`.
     #[serde(rename = "publicKeys")]
     pub public_keys: Option<String>,
     /// Unrecognised query parameters preserved verbatim so `Display` can
@@ -206,6 +207,12 @@ impl LocationParameters {
         self.host = host;
     }
 
+    /// Borrow the `dir` query value, when set. Public access goes through
+    /// [`crate::FlakeRef::dir`].
+    pub(crate) fn dir_value(&self) -> Option<&str> {
+        self.dir.as_deref()
+    }
+
     /// Borrow the `host` query value, when set. The canonical-default
     /// fallback (`github.com` / `gitlab.com` / `git.sr.ht`) is the
     /// `FlakeRef::domain` accessor's job, not this one.
@@ -221,6 +228,11 @@ impl LocationParameters {
         self.nar_hash.as_deref()
     }
 
+    /// Borrow the `lastModified` query value, when set.
+    pub(crate) fn last_modified_value(&self) -> Option<&str> {
+        self.last_modified.as_deref()
+    }
+
     /// Whether `?submodules=` carries the truthy `"1"` value. Used to
     /// gate canonical emission: Nix writes `?submodules=1` only for
     /// the truthy branch. The slot is typed `Option<bool>` because
@@ -302,6 +314,38 @@ impl LocationParameters {
         self.arbitrary.push(arbitrary);
     }
 
+    /// Take every parameter `other` sets, except `dir`, over this one's.
+    /// Arbitrary keys `other` sets replace this one's spellings of them.
+    pub(crate) fn overlay(&mut self, other: &Self) {
+        fn take<T: Clone>(slot: &mut Option<T>, other: &Option<T>) {
+            if other.is_some() {
+                slot.clone_from(other);
+            }
+        }
+        take(&mut self.nar_hash, &other.nar_hash);
+        take(&mut self.submodules, &other.submodules);
+        take(&mut self.shallow, &other.shallow);
+        take(&mut self.host, &other.host);
+        take(&mut self.rev_count, &other.rev_count);
+        take(&mut self.last_modified, &other.last_modified);
+        take(&mut self.lfs, &other.lfs);
+        take(&mut self.export_ignore, &other.export_ignore);
+        take(&mut self.all_refs, &other.all_refs);
+        take(&mut self.verify_commit, &other.verify_commit);
+        take(&mut self.keytype, &other.keytype);
+        take(&mut self.public_key, &other.public_key);
+        take(&mut self.public_keys, &other.public_keys);
+        self.arbitrary
+            .retain(|(key, _)| other.arbitrary.iter().all(|(k, _)| k != key));
+        self.arbitrary.extend(other.arbitrary.iter().cloned());
+    }
+
+    /// The unrecognised parameters, in storage order. For URL-shaped inputs
+    /// these belong to the fetched URL rather than to the flake ref.
+    pub(crate) fn arbitrary(&self) -> &[(String, String)] {
+        &self.arbitrary
+    }
+
     /// Every set query parameter as a `(key, value)` pair: the populated
     /// typed slots followed by the arbitrary key/value bag, in storage order.
     /// Callers that emit a query string (`Display` here, `FlakeRef`'s combined
@@ -635,14 +679,14 @@ mod git_typed_params {
         // pre-existing `narHash` entry: allRefs, exportIgnore, keytype,
         // lfs, narHash, publicKey, publicKeys, verifyCommit.
         let url = "git+ssh://example.com/repo?\
-                   verifyCommit=1&publicKeys=k1.k2&publicKey=abc&\
+                   verifyCommit=1&publicKeys=%5B%7B%22key%22:%22k1%22%7D%5D&publicKey=abc&\
                    narHash=sha256-x&lfs=1&keytype=ssh-ed25519&\
                    exportIgnore=0&allRefs=1";
         let parsed: FlakeRef = url.parse().unwrap();
         let expected = "git+ssh://example.com/repo?\
                         allRefs=1&exportIgnore=0&keytype=ssh-ed25519&\
                         lfs=1&narHash=sha256-x&publicKey=abc&\
-                        publicKeys=k1.k2&verifyCommit=1";
+                        publicKeys=%5B%7B%22key%22:%22k1%22%7D%5D&verifyCommit=1";
         assert_eq!(parsed.to_string(), expected);
     }
 
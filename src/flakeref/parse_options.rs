@@ -0,0 +1,330 @@
+//! Caller-supplied parse configuration.
+//!
+//! [`ParseOptions`] carries the knobs [`crate::FlakeRef::parse_with`] threads
+//! through the parser. The default value reproduces [`std::str::FromStr`]
+//! exactly, so `FlakeRef::parse_with(s, &ParseOptions::default())` and
+//! `s.parse::<FlakeRef>()` are interchangeable.
+//!
+//! Two extension points cover schemes the built-in dispatch does not know:
+//!
+//! - **Aliases** rewrite a leading `<scheme>:` into an expansion string
+//!   before anything else looks at the input. `gh` -> `github:` turns
+//!   `gh:nixos/nixpkgs` into `github:nixos/nixpkgs`; `corp` ->
+//!   `git+ssh://git.corp/` turns `corp:team/repo` into
+//!   `git+ssh://git.corp/team/repo`. The rewritten string is what the
+//!   parser (and therefore `Display`) sees; aliases are an input
+//!   convenience, not a new kind.
+//! - **Handlers** ([`SchemeHandler`]) parse the body of a scheme the
+//!   built-in dispatch would otherwise reject with
+//!   [`UnsupportedReason::UriType`](crate::UnsupportedReason::UriType).
+//!
+//! [`ParseOptions::with_strict_refs`] additionally runs every parsed ref
+//! name through [`crate::check_ref_format`], so names git would refuse
+//! (`foo..bar`, `x.lock`, `a//b`) fail at parse time rather than when the
+//! fetcher runs.
+
+use std::{fmt, sync::Arc};
+
+use crate::{error::NixUriResult, flakeref::FlakeRefType};
+
+/// A parser for a caller-defined URI scheme.
+///
+/// `parse` receives the body after `<scheme>:` with the query string and
+/// fragment already split off; those still route through the regular
+/// parameter handling once the handler returns a kind. Handlers are only
+/// consulted for schemes the built-in dispatch does not recognise, so a
+/// handler registered as `github` is never called.
+pub trait SchemeHandler: fmt::Debug + Send + Sync {
+    /// Classify `body` into a [`FlakeRefType`].
+    fn parse(&self, body: &str) -> NixUriResult<FlakeRefType>;
+}
+
+/// Parse-time configuration for [`crate::FlakeRef::parse_with`].
+///
+/// Built with the consuming `with_*` builders:
+///
+/// ```
+/// # use nix_uri::{FlakeRef, ParseOptions};
+/// let options = ParseOptions::new()
+///     .with_alias("gh", "github:")
+///     .with_alias("corp", "git+ssh://git.corp/");
+/// let parsed = FlakeRef::parse_with("corp:team/repo", &options).unwrap();
+/// assert_eq!(parsed.to_string(), "git+ssh://git.corp/team/repo");
+/// ```
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ParseOptions {
+    aliases: Vec<(String, String)>,
+    handlers: Vec<(String, Arc<dyn SchemeHandler>)>,
+    strict_refs: bool,
+    short_revs: bool,
+    normalized_dirs: bool,
+}
+
+impl ParseOptions {
+    /// Options that behave exactly like [`std::str::FromStr`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `scheme` as an alias: an input starting with `<scheme>:`
+    /// has that prefix replaced by `expansion` before parsing. Expansion
+    /// is a single pass, so an alias whose expansion starts with another
+    /// alias is not expanded again. Re-registering a scheme replaces the
+    /// previous expansion.
+    pub fn with_alias(mut self, scheme: impl Into<String>, expansion: impl Into<String>) -> Self {
+        let scheme = scheme.into();
+        let expansion = expansion.into();
+        match self.aliases.iter_mut().find(|(s, _)| *s == scheme) {
+            Some(slot) => slot.1 = expansion,
+            None => self.aliases.push((scheme, expansion)),
+        }
+        self
+    }
+
+    /// Register a [`SchemeHandler`] for `scheme`. Re-registering a scheme
+    /// replaces the previous handler.
+    pub fn with_handler(
+        mut self,
+        scheme: impl Into<String>,
+        handler: impl SchemeHandler + 'static,
+    ) -> Self {
+        let scheme = scheme.into();
+        let handler: Arc<dyn SchemeHandler> = Arc::new(handler);
+        match self.handlers.iter_mut().find(|(s, _)| *s == scheme) {
+            Some(slot) => slot.1 = handler,
+            None => self.handlers.push((scheme, handler)),
+        }
+        self
+    }
+
+    /// Enable (or disable) full `git check-ref-format` validation of the
+    /// parsed `ref_`. Off by default, which keeps Nix's character-class
+    /// check only. A violation surfaces as [`crate::NixUriError::InvalidValue`]
+    /// with field `ref` and the [`crate::RefFormatViolation`] text as reason.
+    pub fn with_strict_refs(mut self, strict: bool) -> Self {
+        self.strict_refs = strict;
+        self
+    }
+
+    /// Whether [`Self::with_strict_refs`] is enabled.
+    pub(crate) fn strict_refs(&self) -> bool {
+        self.strict_refs
+    }
+
+    /// Accept abbreviated hex revs (at least four digits, as printed by
+    /// `nix flake metadata`) in `?rev=`. Off by default, where only full
+    /// 40/64-hex revs are accepted. The short rev is stored verbatim and
+    /// reported by [`crate::FlakeRef::has_unresolved_rev`] until resolved.
+    pub fn with_short_revs(mut self, lenient: bool) -> Self {
+        self.short_revs = lenient;
+        self
+    }
+
+    /// Whether [`Self::with_short_revs`] is enabled.
+    pub(crate) fn short_revs(&self) -> bool {
+        self.short_revs
+    }
+
+    /// Normalise `dir` at parse time (see [`crate::normalize_dir`]), so
+    /// `dir=./a//b/` and `dir=a/b` parse to equal values, and reject a
+    /// `dir` whose `..` climbs above the source root. Off by default,
+    /// where `dir` is kept verbatim.
+    pub fn with_normalized_dirs(mut self, normalize: bool) -> Self {
+        self.normalized_dirs = normalize;
+        self
+    }
+
+    /// Whether [`Self::with_normalized_dirs`] is enabled.
+    pub(crate) fn normalized_dirs(&self) -> bool {
+        self.normalized_dirs
+    }
+
+    /// Rewrite a leading `<alias>:` in `input`; `None` when no alias
+    /// applies. The scheme token is everything before the first `:`, and
+    /// must not contain `/` (a `:` after a `/` belongs to a path, not a
+    /// scheme).
+    pub(crate) fn expand_alias(&self, input: &str) -> Option<String> {
+        let (scheme, body) = input.split_once(':')?;
+        if scheme.contains('/') {
+            return None;
+        }
+        self.aliases
+            .iter()
+            .find(|(s, _)| s == scheme)
+            .map(|(_, expansion)| format!("{expansion}{body}"))
+    }
+
+    /// The handler registered for `scheme`, if any.
+    pub(crate) fn handler(&self, scheme: &str) -> Option<&dyn SchemeHandler> {
+        self.handlers
+            .iter()
+            .find(|(s, _)| s == scheme)
+            .map(|(_, h)| h.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+    use crate::{
+        FlakeRef, GitForgePlatform, NixUriError, ResourceType, ResourceUrl, TransportLayer,
+        UnsupportedReason,
+    };
+
+    /// Maps `store:<name>` onto a fixed `git+https` host.
+    #[derive(Debug)]
+    struct StoreHandler;
+
+    impl SchemeHandler for StoreHandler {
+        fn parse(&self, body: &str) -> NixUriResult<FlakeRefType> {
+            if body.is_empty() {
+                return Err(NixUriError::InvalidUrl(body.into()));
+            }
+            Ok(FlakeRefType::Resource(ResourceUrl::new(
+                ResourceType::Git,
+                format!("store.example/{body}"),
+                Some(TransportLayer::Https),
+            )))
+        }
+    }
+
+    #[test]
+    fn default_options_match_from_str() {
+        for uri in [
+            "github:nixos/nixpkgs",
+            "git+https://example.com/repo?ref=main",
+            "path:/srv/flake",
+            "nixpkgs/nixos-unstable",
+        ] {
+            let with = FlakeRef::parse_with(uri, &ParseOptions::default()).unwrap();
+            assert_eq!(with, uri.parse::<FlakeRef>().unwrap());
+        }
+    }
+
+    #[test]
+    fn alias_expands_to_forge_scheme() {
+        let options = ParseOptions::new().with_alias("gh", "github:");
+        let parsed = FlakeRef::parse_with("gh:nixos/nixpkgs/nixos-unstable", &options).unwrap();
+        let forge = parsed.forge_identity().unwrap();
+        assert_eq!(forge.platform, GitForgePlatform::GitHub);
+        assert_eq!(parsed.ref_(), Some("nixos-unstable"));
+        assert_eq!(parsed.to_string(), "github:nixos/nixpkgs/nixos-unstable");
+    }
+
+    #[test]
+    fn alias_expands_to_url_prefix_with_query_and_fragment() {
+        let options = ParseOptions::new().with_alias("corp", "git+ssh://git.corp/");
+        let parsed = FlakeRef::parse_with("corp:team/repo?ref=main#pkg", &options).unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "git+ssh://git.corp/team/repo?ref=main#pkg"
+        );
+    }
+
+    #[test]
+    fn alias_is_single_pass() {
+        let options = ParseOptions::new()
+            .with_alias("a", "b:")
+            .with_alias("b", "github:");
+        assert_matches!(
+            FlakeRef::parse_with("a:o/r", &options),
+            Err(NixUriError::Unsupported(UnsupportedReason::UriType { ty })) => assert_eq!(ty, "b")
+        );
+    }
+
+    #[test]
+    fn alias_reregistration_replaces_expansion() {
+        let options = ParseOptions::new()
+            .with_alias("gh", "gitlab:")
+            .with_alias("gh", "github:");
+        let parsed = FlakeRef::parse_with("gh:o/r", &options).unwrap();
+        assert_eq!(parsed.to_string(), "github:o/r");
+    }
+
+    #[test]
+    fn alias_scheme_token_stops_at_slash() {
+        let options = ParseOptions::new().with_alias("./odd", "github:");
+        assert_eq!(options.expand_alias("./odd:x"), None);
+        assert_eq!(options.expand_alias("/abs/path"), None);
+    }
+
+    #[test]
+    fn handler_parses_unknown_scheme() {
+        let options = ParseOptions::new().with_handler("store", StoreHandler);
+        let parsed = FlakeRef::parse_with("store:tools?ref=main#hello", &options).unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            "git+https://store.example/tools?ref=main#hello"
+        );
+    }
+
+    #[test]
+    fn handler_error_propagates() {
+        let options = ParseOptions::new().with_handler("store", StoreHandler);
+        assert_matches!(
+            FlakeRef::parse_with("store:", &options),
+            Err(NixUriError::InvalidUrl(_))
+        );
+    }
+
+    #[test]
+    fn handler_never_shadows_builtin_scheme() {
+        let options = ParseOptions::new().with_handler("github", StoreHandler);
+        let parsed = FlakeRef::parse_with("github:o/r", &options).unwrap();
+        assert!(parsed.forge_identity().is_some());
+    }
+
+    #[test]
+    fn unknown_scheme_without_handler_still_rejects() {
+        let options = ParseOptions::new().with_handler("store", StoreHandler);
+        assert_matches!(
+            FlakeRef::parse_with("other:x", &options),
+            Err(NixUriError::Unsupported(UnsupportedReason::UriType { ty })) => assert_eq!(ty, "other")
+        );
+    }
+
+    #[test]
+    fn strict_refs_reject_check_ref_format_violations() {
+        let strict = ParseOptions::new().with_strict_refs(true);
+        for uri in [
+            "github:o/r/foo..bar",
+            "git+https://example.com/r?ref=foo.lock",
+            "git+https://example.com/r?ref=a//b",
+            "flake:nixpkgs/main.",
+        ] {
+            assert!(uri.parse::<FlakeRef>().is_ok(), "{uri} parses by default");
+            assert_matches!(
+                FlakeRef::parse_with(uri, &strict),
+                Err(NixUriError::InvalidValue { field: "ref", .. }),
+                "{uri}"
+            );
+        }
+    }
+
+    #[test]
+    fn strict_refs_reason_names_the_rule() {
+        let strict = ParseOptions::new().with_strict_refs(true);
+        assert_matches!(
+            FlakeRef::parse_with("github:o/r/foo..bar", &strict),
+            Err(NixUriError::InvalidValue { reason, .. }) => assert_eq!(reason, "ref name cannot contain '..'")
+        );
+    }
+
+    #[test]
+    fn strict_refs_accept_well_formed_refs() {
+        let strict = ParseOptions::new().with_strict_refs(true);
+        for uri in [
+            "github:nixos/nixpkgs/nixos-24.05",
+            "git+https://example.com/r?ref=refs/heads/main",
+            "path:/srv/flake",
+        ] {
+            assert_eq!(
+                FlakeRef::parse_with(uri, &strict).unwrap(),
+                uri.parse::<FlakeRef>().unwrap()
+            );
+        }
+    }
+}
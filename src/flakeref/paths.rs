@@ -0,0 +1,545 @@
+//! Lexical path handling: the subflake `dir` parameter, the helpers that
+//! join it with paths inside a flake, and relative `path:` resolution.
+//!
+//! Everything here is lexical: `.` and `..` are resolved by string
+//! manipulation, never by asking the filesystem, so the same input always
+//! produces the same output regardless of where it runs. The one exception
+//! is [`FlakeRef::canonicalize_against`], which resolves symlinks and says
+//! so in its name.
+
+use std::path::Path;
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{FlakeRef, FlakeRefType, ResourceType, TransportLayer},
+};
+
+/// Split `path` on `/` and resolve it lexically: empty and `.` components
+/// are dropped, and `..` pops the preceding component. Returns the
+/// surviving components together with the number of `..` components that
+/// had nothing left to pop (i.e. that climb above the starting point).
+pub(crate) fn normalize_components(path: &str) -> (Vec<&str>, usize) {
+    let mut components = Vec::new();
+    let mut escaped = 0;
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    escaped += 1;
+                }
+            }
+            c => components.push(c),
+        }
+    }
+    (components, escaped)
+}
+
+/// Normalise a path relative to a flake's source root. `field` tags the
+/// [`NixUriError::InvalidValue`] raised when a `..` climbs above the root.
+fn normalize_within_root(field: &'static str, path: &str) -> NixUriResult<String> {
+    let (components, escaped) = normalize_components(path);
+    if escaped > 0 {
+        return Err(NixUriError::InvalidValue {
+            field,
+            reason: "`..` escapes the flake's source root".to_string(),
+        });
+    }
+    Ok(components.join("/"))
+}
+
+/// Normalise a subflake `dir` value: collapse repeated `/`, strip leading
+/// and trailing `/`, drop `.` components, and resolve `..` against the
+/// preceding component. The root itself normalises to the empty string.
+///
+/// Surfaces [`NixUriError::InvalidValue`] with field `dir` when a `..`
+/// would climb above the source root.
+///
+/// ```
+/// # use nix_uri::normalize_dir;
+/// assert_eq!(normalize_dir("/pkgs//foo/./").unwrap(), "pkgs/foo");
+/// assert_eq!(normalize_dir("a/../b").unwrap(), "b");
+/// assert!(normalize_dir("../outside").is_err());
+/// ```
+pub fn normalize_dir(dir: &str) -> NixUriResult<String> {
+    normalize_within_root("dir", dir)
+}
+
+/// Lexically join `rel` onto the local directory `base`. An absolute
+/// `base` yields an absolute result (`..` stops at `/`); a relative one
+/// stays relative, keeping any `..` that climbs above it.
+pub(crate) fn join_local(base: &str, rel: &str) -> String {
+    let joined = format!("{base}/{rel}");
+    let (components, escaped) = normalize_components(&joined);
+    if base.starts_with('/') {
+        return format!("/{}", components.join("/"));
+    }
+    let mut parts = vec![".."; escaped];
+    parts.extend(components);
+    if parts.is_empty() {
+        ".".to_string()
+    } else if escaped > 0 {
+        parts.join("/")
+    } else {
+        format!("./{}", parts.join("/"))
+    }
+}
+
+/// Lexically normalise a local path, keeping it absolute or relative as
+/// given: `///a//./b/../c` -> `/a/c`, `./x//y/` -> `./x/y`.
+pub(crate) fn normalize_local(path: &str) -> String {
+    if path.starts_with('/') {
+        join_local("/", path)
+    } else {
+        join_local(".", path)
+    }
+}
+
+impl FlakeRef {
+    /// The `dir` query parameter (subflake directory), as stored.
+    pub fn dir(&self) -> Option<&str> {
+        self.params().dir_value()
+    }
+
+    /// Consuming builder that rewrites `dir` into its [`normalize_dir`]
+    /// form, so two spellings of the same subflake compare equal. A `dir`
+    /// that normalises to the root is removed.
+    pub fn with_normalized_dir(mut self) -> NixUriResult<Self> {
+        if let Some(dir) = self.dir() {
+            let normalized = normalize_dir(dir)?;
+            self.set_dir((!normalized.is_empty()).then_some(normalized));
+        }
+        Ok(self)
+    }
+
+    /// Join `path` (a path inside this flake, relative to its `dir`) onto
+    /// the normalised `dir`, giving the location relative to the source
+    /// root. `..` may step out of `dir` into a sibling, but not above the
+    /// source root.
+    ///
+    /// ```
+    /// # use nix_uri::FlakeRef;
+    /// let parent: FlakeRef = "github:org/mono?dir=nix/pkgs".parse().unwrap();
+    /// assert_eq!(parent.join_dir("flake.nix").unwrap(), "nix/pkgs/flake.nix");
+    /// assert_eq!(parent.join_dir("../lib").unwrap(), "nix/lib");
+    /// ```
+    pub fn join_dir(&self, path: &str) -> NixUriResult<String> {
+        let dir = self.dir().unwrap_or_default();
+        normalize_within_root("path", &format!("{dir}/{path}"))
+    }
+
+    /// The flake in subdirectory `path` of this flake's source: the same
+    /// source with `dir` set to [`Self::join_dir`]`(path)`. This is what a
+    /// `path:./sub` input means relative to a parent fetched from the same
+    /// source. Accepts `sub`, `./sub` and `path:./sub`. The fragment is
+    /// dropped; every other parameter is kept, since the subflake shares
+    /// the parent's source tree.
+    pub fn subflake(&self, path: &str) -> NixUriResult<Self> {
+        let path = path.strip_prefix("path:").unwrap_or(path);
+        if path.starts_with('/') {
+            return Err(NixUriError::InvalidValue {
+                field: "path",
+                reason: "expected a path relative to the parent flake".to_string(),
+            });
+        }
+        let dir = self.join_dir(path)?;
+        let mut sub = self.clone().with_fragment(None);
+        sub.set_dir((!dir.is_empty()).then_some(dir));
+        Ok(sub)
+    }
+
+    /// The local filesystem path this ref points at: the path of a
+    /// `path:` ref, or the location of a `git+file:` / `hg+file:`
+    /// resource. `None` for every other kind.
+    pub fn local_path(&self) -> Option<&str> {
+        match self.kind() {
+            FlakeRefType::Path { path, .. } => Some(path),
+            FlakeRefType::Resource(res)
+                if matches!(res.res_type, ResourceType::Git | ResourceType::Mercurial)
+                    && matches!(res.transport_type, Some(TransportLayer::File)) =>
+            {
+                Some(&res.location)
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace the [`Self::local_path`] slot; no-op for other kinds.
+    fn set_local_path(&mut self, new_path: String) {
+        match self.kind_mut() {
+            FlakeRefType::Path { path, .. } => *path = new_path,
+            FlakeRefType::Resource(res) => res.location = new_path,
+            _ => {}
+        }
+    }
+
+    /// Lexically normalise the [`Self::local_path`]: collapse repeated
+    /// `/`, drop `.` components, and resolve `..` against the preceding
+    /// component (stopping at `/` for absolute paths). `path:///a//b/`
+    /// and `path:/a/./b` both become `path:/a/b`, so two spellings of the
+    /// same directory compare equal. Other kinds are returned unchanged.
+    pub fn normalize_path(&self) -> Self {
+        let mut normalized = self.clone();
+        if let Some(path) = self.local_path() {
+            normalized.set_local_path(normalize_local(path));
+        }
+        normalized
+    }
+
+    /// Make a relative [`Self::local_path`] absolute by lexically joining
+    /// it onto `base_dir`, then [`Self::normalize_path`]. Absolute paths
+    /// are only normalised; other kinds are returned unchanged.
+    ///
+    /// Surfaces [`NixUriError::InvalidValue`] with field `path` when
+    /// `base_dir` is not an absolute UTF-8 path.
+    ///
+    /// ```
+    /// # use nix_uri::FlakeRef;
+    /// let rel: FlakeRef = "path:./sub/../app".parse().unwrap();
+    /// let abs = rel.resolve_against("/src/mono").unwrap();
+    /// assert_eq!(abs.to_string(), "path:/src/mono/app");
+    /// ```
+    pub fn resolve_against(&self, base_dir: impl AsRef<Path>) -> NixUriResult<Self> {
+        let Some(path) = self.local_path() else {
+            return Ok(self.clone());
+        };
+        let base = base_dir.as_ref();
+        let base = base
+            .to_str()
+            .filter(|b| b.starts_with('/'))
+            .ok_or_else(|| NixUriError::InvalidValue {
+                field: "path",
+                reason: format!("base directory `{}` is not absolute", base.display()),
+            })?;
+        let mut resolved = self.clone();
+        resolved.set_local_path(if path.starts_with('/') {
+            normalize_local(path)
+        } else {
+            join_local(base, path)
+        });
+        Ok(resolved)
+    }
+
+    /// Like [`Self::resolve_against`], but resolves the path through the
+    /// filesystem with [`std::fs::canonicalize`], so symlinks are followed
+    /// and `..` is applied after them (which the lexical form cannot do).
+    /// The path must exist; failures surface as [`NixUriError::Io`].
+    pub fn canonicalize_against(&self, base_dir: impl AsRef<Path>) -> NixUriResult<Self> {
+        let Some(path) = self.local_path() else {
+            return Ok(self.clone());
+        };
+        let joined = base_dir.as_ref().join(path);
+        let canonical = std::fs::canonicalize(&joined).map_err(|source| NixUriError::Io {
+            path: joined.clone(),
+            source,
+        })?;
+        let canonical =
+            canonical
+                .into_os_string()
+                .into_string()
+                .map_err(|raw| NixUriError::InvalidValue {
+                    field: "path",
+                    reason: format!("`{}` is not valid UTF-8", Path::new(&raw).display()),
+                })?;
+        let mut resolved = self.clone();
+        resolved.set_local_path(canonical);
+        Ok(resolved)
+    }
+
+    /// `true` for a `path:` ref whose path is relative (`path:./sub`,
+    /// `path:../lib`); those only mean something relative to a parent.
+    pub fn is_relative_path(&self) -> bool {
+        matches!(self.kind(), FlakeRefType::Path { path, .. } if !path.starts_with('/'))
+    }
+
+    /// Resolve a relative `path:` input against the `parent` flake that
+    /// declares it, the way Nix locks `inputs.foo.url = "path:./sub"`:
+    ///
+    /// - a `path:` parent yields a `path:` ref whose path is the parent's
+    ///   path (plus its `dir`) joined with this one, keeping this ref's
+    ///   `dir` as the subflake selector;
+    /// - any other parent (`github:`, `git+https:`, `git+file:`, tarballs,
+    ///   indirect refs) yields the parent's source with `dir` pointing at
+    ///   the subflake (see [`Self::subflake`]), this ref's `dir` included,
+    ///   since the subflake lives in the same fetched tree.
+    ///
+    /// This ref's other parameters and its fragment carry over, taking
+    /// precedence over the parent's. A ref that is not a
+    /// relative path is returned unchanged. Surfaces
+    /// [`NixUriError::InvalidValue`] when a remote parent's subflake would
+    /// climb above the source root.
+    ///
+    /// ```
+    /// # use nix_uri::FlakeRef;
+    /// let child: FlakeRef = "path:./sub".parse().unwrap();
+    /// let remote: FlakeRef = "github:org/mono?dir=nix".parse().unwrap();
+    /// assert_eq!(
+    ///     child.resolve_relative(&remote).unwrap().to_string(),
+    ///     "github:org/mono?dir=nix/sub"
+    /// );
+    /// let local: FlakeRef = "path:/src/mono".parse().unwrap();
+    /// assert_eq!(
+    ///     child.resolve_relative(&local).unwrap().to_string(),
+    ///     "path:/src/mono/sub"
+    /// );
+    /// ```
+    pub fn resolve_relative(&self, parent: &Self) -> NixUriResult<Self> {
+        let FlakeRefType::Path { path: rel, .. } = self.kind() else {
+            return Ok(self.clone());
+        };
+        if !self.is_relative_path() {
+            return Ok(self.clone());
+        }
+        let resolved = match parent.kind() {
+            FlakeRefType::Path { path: base, .. } => {
+                let base = match parent.dir() {
+                    Some(dir) => format!("{base}/{dir}"),
+                    None => base.clone(),
+                };
+                let mut resolved = self.clone();
+                if let FlakeRefType::Path { path, .. } = resolved.kind_mut() {
+                    *path = join_local(&base, rel);
+                }
+                resolved
+            }
+            _ => {
+                let rel = match self.dir() {
+                    Some(dir) => format!("{rel}/{dir}"),
+                    None => rel.clone(),
+                };
+                let mut resolved = parent.subflake(&rel)?;
+                resolved.params.overlay(&self.params);
+                resolved
+            }
+        };
+        Ok(resolved.with_fragment(self.fragment().map(str::to_string)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::{ParseOptions, test_support::Scratch};
+
+    #[rstest]
+    #[case("sub", "sub")]
+    #[case("/sub/", "sub")]
+    #[case("a//b", "a/b")]
+    #[case("./a/./b/.", "a/b")]
+    #[case("a/b/../c", "a/c")]
+    #[case("a/..", "")]
+    #[case("/", "")]
+    #[case("", "")]
+    fn normalizes(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(normalize_dir(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("..")]
+    #[case("../x")]
+    #[case("a/../../x")]
+    #[case("/../x")]
+    fn rejects_escape(#[case] input: &str) {
+        assert_matches!(
+            normalize_dir(input),
+            Err(NixUriError::InvalidValue { field: "dir", .. })
+        );
+    }
+
+    #[test]
+    fn normalized_dir_dedups_spellings() {
+        let a: FlakeRef = "github:org/mono?dir=pkgs/foo".parse().unwrap();
+        let b: FlakeRef = "github:org/mono?dir=./pkgs//foo/".parse().unwrap();
+        assert_ne!(a, b);
+        assert_eq!(
+            a.with_normalized_dir().unwrap(),
+            b.with_normalized_dir().unwrap()
+        );
+    }
+
+    #[test]
+    fn root_dir_is_removed() {
+        let parsed: FlakeRef = "github:org/mono?dir=./".parse().unwrap();
+        let normalized = parsed.with_normalized_dir().unwrap();
+        assert_eq!(normalized.dir(), None);
+        assert_eq!(normalized.to_string(), "github:org/mono");
+    }
+
+    #[test]
+    fn parse_option_normalizes_dir() {
+        let options = ParseOptions::new().with_normalized_dirs(true);
+        let parsed = FlakeRef::parse_with("github:org/mono?dir=/pkgs//foo/", &options).unwrap();
+        assert_eq!(parsed.dir(), Some("pkgs/foo"));
+        assert_matches!(
+            FlakeRef::parse_with("github:org/mono?dir=../x", &options),
+            Err(NixUriError::InvalidValue { field: "dir", .. })
+        );
+        let verbatim: FlakeRef = "github:org/mono?dir=/pkgs//foo/".parse().unwrap();
+        assert_eq!(verbatim.dir(), Some("/pkgs//foo/"));
+    }
+
+    #[test]
+    fn join_dir_rejects_escape_above_root() {
+        let parent: FlakeRef = "github:org/mono?dir=a".parse().unwrap();
+        assert_matches!(
+            parent.join_dir("../../x"),
+            Err(NixUriError::InvalidValue { field: "path", .. })
+        );
+    }
+
+    #[rstest]
+    #[case("github:org/mono", "./sub", "github:org/mono?dir=sub")]
+    #[case("github:org/mono?dir=nix", "path:./sub", "github:org/mono?dir=nix/sub")]
+    #[case("github:org/mono?dir=nix#pkg", "..", "github:org/mono")]
+    #[case(
+        "git+https://example.com/mono?ref=main&dir=a",
+        "../b",
+        "git+https://example.com/mono?dir=b&ref=main"
+    )]
+    fn subflake(#[case] parent: &str, #[case] path: &str, #[case] expected: &str) {
+        let parent: FlakeRef = parent.parse().unwrap();
+        assert_eq!(parent.subflake(path).unwrap().to_string(), expected);
+    }
+
+    #[test]
+    fn subflake_rejects_absolute_path() {
+        let parent: FlakeRef = "github:org/mono".parse().unwrap();
+        assert_matches!(
+            parent.subflake("/abs"),
+            Err(NixUriError::InvalidValue { field: "path", .. })
+        );
+    }
+
+    #[rstest]
+    #[case("path:./sub", "github:org/mono", "github:org/mono?dir=sub")]
+    #[case(
+        "path:../lib",
+        "github:org/mono?dir=nix/app",
+        "github:org/mono?dir=nix/lib"
+    )]
+    #[case("path:.", "github:org/mono?dir=nix", "github:org/mono?dir=nix")]
+    #[case(
+        "path:./sub#pkg",
+        "git+file:///src/mono?ref=main",
+        "git+file:///src/mono?dir=sub&ref=main#pkg"
+    )]
+    #[case(
+        "path:./sub",
+        "git+https://example.com/mono?dir=a",
+        "git+https://example.com/mono?dir=a/sub"
+    )]
+    #[case("path:./sub", "path:/src/mono", "path:/src/mono/sub")]
+    #[case("path:./sub", "path:/src/mono?dir=nix", "path:/src/mono/nix/sub")]
+    #[case("path:../lib", "path:/src/mono/app", "path:/src/mono/lib")]
+    #[case("path:./sub", "path:./mono", "path:./mono/sub")]
+    #[case("path:../../x", "path:./mono", "path:../x")]
+    #[case("path:./sub?dir=inner", "path:/src", "path:/src/sub?dir=inner")]
+    #[case(
+        "path:./sub?dir=inner&narHash=sha256-x",
+        "path:/src?dir=nix",
+        "path:/src/nix/sub?dir=inner&narHash=sha256-x"
+    )]
+    #[case(
+        "path:./sub?dir=inner",
+        "github:org/mono?dir=nix",
+        "github:org/mono?dir=nix/sub/inner"
+    )]
+    #[case(
+        "path:./sub?lastModified=5&narHash=sha256-child",
+        "git+https://example.com/mono?narHash=sha256-parent&ref=main",
+        "git+https://example.com/mono?dir=sub&lastModified=5&narHash=sha256-child&ref=main"
+    )]
+    fn resolve_relative(#[case] child: &str, #[case] parent: &str, #[case] expected: &str) {
+        let child: FlakeRef = child.parse().unwrap();
+        let parent: FlakeRef = parent.parse().unwrap();
+        assert_eq!(
+            child.resolve_relative(&parent).unwrap().to_string(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("path:/abs/sub")]
+    #[case("github:org/other")]
+    fn resolve_relative_leaves_absolute_refs(#[case] child: &str) {
+        let child: FlakeRef = child.parse().unwrap();
+        let parent: FlakeRef = "github:org/mono".parse().unwrap();
+        assert_eq!(child.resolve_relative(&parent).unwrap(), child);
+    }
+
+    #[test]
+    fn resolve_relative_rejects_escape_from_remote_source() {
+        let child: FlakeRef = "path:../../x".parse().unwrap();
+        let parent: FlakeRef = "github:org/mono?dir=a".parse().unwrap();
+        assert_matches!(
+            child.resolve_relative(&parent),
+            Err(NixUriError::InvalidValue { field: "path", .. })
+        );
+    }
+
+    #[rstest]
+    #[case("path:///a//b/", "path:/a/b")]
+    #[case("path:/a/./b/../c", "path:/a/c")]
+    #[case("path:/../a", "path:/a")]
+    #[case("path:./x//y/", "path:./x/y")]
+    #[case("path:../x/./y", "path:../x/y")]
+    #[case("git+file:///repo//sub/.?ref=main", "git+file:///repo/sub?ref=main")]
+    #[case("github:o/r", "github:o/r")]
+    fn normalize_path(#[case] input: &str, #[case] expected: &str) {
+        let parsed: FlakeRef = input.parse().unwrap();
+        assert_eq!(parsed.normalize_path().to_string(), expected);
+    }
+
+    #[test]
+    fn two_spellings_compare_equal() {
+        let a: FlakeRef = "path:///home/me//flake/".parse().unwrap();
+        let b: FlakeRef = "path:/home/me/./flake".parse().unwrap();
+        assert_ne!(a, b);
+        assert_eq!(a.normalize_path(), b.normalize_path());
+    }
+
+    #[rstest]
+    #[case("path:./sub", "/src", "path:/src/sub")]
+    #[case("path:../lib", "/src/app", "path:/src/lib")]
+    #[case("path:/abs//x", "/src", "path:/abs/x")]
+    #[case("git+file:./repo", "/src", "git+file:///src/repo")]
+    #[case("github:o/r", "/src", "github:o/r")]
+    fn resolve_against(#[case] input: &str, #[case] base: &str, #[case] expected: &str) {
+        let parsed: FlakeRef = input.parse().unwrap();
+        assert_eq!(parsed.resolve_against(base).unwrap().to_string(), expected);
+    }
+
+    #[test]
+    fn resolve_against_requires_absolute_base() {
+        let parsed: FlakeRef = "path:./sub".parse().unwrap();
+        assert_matches!(
+            parsed.resolve_against("relative"),
+            Err(NixUriError::InvalidValue { field: "path", .. })
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn canonicalize_follows_symlinks() {
+        let scratch = Scratch::new("canon");
+        let root = scratch.path();
+        let real = scratch.mkdir("real");
+        std::os::unix::fs::symlink(&real, scratch.join("link")).unwrap();
+
+        let parsed: FlakeRef = "path:./link".parse().unwrap();
+        let canonical = parsed.canonicalize_against(root).unwrap();
+        let expected = std::fs::canonicalize(&real).unwrap();
+        assert_eq!(canonical.local_path(), expected.to_str());
+
+        assert_matches!(
+            "path:./missing"
+                .parse::<FlakeRef>()
+                .unwrap()
+                .canonicalize_against(root),
+            Err(NixUriError::Io { .. })
+        );
+    }
+}
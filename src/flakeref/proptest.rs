@@ -15,8 +15,8 @@
 use proptest::prelude::*;
 
 use super::{
-    FlakeRef, FlakeRefType, GitForge, GitForgePlatform, LocationParameters, RefLocation,
-    ResourceType, ResourceUrl, TransportLayer,
+    FlakeRef, FlakeRefType, GitForge, GitForgePlatform, KeyType, LocationParameters, PublicKey,
+    RefLocation, ResourceType, ResourceUrl, TransportLayer,
 };
 
 /// A ref-name string. Bounded length, no `/` or other URL-special characters,
@@ -383,9 +383,9 @@ fn fragment_strategy() -> impl Strategy<Value = Option<String>> {
 /// Per-field strategy for the seven Git-typed params. Bools are
 /// represented `Option<bool>` because the parser surfaces typed bools
 /// for `lfs`/`exportIgnore`/`allRefs`/`verifyCommit`; the three
-/// signature-key params (`keytype`, `publicKey`, `publicKeys`) stay
-/// free-form `Option<String>` because Nix does not enumerate valid
-/// values.
+/// signature-key params (`keytype`, `publicKey`, `publicKeys`) are
+/// generated well formed because `verifyCommit=1` validates them at parse
+/// time: a Nix key type, a base64 blob, and a Nix-encoded JSON key list.
 type GitTypedParams = (
     Option<bool>,
     Option<bool>,
@@ -397,15 +397,30 @@ type GitTypedParams = (
 );
 
 fn git_typed_params_strategy() -> impl Strategy<Value = GitTypedParams> {
-    let key_value = "[a-zA-Z0-9._\\-]{1,16}";
+    let key_type = prop::sample::select(vec![
+        KeyType::Dsa,
+        KeyType::Ecdsa,
+        KeyType::EcdsaSk,
+        KeyType::Ed25519,
+        KeyType::Ed25519Sk,
+        KeyType::Rsa,
+    ]);
+    let key_blob = "[A-Za-z0-9+/]{4,32}={0,2}";
+    let key_list = prop::collection::vec((key_type.clone(), key_blob), 1..3).prop_map(|keys| {
+        let keys: Vec<PublicKey> = keys
+            .into_iter()
+            .map(|(kt, key)| PublicKey::new(kt, key))
+            .collect();
+        PublicKey::render_list(&keys)
+    });
     (
         prop::option::of(any::<bool>()),
         prop::option::of(any::<bool>()),
         prop::option::of(any::<bool>()),
         prop::option::of(any::<bool>()),
-        prop::option::of(key_value),
-        prop::option::of(key_value),
-        prop::option::of(key_value),
+        prop::option::of(key_type.prop_map(|kt| kt.as_str().to_string())),
+        prop::option::of(key_blob),
+        prop::option::of(key_list),
     )
 }
 
@@ -0,0 +1,651 @@
+//! Classification of `ref_` strings.
+//!
+//! On the wire a ref is an opaque string; [`RefName`] recognises the three
+//! shapes update tooling cares about: semver tags (`v1.2.3`), Nixpkgs/NixOS
+//! channel branches (`nixos-24.05`, `nixpkgs-unstable`, `release-23.11`),
+//! and fully qualified refs (`refs/heads/x`, `refs/tags/y`,
+//! `refs/pull/N/head`). Anything else is [`RefName::Other`].
+//!
+//! Classification is lossless: every variant's `Display` reproduces the
+//! input string, so a classified ref can be written straight back into a
+//! `FlakeRef`.
+
+use std::{cmp::Ordering, fmt::Display};
+
+use semver::Version;
+
+/// A classified ref name. Built with [`RefName::parse`] or
+/// [`crate::FlakeRef::ref_name`].
+///
+/// `PartialOrd` is defined only where an ordering is meaningful: two
+/// semver tags compare by version, and two channels of the same family
+/// and variant compare by release (see [`Channel`]). Every other pair is
+/// unordered (`None`) unless the two values are equal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RefName {
+    /// A semver tag, optionally `v`-prefixed. `tag` is the spelling from
+    /// the ref; `version` its parsed value.
+    Semver { tag: String, version: Version },
+    /// A Nixpkgs/NixOS channel branch.
+    Channel(Channel),
+    /// A fully qualified `refs/...` name.
+    Qualified(QualifiedRef),
+    /// Any other ref name.
+    Other(String),
+}
+
+impl RefName {
+    /// Classify `name`. Never fails: unrecognised names are
+    /// [`RefName::Other`].
+    pub fn parse(name: &str) -> Self {
+        if let Some(version) = parse_semver_tag(name) {
+            return Self::Semver {
+                tag: name.to_string(),
+                version,
+            };
+        }
+        if let Some(channel) = Channel::parse(name) {
+            return Self::Channel(channel);
+        }
+        if let Some(qualified) = QualifiedRef::parse(name) {
+            return Self::Qualified(qualified);
+        }
+        Self::Other(name.to_string())
+    }
+
+    /// The semver version for a [`RefName::Semver`] tag, or for a
+    /// `refs/tags/<semver>` qualified ref.
+    pub fn semver(&self) -> Option<&Version> {
+        match self {
+            Self::Semver { version, .. } => Some(version),
+            Self::Qualified(QualifiedRef::Tag { version, .. }) => version.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The channel for a [`RefName::Channel`].
+    pub fn channel(&self) -> Option<&Channel> {
+        match self {
+            Self::Channel(channel) => Some(channel),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RefName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Semver { tag, .. } => f.write_str(tag),
+            Self::Channel(channel) => write!(f, "{channel}"),
+            Self::Qualified(qualified) => write!(f, "{qualified}"),
+            Self::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+impl PartialOrd for RefName {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (
+                Self::Semver {
+                    tag: a,
+                    version: va,
+                },
+                Self::Semver {
+                    tag: b,
+                    version: vb,
+                },
+            ) => match va.cmp(vb) {
+                // `v1.0.0` and `1.0.0` name the same version but are
+                // distinct refs; leave them unordered rather than equal.
+                Ordering::Equal if a != b => None,
+                ord => Some(ord),
+            },
+            (Self::Channel(a), Self::Channel(b)) => a.partial_cmp(b),
+            _ if self == other => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+/// Strict semver (`MAJOR.MINOR.PATCH[-pre][+build]`) with an optional
+/// leading `v`.
+fn parse_semver_tag(name: &str) -> Option<Version> {
+    let body = name.strip_prefix('v').unwrap_or(name);
+    if !body.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Version::parse(body).ok()
+}
+
+/// Which branch family a [`Channel`] belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ChannelFamily {
+    /// `nixos-*`: tested against the NixOS test suite.
+    Nixos,
+    /// `nixpkgs-*`: the package-set-only channels (e.g. darwin).
+    Nixpkgs,
+    /// `release-*`: the un-channelled release branches.
+    Release,
+}
+
+impl ChannelFamily {
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Nixos => "nixos",
+            Self::Nixpkgs => "nixpkgs",
+            Self::Release => "release",
+        }
+    }
+}
+
+/// The release a [`Channel`] tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ChannelRelease {
+    /// A stable `YY.MM` release.
+    Stable { year: u8, month: u8 },
+    /// The rolling `unstable` channel.
+    Unstable,
+}
+
+impl ChannelRelease {
+    /// The release after this one on the NixOS calendar: `.03`/`.09`
+    /// until 20.09, then `.05`/`.11` from 21.05 on. `None` for
+    /// [`Self::Unstable`] and for months outside either cadence.
+    pub fn next(self) -> Option<Self> {
+        let Self::Stable { year, month } = self else {
+            return None;
+        };
+        let (year, month) = match (year, month) {
+            (20, 9) => (21, 5),
+            (..=20, 3) => (year, 9),
+            (..=20, 9) => (year.checked_add(1)?, 3),
+            (21.., 5) => (year, 11),
+            (21.., 11) => (year.checked_add(1)?, 5),
+            _ => return None,
+        };
+        Some(Self::Stable { year, month })
+    }
+
+    /// The last day (`YYYY-MM-DD`) this release received updates, for the
+    /// releases from 20.09 to 26.05.
+    ///
+    /// `None` for [`Self::Unstable`] and for releases outside that range:
+    /// older ones (see [`Self::predates_end_of_life_dates`]) are long out
+    /// of support but their dates are not tracked, and newer ones are
+    /// unknown to this version of the crate, so a caller cannot tell from
+    /// `None` alone whether a release is still supported.
+    pub fn end_of_life(self) -> Option<&'static str> {
+        let Self::Stable { year, month } = self else {
+            return None;
+        };
+        NIXOS_END_OF_LIFE
+            .iter()
+            .find(|(y, m, _)| (*y, *m) == (year, month))
+            .map(|(_, _, date)| *date)
+    }
+
+    /// `true` for a stable release older than every release
+    /// [`Self::end_of_life`] knows, all of which are out of support.
+    pub fn predates_end_of_life_dates(self) -> bool {
+        let Self::Stable { year, month } = self else {
+            return false;
+        };
+        NIXOS_END_OF_LIFE
+            .first()
+            .is_some_and(|(y, m, _)| (year, month) < (*y, *m))
+    }
+}
+
+/// NixOS stable releases and the last day each was supported. From 21.05
+/// on a release is maintained until the end of the month after its
+/// successor ships: `.05` through December, `.11` through June.
+const NIXOS_END_OF_LIFE: &[(u8, u8, &str)] = &[
+    (20, 9, "2021-06-30"),
+    (21, 5, "2021-12-31"),
+    (21, 11, "2022-06-30"),
+    (22, 5, "2022-12-31"),
+    (22, 11, "2023-06-30"),
+    (23, 5, "2023-12-31"),
+    (23, 11, "2024-06-30"),
+    (24, 5, "2024-12-31"),
+    (24, 11, "2025-06-30"),
+    (25, 5, "2025-12-31"),
+    (25, 11, "2026-06-30"),
+    (26, 5, "2026-12-31"),
+];
+
+impl PartialOrd for ChannelRelease {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ChannelRelease {
+    /// Stable releases order by `(year, month)`; `unstable` sorts after
+    /// every stable release.
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Unstable, Self::Unstable) => Ordering::Equal,
+            (Self::Unstable, Self::Stable { .. }) => Ordering::Greater,
+            (Self::Stable { .. }, Self::Unstable) => Ordering::Less,
+            (
+                Self::Stable {
+                    year: ya,
+                    month: ma,
+                },
+                Self::Stable {
+                    year: yb,
+                    month: mb,
+                },
+            ) => (ya, ma).cmp(&(yb, mb)),
+        }
+    }
+}
+
+/// A Nixpkgs/NixOS channel branch: `<family>-<release>[-<variant>]`, e.g.
+/// `nixos-24.05`, `nixos-unstable-small`, `nixpkgs-24.05-darwin`,
+/// `release-23.11`.
+///
+/// Two channels are ordered only when they share `family` and `variant`;
+/// `nixos-24.05 < nixos-24.11 < nixos-unstable`, but `nixos-24.05` and
+/// `nixos-24.05-small` are unordered.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Channel {
+    pub family: ChannelFamily,
+    pub release: ChannelRelease,
+    /// Trailing `-<variant>` (`small`, `darwin`, ...), if any.
+    pub variant: Option<String>,
+}
+
+impl Channel {
+    /// Recognise a channel branch name. The release must be spelled
+    /// `YY.MM` with two digits each (or `unstable`), so `Display`
+    /// reproduces the input; `release-unstable` is not a branch and is
+    /// rejected.
+    pub fn parse(name: &str) -> Option<Self> {
+        let (family, rest) = [
+            ChannelFamily::Nixos,
+            ChannelFamily::Nixpkgs,
+            ChannelFamily::Release,
+        ]
+        .into_iter()
+        .find_map(|family| {
+            name.strip_prefix(family.prefix())
+                .and_then(|r| r.strip_prefix('-'))
+                .map(|r| (family, r))
+        })?;
+        let (release, variant) = match rest.split_once('-') {
+            Some((release, variant)) => (release, Some(variant)),
+            None => (rest, None),
+        };
+        if variant.is_some_and(|v| v.is_empty()) {
+            return None;
+        }
+        let release = if release == "unstable" {
+            if family == ChannelFamily::Release {
+                return None;
+            }
+            ChannelRelease::Unstable
+        } else {
+            let (year, month) = release.split_once('.')?;
+            if year.len() != 2 || month.len() != 2 {
+                return None;
+            }
+            if !year
+                .bytes()
+                .chain(month.bytes())
+                .all(|b| b.is_ascii_digit())
+            {
+                return None;
+            }
+            let year: u8 = year.parse().ok()?;
+            let month: u8 = month.parse().ok()?;
+            if !(1..=12).contains(&month) {
+                return None;
+            }
+            ChannelRelease::Stable { year, month }
+        };
+        Some(Self {
+            family,
+            release,
+            variant: variant.map(str::to_string),
+        })
+    }
+
+    /// Whether this channel tracks a stable release.
+    pub fn is_stable(&self) -> bool {
+        matches!(self.release, ChannelRelease::Stable { .. })
+    }
+
+    /// The same family and variant on the next stable release, e.g.
+    /// `nixos-24.05` -> `nixos-24.11`, `nixpkgs-24.11-darwin` ->
+    /// `nixpkgs-25.05-darwin`. `None` for `unstable`.
+    pub fn next(&self) -> Option<Self> {
+        Some(Self {
+            release: self.release.next()?,
+            ..self.clone()
+        })
+    }
+}
+
+impl PartialOrd for Channel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.family != other.family || self.variant != other.variant {
+            return None;
+        }
+        Some(self.release.cmp(&other.release))
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.family.prefix())?;
+        match self.release {
+            ChannelRelease::Stable { year, month } => write!(f, "-{year:02}.{month:02}")?,
+            ChannelRelease::Unstable => f.write_str("-unstable")?,
+        }
+        if let Some(variant) = &self.variant {
+            write!(f, "-{variant}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A fully qualified `refs/...` name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum QualifiedRef {
+    /// `refs/heads/<name>`.
+    Head(String),
+    /// `refs/tags/<name>`; `version` is set when `<name>` is a semver tag.
+    Tag {
+        name: String,
+        version: Option<Version>,
+    },
+    /// `refs/pull/<number>/<suffix>` (GitHub) or
+    /// `refs/merge-requests/<number>/<suffix>` (GitLab); `suffix` is
+    /// usually `head` or `merge`.
+    Pull {
+        namespace: PullNamespace,
+        number: u64,
+        suffix: String,
+    },
+    /// Any other `refs/...` name, stored in full.
+    Other(String),
+}
+
+/// Which forge namespace a [`QualifiedRef::Pull`] lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PullNamespace {
+    /// `refs/pull/...`
+    PullRequest,
+    /// `refs/merge-requests/...`
+    MergeRequest,
+}
+
+impl QualifiedRef {
+    /// Recognise a `refs/...` name; `None` for anything not under `refs/`.
+    pub fn parse(name: &str) -> Option<Self> {
+        let rest = name.strip_prefix("refs/")?;
+        if let Some(head) = rest.strip_prefix("heads/").filter(|h| !h.is_empty()) {
+            return Some(Self::Head(head.to_string()));
+        }
+        if let Some(tag) = rest.strip_prefix("tags/").filter(|t| !t.is_empty()) {
+            return Some(Self::Tag {
+                name: tag.to_string(),
+                version: parse_semver_tag(tag),
+            });
+        }
+        for (prefix, namespace) in [
+            ("pull/", PullNamespace::PullRequest),
+            ("merge-requests/", PullNamespace::MergeRequest),
+        ] {
+            let Some(pull) = rest.strip_prefix(prefix) else {
+                continue;
+            };
+            if let Some((number, suffix)) = pull.split_once('/') {
+                let canonical_number = !number.starts_with('0') || number == "0";
+                if let (Ok(number), true, false) =
+                    (number.parse(), canonical_number, suffix.is_empty())
+                {
+                    return Some(Self::Pull {
+                        namespace,
+                        number,
+                        suffix: suffix.to_string(),
+                    });
+                }
+            }
+        }
+        Some(Self::Other(name.to_string()))
+    }
+
+    /// The name without its `refs/<namespace>/` prefix for heads and
+    /// tags; the full ref otherwise.
+    pub fn short_name(&self) -> String {
+        match self {
+            Self::Head(name) | Self::Tag { name, .. } => name.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl Display for QualifiedRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Head(name) => write!(f, "refs/heads/{name}"),
+            Self::Tag { name, .. } => write!(f, "refs/tags/{name}"),
+            Self::Pull {
+                namespace,
+                number,
+                suffix,
+            } => {
+                let ns = match namespace {
+                    PullNamespace::PullRequest => "pull",
+                    PullNamespace::MergeRequest => "merge-requests",
+                };
+                write!(f, "refs/{ns}/{number}/{suffix}")
+            }
+            Self::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::FlakeRef;
+
+    fn channel(name: &str) -> Channel {
+        Channel::parse(name).unwrap_or_else(|| panic!("{name} must classify as a channel"))
+    }
+
+    #[rstest]
+    #[case("v1.2.3", 1, 2, 3)]
+    #[case("1.2.3", 1, 2, 3)]
+    #[case("v0.10.0-rc.1", 0, 10, 0)]
+    fn semver_tags(#[case] name: &str, #[case] major: u64, #[case] minor: u64, #[case] patch: u64) {
+        let parsed = RefName::parse(name);
+        let v = parsed.semver().unwrap();
+        assert_eq!((v.major, v.minor, v.patch), (major, minor, patch));
+        assert_eq!(parsed.to_string(), name);
+    }
+
+    #[rstest]
+    #[case("v0.5")]
+    #[case("v1")]
+    #[case("version-1.2.3")]
+    #[case("main")]
+    fn non_semver_tags_are_other(#[case] name: &str) {
+        assert_eq!(RefName::parse(name), RefName::Other(name.into()));
+    }
+
+    #[rstest]
+    #[case("nixos-24.05", ChannelFamily::Nixos, ChannelRelease::Stable { year: 24, month: 5 }, None)]
+    #[case("nixos-unstable", ChannelFamily::Nixos, ChannelRelease::Unstable, None)]
+    #[case(
+        "nixos-unstable-small",
+        ChannelFamily::Nixos,
+        ChannelRelease::Unstable,
+        Some("small")
+    )]
+    #[case("nixos-24.11-small", ChannelFamily::Nixos, ChannelRelease::Stable { year: 24, month: 11 }, Some("small"))]
+    #[case("nixpkgs-24.05-darwin", ChannelFamily::Nixpkgs, ChannelRelease::Stable { year: 24, month: 5 }, Some("darwin"))]
+    #[case(
+        "nixpkgs-unstable",
+        ChannelFamily::Nixpkgs,
+        ChannelRelease::Unstable,
+        None
+    )]
+    #[case("release-23.11", ChannelFamily::Release, ChannelRelease::Stable { year: 23, month: 11 }, None)]
+    fn channels(
+        #[case] name: &str,
+        #[case] family: ChannelFamily,
+        #[case] release: ChannelRelease,
+        #[case] variant: Option<&str>,
+    ) {
+        let parsed = RefName::parse(name);
+        let c = parsed.channel().unwrap();
+        assert_eq!(c.family, family);
+        assert_eq!(c.release, release);
+        assert_eq!(c.variant.as_deref(), variant);
+        assert_eq!(parsed.to_string(), name);
+    }
+
+    #[rstest]
+    #[case("nixos-24.5")]
+    #[case("nixos-2024.05")]
+    #[case("nixos-24.13")]
+    #[case("nixos-")]
+    #[case("nixos-24.05-")]
+    #[case("release-unstable")]
+    #[case("nixosish-24.05")]
+    fn channel_lookalikes_are_other(#[case] name: &str) {
+        assert_eq!(RefName::parse(name), RefName::Other(name.into()));
+    }
+
+    #[rstest]
+    #[case("nixos-24.05", "nixos-24.11")]
+    #[case("nixos-24.11", "nixos-25.05")]
+    #[case("nixpkgs-24.11-darwin", "nixpkgs-25.05-darwin")]
+    #[case("release-20.03", "release-20.09")]
+    #[case("nixos-19.09", "nixos-20.03")]
+    #[case("nixos-20.09", "nixos-21.05")]
+    fn next_channel(#[case] current: &str, #[case] next: &str) {
+        assert_eq!(channel(current).next().unwrap().to_string(), next);
+    }
+
+    #[test]
+    fn unstable_has_no_next() {
+        assert_eq!(channel("nixos-unstable").next(), None);
+    }
+
+    #[test]
+    fn channels_order_within_family_and_variant() {
+        assert!(channel("nixos-24.05") < channel("nixos-24.11"));
+        assert!(channel("nixos-24.11") < channel("nixos-unstable"));
+        assert_eq!(
+            channel("nixos-24.05").partial_cmp(&channel("nixos-24.05-small")),
+            None
+        );
+        assert_eq!(
+            channel("nixos-24.05").partial_cmp(&channel("nixpkgs-24.05")),
+            None
+        );
+    }
+
+    #[test]
+    fn ref_names_order_only_when_comparable() {
+        assert!(RefName::parse("v1.2.3") < RefName::parse("v1.10.0"));
+        assert!(RefName::parse("nixos-23.11") < RefName::parse("nixos-24.05"));
+        assert_eq!(
+            RefName::parse("v1.0.0").partial_cmp(&RefName::parse("1.0.0")),
+            None
+        );
+        assert_eq!(
+            RefName::parse("v1.0.0").partial_cmp(&RefName::parse("nixos-24.05")),
+            None
+        );
+        assert_eq!(
+            RefName::parse("main").partial_cmp(&RefName::parse("main")),
+            Some(Ordering::Equal)
+        );
+    }
+
+    #[rstest]
+    #[case("nixos-24.05", Some("2024-12-31"))]
+    #[case("nixpkgs-24.11-darwin", Some("2025-06-30"))]
+    #[case("nixos-20.09", Some("2021-06-30"))]
+    #[case("release-19.09", None)]
+    #[case("nixos-26.11", None)]
+    #[case("nixos-unstable", None)]
+    fn end_of_life(#[case] name: &str, #[case] expected: Option<&str>) {
+        assert_eq!(channel(name).release.end_of_life(), expected);
+    }
+
+    #[rstest]
+    #[case("release-19.09", true)]
+    #[case("nixos-17.03", true)]
+    #[case("nixos-20.09", false)]
+    #[case("nixos-26.11", false)]
+    #[case("nixos-unstable", false)]
+    fn predates_end_of_life_dates(#[case] name: &str, #[case] expected: bool) {
+        assert_eq!(channel(name).release.predates_end_of_life_dates(), expected);
+    }
+
+    #[rstest]
+    #[case("refs/heads/main")]
+    #[case("refs/tags/v1.2.3")]
+    #[case("refs/pull/123/head")]
+    #[case("refs/merge-requests/7/merge")]
+    #[case("refs/notes/commits")]
+    fn qualified_refs_round_trip(#[case] name: &str) {
+        let parsed = RefName::parse(name);
+        assert_matches!(parsed, RefName::Qualified(_));
+        assert_eq!(parsed.to_string(), name);
+    }
+
+    #[test]
+    fn qualified_ref_shapes() {
+        assert_eq!(
+            QualifiedRef::parse("refs/heads/feature/x"),
+            Some(QualifiedRef::Head("feature/x".into()))
+        );
+        assert_eq!(
+            RefName::parse("refs/tags/v1.2.3").semver(),
+            Some(&Version::new(1, 2, 3))
+        );
+        assert_eq!(
+            QualifiedRef::parse("refs/pull/42/head"),
+            Some(QualifiedRef::Pull {
+                namespace: PullNamespace::PullRequest,
+                number: 42,
+                suffix: "head".into(),
+            })
+        );
+        assert_matches!(
+            QualifiedRef::parse("refs/pull/042/head"),
+            Some(QualifiedRef::Other(_))
+        );
+        assert_eq!(QualifiedRef::parse("heads/main"), None);
+    }
+
+    #[test]
+    fn flake_ref_ref_name() {
+        let parsed: FlakeRef = "github:nixos/nixpkgs/nixos-24.05".parse().unwrap();
+        assert_eq!(
+            parsed.ref_name().unwrap().channel().unwrap().next(),
+            Some(channel("nixos-24.11"))
+        );
+        let unpinned: FlakeRef = "github:nixos/nixpkgs".parse().unwrap();
+        assert_eq!(unpinned.ref_name(), None);
+    }
+}
@@ -0,0 +1,389 @@
+//! Locking refs through pluggable resolvers.
+//!
+//! A [`Resolver`] answers "which exact source does this ref denote?" with
+//! [`LockedAttrs`]; [`lock`] writes those attributes into the ref the way
+//! Nix does when it creates a lock file entry: only the attributes the
+//! input type records, never silently replacing a `rev` or `narHash` the
+//! input already pins, and failing when the result is still not locked.
+//! Fetching is the resolver's business; nothing here touches the network.
+
+use std::collections::HashMap;
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{FlakeRef, FlakeRefType, ResourceType, Rev},
+};
+
+/// What a [`Resolver`] learned about the source a ref denotes. Every field
+/// is optional; [`lock`] drops the ones the input type does not record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LockedAttrs {
+    /// The commit the ref resolved to.
+    pub rev: Option<Rev>,
+    /// The fully qualified ref that was followed (`refs/heads/main`).
+    /// Recorded for git and Mercurial inputs that named no ref.
+    pub ref_: Option<String>,
+    /// SRI hash of the NAR serialisation of the source tree.
+    pub nar_hash: Option<String>,
+    /// Commit or archive timestamp, in seconds since the epoch.
+    pub last_modified: Option<u64>,
+    /// Number of commits reachable from `rev`.
+    pub rev_count: Option<u64>,
+}
+
+impl LockedAttrs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rev(mut self, rev: Rev) -> Self {
+        self.rev = Some(rev);
+        self
+    }
+
+    pub fn with_ref(mut self, ref_: Option<String>) -> Self {
+        self.ref_ = ref_;
+        self
+    }
+
+    pub fn with_nar_hash(mut self, nar_hash: impl Into<String>) -> Self {
+        self.nar_hash = Some(nar_hash.into());
+        self
+    }
+
+    pub fn with_last_modified(mut self, last_modified: u64) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    pub fn with_rev_count(mut self, rev_count: u64) -> Self {
+        self.rev_count = Some(rev_count);
+        self
+    }
+}
+
+/// Turns an unlocked ref into the attributes that pin it.
+///
+/// Implementations fetch however they like (a forge API, the local
+/// repository, a cache); [`lock`] applies the answer. Errors should
+/// surface as [`NixUriError::Resolve`] unless a more specific variant
+/// fits.
+pub trait Resolver {
+    /// The attributes identifying exactly one source tree for `flake_ref`.
+    fn resolve(&self, flake_ref: &FlakeRef) -> NixUriResult<LockedAttrs>;
+}
+
+/// Lock `flake_ref` with `resolver`, following Nix's locked-attribute
+/// rules:
+///
+/// - forges (`github:` etc.) record `rev`, `narHash` and `lastModified`,
+///   and drop the `ref` once pinned;
+/// - `git+` inputs record all of `rev`, `ref`, `revCount`,
+///   `lastModified` and `narHash`; `hg+` inputs the same minus
+///   `lastModified`;
+/// - tarballs, files and paths record `narHash`, `lastModified`, `rev`
+///   and `revCount`.
+///
+/// A `rev` or `narHash` the input already carries must agree with the
+/// resolver's (an abbreviated `rev` must be a prefix of it). The result
+/// must satisfy [`FlakeRef::is_locked`]. Indirect refs have to go through
+/// a registry first ([`crate::resolve_indirect`]). Failures surface as
+/// [`NixUriError::Resolve`].
+pub fn lock(flake_ref: &FlakeRef, resolver: &dyn Resolver) -> NixUriResult<FlakeRef> {
+    let fail = |reason: String| NixUriError::Resolve {
+        input: flake_ref.to_string(),
+        reason,
+    };
+    if matches!(flake_ref.kind(), FlakeRefType::Indirect { .. }) {
+        return Err(fail(
+            "indirect refs must be resolved through a registry before locking".into(),
+        ));
+    }
+    let attrs = resolver.resolve(flake_ref)?;
+    let recorded = Recorded::for_kind(flake_ref.kind());
+    let mut locked = flake_ref.clone();
+
+    if let Some(rev) = attrs.rev.filter(|_| recorded.rev) {
+        if rev.is_abbreviated() {
+            return Err(fail(format!("resolver returned abbreviated rev `{rev}`")));
+        }
+        if let Some(pinned) = flake_ref.rev() {
+            if !rev.matches_prefix(pinned) {
+                return Err(fail(format!(
+                    "input is pinned to `{pinned}` but resolved to `{rev}`"
+                )));
+            }
+        }
+        if matches!(flake_ref.kind(), FlakeRefType::GitForge(_)) {
+            locked = locked.pin_to_rev(rev.to_string());
+        } else {
+            locked.set_rev(Some(rev.to_string()));
+        }
+    }
+    if let Some(ref_) = attrs.ref_.filter(|_| recorded.ref_) {
+        if locked.ref_().is_none() {
+            locked.set_ref(Some(ref_));
+        }
+    }
+    if let Some(nar_hash) = attrs.nar_hash {
+        if let Some(pinned) = flake_ref.params().nar_hash_value() {
+            if pinned != nar_hash {
+                return Err(fail(format!(
+                    "NAR hash mismatch: input has `{pinned}`, resolved to `{nar_hash}`"
+                )));
+            }
+        }
+        locked.set_nar_hash(Some(nar_hash));
+    }
+    if let Some(last_modified) = attrs.last_modified.filter(|_| recorded.last_modified) {
+        locked.set_last_modified(Some(last_modified.to_string()));
+    }
+    if let Some(rev_count) = attrs.rev_count.filter(|_| recorded.rev_count) {
+        locked.set_rev_count(Some(rev_count.to_string()));
+    }
+
+    if !locked.is_locked() {
+        return Err(fail(
+            "resolver returned neither a full rev nor a NAR hash".into(),
+        ));
+    }
+    Ok(locked)
+}
+
+/// Which [`LockedAttrs`] an input type records in its locked form.
+#[allow(clippy::struct_excessive_bools)]
+struct Recorded {
+    rev: bool,
+    ref_: bool,
+    last_modified: bool,
+    rev_count: bool,
+}
+
+impl Recorded {
+    fn for_kind(kind: &FlakeRefType) -> Self {
+        let (rev, ref_, last_modified, rev_count) = match kind {
+            FlakeRefType::GitForge(_) => (true, false, true, false),
+            FlakeRefType::Resource(res) => match res.res_type {
+                ResourceType::Git => (true, true, true, true),
+                ResourceType::Mercurial => (true, true, false, true),
+                ResourceType::File | ResourceType::Tarball => (true, false, true, true),
+            },
+            FlakeRefType::Path { .. } => (true, false, true, true),
+            FlakeRefType::Indirect { .. } => (false, false, false, false),
+        };
+        Self {
+            rev,
+            ref_,
+            last_modified,
+            rev_count,
+        }
+    }
+}
+
+impl FlakeRef {
+    /// `true` when the ref identifies exactly one source tree, by Nix's
+    /// per-type rule: forges, git and Mercurial need a full `rev`;
+    /// tarballs, files and paths need a `narHash`. Indirect refs are never
+    /// locked.
+    pub fn is_locked(&self) -> bool {
+        let full_rev = || self.typed_rev().is_some_and(|rev| !rev.is_abbreviated());
+        match self.kind() {
+            FlakeRefType::GitForge(_) => full_rev(),
+            FlakeRefType::Resource(res) => match res.res_type {
+                ResourceType::Git | ResourceType::Mercurial => full_rev(),
+                ResourceType::File | ResourceType::Tarball => {
+                    self.params().nar_hash_value().is_some()
+                }
+            },
+            FlakeRefType::Path { .. } => self.params().nar_hash_value().is_some(),
+            FlakeRefType::Indirect { .. } => false,
+        }
+    }
+}
+
+/// A [`Resolver`] answering from a fixed table, for tests and offline
+/// tooling. Refs are matched on their canonical form
+/// ([`FlakeRef::to_canonical_string`]), so `?ref=main` and `/main`
+/// spellings of a forge ref share an entry.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    entries: HashMap<String, LockedAttrs>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `attrs` for `flake_ref`, replacing any earlier entry.
+    pub fn insert(&mut self, flake_ref: &FlakeRef, attrs: LockedAttrs) {
+        self.entries.insert(flake_ref.to_canonical_string(), attrs);
+    }
+
+    /// Builder form of [`Self::insert`].
+    pub fn with(mut self, flake_ref: &FlakeRef, attrs: LockedAttrs) -> Self {
+        self.insert(flake_ref, attrs);
+        self
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn resolve(&self, flake_ref: &FlakeRef) -> NixUriResult<LockedAttrs> {
+        self.entries
+            .get(&flake_ref.to_canonical_string())
+            .cloned()
+            .ok_or_else(|| NixUriError::Resolve {
+                input: flake_ref.to_string(),
+                reason: "no entry in memory resolver".into(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+
+    const REV: &str = "b2df4e4e80e04cbb33a350f87717f4bd6140d298";
+    const NAR: &str = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    fn parse(input: &str) -> FlakeRef {
+        input.parse().unwrap()
+    }
+
+    fn full_attrs() -> LockedAttrs {
+        LockedAttrs::new()
+            .with_rev(Rev::parse(REV).unwrap())
+            .with_ref(Some("refs/heads/main".into()))
+            .with_nar_hash(NAR)
+            .with_last_modified(1_700_000_000)
+            .with_rev_count(42)
+    }
+
+    fn lock_with(input: &str) -> NixUriResult<FlakeRef> {
+        let flake_ref = parse(input);
+        let resolver = MemoryResolver::new().with(&flake_ref, full_attrs());
+        lock(&flake_ref, &resolver)
+    }
+
+    #[test]
+    fn forge_drops_ref_and_rev_count() {
+        let locked = lock_with("github:nixos/nixpkgs/nixos-unstable").unwrap();
+        assert_eq!(
+            locked.to_string(),
+            format!(
+                "github:nixos/nixpkgs/{REV}?lastModified=1700000000&narHash={}",
+                NAR.replace('=', "%3D")
+            )
+        );
+        assert!(locked.is_locked());
+    }
+
+    #[test]
+    fn git_records_everything() {
+        let locked = lock_with("git+https://example.com/repo?ref=main").unwrap();
+        assert_eq!(locked.ref_(), Some("main"));
+        assert_eq!(locked.rev(), Some(REV));
+        let rendered = locked.to_string();
+        assert!(rendered.contains("revCount=42"));
+        assert!(rendered.contains("lastModified=1700000000"));
+
+        // A ref is filled in only when the input named none.
+        let locked = lock_with("git+https://example.com/repo").unwrap();
+        assert_eq!(locked.ref_(), Some("refs/heads/main"));
+    }
+
+    #[test]
+    fn mercurial_has_no_last_modified() {
+        let locked = lock_with("hg+https://example.com/repo").unwrap();
+        assert!(!locked.to_string().contains("lastModified"));
+        assert!(locked.to_string().contains("revCount=42"));
+    }
+
+    #[test]
+    fn tarball_is_locked_by_nar_hash() {
+        let flake_ref = parse("https://example.com/src.tar.gz");
+        assert!(!flake_ref.is_locked());
+        let resolver =
+            MemoryResolver::new().with(&flake_ref, LockedAttrs::new().with_nar_hash(NAR));
+        let locked = lock(&flake_ref, &resolver).unwrap();
+        assert!(locked.is_locked());
+        assert_eq!(locked.params().nar_hash_value(), Some(NAR));
+    }
+
+    #[test]
+    fn pinned_rev_must_agree() {
+        let flake_ref = parse(&format!("github:o/r/{REV}"));
+        let other = "0000000000000000000000000000000000000000";
+        let resolver = MemoryResolver::new().with(
+            &flake_ref,
+            LockedAttrs::new().with_rev(Rev::parse(other).unwrap()),
+        );
+        assert_matches!(
+            lock(&flake_ref, &resolver),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("pinned"))
+        );
+    }
+
+    #[test]
+    fn abbreviated_pin_is_completed() {
+        let options = crate::ParseOptions::new().with_short_revs(true);
+        let flake_ref =
+            FlakeRef::parse_with("git+https://example.com/r?rev=b2df4e4", &options).unwrap();
+        assert!(!flake_ref.is_locked());
+        let resolver = MemoryResolver::new().with(&flake_ref, full_attrs());
+        assert_eq!(lock(&flake_ref, &resolver).unwrap().rev(), Some(REV));
+    }
+
+    #[test]
+    fn nar_hash_mismatch_is_rejected() {
+        let flake_ref = parse("https://example.com/src.tar.gz?narHash=sha256-other");
+        let resolver =
+            MemoryResolver::new().with(&flake_ref, LockedAttrs::new().with_nar_hash(NAR));
+        assert_matches!(
+            lock(&flake_ref, &resolver),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("NAR hash"))
+        );
+    }
+
+    #[test]
+    fn result_must_be_locked() {
+        let flake_ref = parse("github:o/r");
+        let resolver =
+            MemoryResolver::new().with(&flake_ref, LockedAttrs::new().with_last_modified(1));
+        assert_matches!(
+            lock(&flake_ref, &resolver),
+            Err(NixUriError::Resolve { .. })
+        );
+    }
+
+    #[test]
+    fn indirect_and_unknown_refs_fail() {
+        assert_matches!(lock_with("nixpkgs"), Err(NixUriError::Resolve { .. }));
+        assert_matches!(
+            lock(&parse("github:o/r"), &MemoryResolver::new()),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("memory"))
+        );
+    }
+
+    #[test]
+    fn memory_resolver_matches_canonical_spellings() {
+        let resolver = MemoryResolver::new().with(&parse("github:o/r/main"), full_attrs());
+        assert!(lock(&parse("github:o/r?ref=main"), &resolver).is_ok());
+    }
+
+    #[rstest]
+    #[case("github:o/r", false)]
+    #[case(&format!("github:o/r/{REV}"), true)]
+    #[case(&format!("git+file:///r?rev={REV}"), true)]
+    #[case("git+file:///r?ref=main", false)]
+    #[case(&format!("path:/r?narHash={NAR}"), true)]
+    #[case(&format!("path:/r?rev={REV}"), false)]
+    #[case(&format!("nixpkgs/{REV}"), false)]
+    fn locked_by_type(#[case] input: &str, #[case] expected: bool) {
+        assert_eq!(parse(input).is_locked(), expected);
+    }
+}
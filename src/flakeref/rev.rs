@@ -0,0 +1,213 @@
+//! Typed commit revisions.
+//!
+//! The `rev` slot on a `FlakeRef` is a plain string; [`Rev`] is the typed
+//! view over it. A full rev is 40 hex digits (SHA-1) or 64 (SHA-256).
+//! Nix prints abbreviated revs (`abc1234`) in `nix flake metadata`, and
+//! those only identify a commit once resolved against a repository, so
+//! [`Rev`] keeps them distinguishable ([`RevKind::Abbreviated`]) instead of
+//! treating every hex string alike.
+
+use std::fmt::Display;
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::validators::looks_like_rev,
+};
+
+/// Shortest abbreviated rev accepted, matching git's `core.abbrev` floor.
+pub(crate) const MIN_ABBREV_LEN: usize = 4;
+
+/// Which hash shape a [`Rev`] has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RevKind {
+    /// A full 40-hex SHA-1 commit hash.
+    Sha1,
+    /// A full 64-hex SHA-256 commit hash.
+    Sha256,
+    /// A hex prefix shorter than a full hash; unresolved until looked up in
+    /// a repository.
+    Abbreviated,
+}
+
+/// A commit revision: a full SHA-1/SHA-256 hash or an abbreviated prefix.
+///
+/// Stored lowercase, so comparisons and prefix checks are
+/// case-insensitive like git's.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Rev(String);
+
+impl Rev {
+    /// Parse a full 40/64-hex rev or an abbreviated hex prefix of at least
+    /// four digits. Surfaces [`NixUriError::InvalidValue`] with field `rev`
+    /// otherwise.
+    pub fn parse(s: &str) -> NixUriResult<Self> {
+        if looks_like_rev(s) || is_abbreviated_rev(s) {
+            Ok(Self(s.to_ascii_lowercase()))
+        } else {
+            Err(NixUriError::InvalidValue {
+                field: "rev",
+                reason: format!(
+                    "expected a 40-hex (SHA-1) or 64-hex (SHA-256) commit, or a hex prefix of at least {MIN_ABBREV_LEN} digits"
+                ),
+            })
+        }
+    }
+
+    /// Which hash shape this is. A 40-hex rev is read as SHA-1, never as
+    /// an abbreviated SHA-256, matching Nix.
+    pub fn kind(&self) -> RevKind {
+        match self.0.len() {
+            40 => RevKind::Sha1,
+            64 => RevKind::Sha256,
+            _ => RevKind::Abbreviated,
+        }
+    }
+
+    /// `true` for an abbreviated (unresolved) prefix.
+    pub fn is_abbreviated(&self) -> bool {
+        self.kind() == RevKind::Abbreviated
+    }
+
+    /// The lowercase hex string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The first `n` hex digits, as `git rev-parse --short=<n>` would
+    /// print them. Clamped to the rev's own length.
+    pub fn abbrev(&self, n: usize) -> &str {
+        &self.0[..n.min(self.0.len())]
+    }
+
+    /// `true` when `prefix` (case-insensitive) is a prefix of this rev.
+    /// An empty prefix matches nothing: it identifies no commit.
+    pub fn matches_prefix(&self, prefix: &str) -> bool {
+        !prefix.is_empty()
+            && prefix.len() <= self.0.len()
+            && self.0.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+    }
+}
+
+impl Display for Rev {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Rev {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for Rev {
+    type Err = NixUriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// `true` for a hex string at least [`MIN_ABBREV_LEN`] long and shorter than
+/// a full SHA-256 that is not itself a full rev.
+pub(crate) fn is_abbreviated_rev(s: &str) -> bool {
+    (MIN_ABBREV_LEN..64).contains(&s.len())
+        && s.len() != 40
+        && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::{FlakeRef, ParseOptions};
+
+    const SHA1: &str = "b2df4e4e80e04cbb33a350f87717f4bd6140d298";
+    const SHA256: &str = "b2df4e4e80e04cbb33a350f87717f4bd6140d298b2df4e4e80e04cbb33a350f8";
+
+    #[rstest]
+    #[case(SHA1, RevKind::Sha1)]
+    #[case(SHA256, RevKind::Sha256)]
+    #[case("abc1234", RevKind::Abbreviated)]
+    #[case("abcd", RevKind::Abbreviated)]
+    fn kinds(#[case] input: &str, #[case] kind: RevKind) {
+        assert_eq!(Rev::parse(input).unwrap().kind(), kind);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("abc")]
+    #[case("main")]
+    #[case("abc123g")]
+    #[case("b2df4e4e80e04cbb33a350f87717f4bd6140d298b2df4e4e80e04cbb33a350f87")]
+    fn rejects(#[case] input: &str) {
+        assert_matches!(
+            Rev::parse(input),
+            Err(NixUriError::InvalidValue { field: "rev", .. })
+        );
+    }
+
+    #[test]
+    fn lowercases() {
+        assert_eq!(Rev::parse("ABC1234").unwrap().as_str(), "abc1234");
+    }
+
+    #[test]
+    fn abbrev_clamps() {
+        let rev = Rev::parse(SHA1).unwrap();
+        assert_eq!(rev.abbrev(7), "b2df4e4");
+        assert_eq!(rev.abbrev(100), SHA1);
+    }
+
+    #[test]
+    fn prefix_matching() {
+        let rev = Rev::parse(SHA1).unwrap();
+        assert!(rev.matches_prefix("b2df4e4"));
+        assert!(rev.matches_prefix("B2DF4E4"));
+        assert!(rev.matches_prefix(SHA1));
+        assert!(!rev.matches_prefix("b2df4e5"));
+        assert!(!rev.matches_prefix(""));
+        assert!(!rev.matches_prefix(&format!("{SHA1}0")));
+    }
+
+    #[test]
+    fn short_query_rev_rejected_by_default() {
+        assert_matches!(
+            "github:o/r?rev=abc1234".parse::<FlakeRef>(),
+            Err(NixUriError::InvalidValue { field: "rev", .. })
+        );
+    }
+
+    #[test]
+    fn short_query_rev_accepted_leniently_and_marked_unresolved() {
+        let options = ParseOptions::new().with_short_revs(true);
+        let parsed = FlakeRef::parse_with("github:o/r?rev=abc1234", &options).unwrap();
+        assert_eq!(parsed.rev(), Some("abc1234"));
+        assert!(parsed.typed_rev().unwrap().is_abbreviated());
+        assert!(parsed.has_unresolved_rev());
+        assert_eq!(parsed.to_string(), "github:o/r?rev=abc1234");
+    }
+
+    #[test]
+    fn lenient_mode_still_rejects_non_hex() {
+        let options = ParseOptions::new().with_short_revs(true);
+        assert_matches!(
+            FlakeRef::parse_with("github:o/r?rev=abc", &options),
+            Err(NixUriError::InvalidValue { field: "rev", .. })
+        );
+        assert_matches!(
+            FlakeRef::parse_with("github:o/r?rev=main", &options),
+            Err(NixUriError::InvalidValue { field: "rev", .. })
+        );
+    }
+
+    #[test]
+    fn full_rev_is_resolved() {
+        let parsed: FlakeRef = format!("github:o/r/{SHA1}").parse().unwrap();
+        assert_eq!(parsed.typed_rev().unwrap().kind(), RevKind::Sha1);
+        assert!(!parsed.has_unresolved_rev());
+    }
+}
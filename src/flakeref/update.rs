@@ -0,0 +1,418 @@
+//! Offline update planning.
+//!
+//! [`FlakeRef::plan_update`] takes the refs a remote advertises (as
+//! [`Candidate`]s the caller fetched however it likes) and an
+//! [`UpdatePolicy`], and proposes the `FlakeRef` the input should move to.
+//! Nothing here touches the network; the candidate list is the only view
+//! of the remote, which keeps planning deterministic and testable.
+
+use std::fmt::Display;
+
+use semver::Version;
+
+use crate::flakeref::{Channel, FlakeRef, FlakeRefType, RefName, validators::looks_like_rev};
+
+/// One ref advertised by the remote: a tag or branch name and the commit
+/// it points at. Candidates whose `rev` is not a full 40/64-hex hash are
+/// ignored by the planner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Candidate {
+    pub name: String,
+    pub rev: String,
+}
+
+impl Candidate {
+    pub fn new(name: impl Into<String>, rev: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            rev: rev.into(),
+        }
+    }
+}
+
+/// How [`FlakeRef::plan_update`] chooses among candidates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UpdatePolicy {
+    /// Move a semver tag (`v1.2.3`) to the highest tag with the same major
+    /// version. Pre-releases are only considered when the current tag is
+    /// itself a pre-release.
+    LatestSemverSameMajor,
+    /// Move a stable channel branch (`nixos-24.05`) to the oldest newer
+    /// stable channel of the same family and variant in the candidate list
+    /// (`nixos-24.11`). Never moves onto or off `unstable`.
+    NextStableChannel,
+    /// Pin the newest commit of a branch. `branch` names the branch to
+    /// follow; `None` follows the input's current `ref_`.
+    TrackBranch { branch: Option<String> },
+}
+
+/// Why an [`UpdateProposal`] was made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UpdateReason {
+    /// A newer semver tag within the same major version.
+    SemverBump { from: Version, to: Version },
+    /// The next stable channel of the same family.
+    ChannelUpgrade { from: Channel, to: Channel },
+    /// The tracked branch moved to a new commit. `from` is the previously
+    /// pinned rev, if any.
+    NewRev {
+        branch: String,
+        from: Option<String>,
+        to: String,
+    },
+}
+
+impl Display for UpdateReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SemverBump { from, to } => write!(f, "semver {from} -> {to}"),
+            Self::ChannelUpgrade { from, to } => write!(f, "channel {from} -> {to}"),
+            Self::NewRev {
+                branch,
+                from: Some(from),
+                to,
+            } => write!(f, "branch {branch} moved {from} -> {to}"),
+            Self::NewRev {
+                branch,
+                from: None,
+                to,
+            } => write!(f, "branch {branch} pinned at {to}"),
+        }
+    }
+}
+
+/// The outcome of [`FlakeRef::plan_update`]: the updated input and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct UpdateProposal {
+    pub flake_ref: FlakeRef,
+    pub reason: UpdateReason,
+}
+
+impl FlakeRef {
+    /// Propose an update for this input from `candidates` under `policy`.
+    ///
+    /// Returns `None` when the policy does not apply (e.g. the current ref
+    /// is not a semver tag under [`UpdatePolicy::LatestSemverSameMajor`])
+    /// or no candidate is newer than what is already set. Query
+    /// parameters and fragment carry over unchanged.
+    ///
+    /// Refs and candidates are classified by their short names, so
+    /// `refs/tags/v1.2.3` is a semver tag and `refs/heads/nixos-24.05` a
+    /// channel; a ref the input spells with its `refs/heads/` or
+    /// `refs/tags/` prefix keeps it when moved.
+    ///
+    /// Ref moves go through [`Self::with_ref`]; if the input was also
+    /// pinned to a rev, the rev is moved to the chosen candidate's commit
+    /// so the two never disagree. [`UpdatePolicy::TrackBranch`] pins with
+    /// [`Self::pin_to_rev`] on git forges (which cannot carry both a ref
+    /// and a rev) and with [`Self::with_rev`] elsewhere, keeping `?ref=`.
+    pub fn plan_update(
+        &self,
+        candidates: &[Candidate],
+        policy: &UpdatePolicy,
+    ) -> Option<UpdateProposal> {
+        let mut candidates = candidates.iter().filter(|c| looks_like_rev(&c.rev));
+        match policy {
+            UpdatePolicy::LatestSemverSameMajor => {
+                let from = short_ref_name(self.ref_()?).semver()?.clone();
+                let allow_pre = !from.pre.is_empty();
+                let (best, to) = candidates
+                    .filter_map(|c| {
+                        let RefName::Semver { version, .. } = short_ref_name(&c.name) else {
+                            return None;
+                        };
+                        (version.major == from.major
+                            && version > from
+                            && (allow_pre || version.pre.is_empty()))
+                        .then_some((c, version))
+                    })
+                    .max_by(|(_, a), (_, b)| a.cmp(b))?;
+                Some(UpdateProposal {
+                    flake_ref: self.retarget(best),
+                    reason: UpdateReason::SemverBump { from, to },
+                })
+            }
+            UpdatePolicy::NextStableChannel => {
+                let from = short_ref_name(self.ref_()?).channel()?.clone();
+                if !from.is_stable() {
+                    return None;
+                }
+                let (best, to) = candidates
+                    .filter_map(|c| {
+                        let RefName::Channel(channel) = short_ref_name(&c.name) else {
+                            return None;
+                        };
+                        (channel.is_stable() && channel > from).then_some((c, channel))
+                    })
+                    .min_by(|(_, a), (_, b)| a.release.cmp(&b.release))?;
+                Some(UpdateProposal {
+                    flake_ref: self.retarget(best),
+                    reason: UpdateReason::ChannelUpgrade { from, to },
+                })
+            }
+            UpdatePolicy::TrackBranch { branch } => {
+                let branch = branch.as_deref().or_else(|| self.ref_())?;
+                let head = candidates.find(|c| short_name(&c.name) == short_name(branch))?;
+                let from = self.rev();
+                if from == Some(head.rev.as_str()) {
+                    return None;
+                }
+                let reason = UpdateReason::NewRev {
+                    branch: branch.to_string(),
+                    from: from.map(str::to_string),
+                    to: head.rev.clone(),
+                };
+                let flake_ref = if matches!(self.kind(), FlakeRefType::GitForge(_)) {
+                    self.clone().pin_to_rev(head.rev.clone())
+                } else {
+                    self.clone().with_rev(Some(head.rev.clone()))
+                };
+                Some(UpdateProposal { flake_ref, reason })
+            }
+        }
+    }
+
+    /// Point the ref at `candidate`, moving an existing rev pin along. The
+    /// candidate's short name is used, qualified the way the current ref
+    /// is.
+    fn retarget(&self, candidate: &Candidate) -> Self {
+        let current = self.ref_().unwrap_or_default();
+        let prefix = &current[..current.len() - short_name(current).len()];
+        let name = format!("{prefix}{}", short_name(&candidate.name));
+        let moved = self.clone().with_ref(Some(name));
+        if self.rev().is_some() {
+            moved.with_rev(Some(candidate.rev.clone()))
+        } else {
+            moved
+        }
+    }
+}
+
+/// `name` without a `refs/heads/` or `refs/tags/` prefix.
+fn short_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or_else(|| name.strip_prefix("refs/tags/"))
+        .filter(|short| !short.is_empty())
+        .unwrap_or(name)
+}
+
+/// Classify `name` by its short name.
+fn short_ref_name(name: &str) -> RefName {
+    RefName::parse(short_name(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+
+    const REV_A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const REV_B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+    const REV_C: &str = "cccccccccccccccccccccccccccccccccccccccc";
+
+    fn parse(uri: &str) -> FlakeRef {
+        uri.parse().unwrap()
+    }
+
+    fn tags() -> Vec<Candidate> {
+        vec![
+            Candidate::new("v1.2.3", REV_A),
+            Candidate::new("v1.4.0", REV_B),
+            Candidate::new("v1.5.0-rc.1", REV_C),
+            Candidate::new("v2.0.0", REV_C),
+            Candidate::new("main", REV_C),
+        ]
+    }
+
+    #[test]
+    fn semver_stays_within_major() {
+        let proposal = parse("github:o/r/v1.2.3")
+            .plan_update(&tags(), &UpdatePolicy::LatestSemverSameMajor)
+            .unwrap();
+        assert_eq!(proposal.flake_ref.to_string(), "github:o/r/v1.4.0");
+        assert_eq!(
+            proposal.reason,
+            UpdateReason::SemverBump {
+                from: Version::new(1, 2, 3),
+                to: Version::new(1, 4, 0),
+            }
+        );
+        assert_eq!(proposal.reason.to_string(), "semver 1.2.3 -> 1.4.0");
+    }
+
+    #[test]
+    fn semver_prerelease_only_from_prerelease() {
+        let proposal = parse("github:o/r/v1.5.0-beta.1")
+            .plan_update(&tags(), &UpdatePolicy::LatestSemverSameMajor)
+            .unwrap();
+        assert_eq!(proposal.flake_ref.ref_(), Some("v1.5.0-rc.1"));
+    }
+
+    #[test]
+    fn semver_already_latest_is_none() {
+        assert_eq!(
+            parse("github:o/r/v1.4.0").plan_update(&tags(), &UpdatePolicy::LatestSemverSameMajor),
+            None
+        );
+    }
+
+    #[test]
+    fn semver_policy_ignores_non_semver_ref() {
+        assert_eq!(
+            parse("github:o/r/main").plan_update(&tags(), &UpdatePolicy::LatestSemverSameMajor),
+            None
+        );
+    }
+
+    #[test]
+    fn semver_moves_existing_rev_pin() {
+        let current = parse(&format!("git+https://example.com/r?ref=v1.2.3&rev={REV_A}"));
+        let proposal = current
+            .plan_update(&tags(), &UpdatePolicy::LatestSemverSameMajor)
+            .unwrap();
+        assert_eq!(proposal.flake_ref.ref_(), Some("v1.4.0"));
+        assert_eq!(proposal.flake_ref.rev(), Some(REV_B));
+    }
+
+    #[test]
+    fn semver_classifies_qualified_refs_by_short_name() {
+        let qualified = [
+            Candidate::new("refs/tags/v1.2.3", REV_A),
+            Candidate::new("refs/tags/v1.4.0", REV_B),
+        ];
+        let short = parse("git+https://example.com/r?ref=v1.2.3")
+            .plan_update(&qualified, &UpdatePolicy::LatestSemverSameMajor)
+            .unwrap();
+        assert_eq!(short.flake_ref.ref_(), Some("v1.4.0"));
+
+        let current = parse(&format!(
+            "git+https://example.com/r?ref=refs/tags/v1.2.3&rev={REV_A}"
+        ));
+        for candidates in [&qualified[..], &tags()] {
+            let proposal = current
+                .plan_update(candidates, &UpdatePolicy::LatestSemverSameMajor)
+                .unwrap();
+            assert_eq!(proposal.flake_ref.ref_(), Some("refs/tags/v1.4.0"));
+            assert_eq!(proposal.flake_ref.rev(), Some(REV_B));
+        }
+    }
+
+    #[test]
+    fn channel_keeps_qualified_branch() {
+        let candidates = [
+            Candidate::new("refs/heads/nixos-24.05", REV_A),
+            Candidate::new("refs/heads/nixos-24.11", REV_B),
+        ];
+        let proposal = parse("git+https://github.com/NixOS/nixpkgs?ref=refs/heads/nixos-24.05")
+            .plan_update(&candidates, &UpdatePolicy::NextStableChannel)
+            .unwrap();
+        assert_eq!(proposal.flake_ref.ref_(), Some("refs/heads/nixos-24.11"));
+    }
+
+    #[test]
+    fn next_stable_channel() {
+        let candidates = [
+            Candidate::new("nixos-23.11", REV_A),
+            Candidate::new("nixos-24.05", REV_A),
+            Candidate::new("nixos-24.11", REV_B),
+            Candidate::new("nixos-24.11-small", REV_B),
+            Candidate::new("nixos-25.05", REV_C),
+            Candidate::new("nixos-unstable", REV_C),
+        ];
+        let proposal = parse("github:nixos/nixpkgs/nixos-24.05?dir=lib#hello")
+            .plan_update(&candidates, &UpdatePolicy::NextStableChannel)
+            .unwrap();
+        assert_eq!(
+            proposal.flake_ref.to_string(),
+            "github:nixos/nixpkgs/nixos-24.11?dir=lib#hello"
+        );
+        assert_eq!(
+            proposal.reason.to_string(),
+            "channel nixos-24.05 -> nixos-24.11"
+        );
+    }
+
+    #[test]
+    fn unstable_channel_is_not_upgraded() {
+        let candidates = [Candidate::new("nixos-24.11", REV_B)];
+        assert_eq!(
+            parse("github:nixos/nixpkgs/nixos-unstable")
+                .plan_update(&candidates, &UpdatePolicy::NextStableChannel),
+            None
+        );
+    }
+
+    #[test]
+    fn track_branch_pins_forge_rev() {
+        let proposal = parse("github:o/r/main")
+            .plan_update(&tags(), &UpdatePolicy::TrackBranch { branch: None })
+            .unwrap();
+        assert_eq!(
+            proposal.flake_ref.to_string(),
+            format!("github:o/r/{REV_C}")
+        );
+        assert_matches!(
+            proposal.reason,
+            UpdateReason::NewRev { branch, from: None, .. } => assert_eq!(branch, "main")
+        );
+    }
+
+    #[test]
+    fn track_branch_keeps_query_ref() {
+        let current = parse(&format!("git+https://example.com/r?ref=main&rev={REV_A}"));
+        let proposal = current
+            .plan_update(&tags(), &UpdatePolicy::TrackBranch { branch: None })
+            .unwrap();
+        assert_eq!(proposal.flake_ref.ref_(), Some("main"));
+        assert_eq!(proposal.flake_ref.rev(), Some(REV_C));
+        assert_eq!(
+            proposal.reason.to_string(),
+            format!("branch main moved {REV_A} -> {REV_C}")
+        );
+    }
+
+    #[test]
+    fn track_branch_matches_qualified_names() {
+        let qualified = [Candidate::new("refs/heads/main", REV_C)];
+        let proposal = parse(&format!("git+https://example.com/r?ref=main&rev={REV_A}"))
+            .plan_update(&qualified, &UpdatePolicy::TrackBranch { branch: None })
+            .unwrap();
+        assert_eq!(proposal.flake_ref.ref_(), Some("main"));
+        assert_eq!(proposal.flake_ref.rev(), Some(REV_C));
+
+        let proposal = parse(&format!(
+            "git+https://example.com/r?ref=refs/heads/main&rev={REV_A}"
+        ))
+        .plan_update(&tags(), &UpdatePolicy::TrackBranch { branch: None })
+        .unwrap();
+        assert_eq!(proposal.flake_ref.ref_(), Some("refs/heads/main"));
+        assert_eq!(proposal.flake_ref.rev(), Some(REV_C));
+    }
+
+    #[test]
+    fn track_named_branch_from_pinned_forge() {
+        let current = parse(&format!("github:o/r/{REV_A}"));
+        let policy = UpdatePolicy::TrackBranch {
+            branch: Some("main".into()),
+        };
+        let proposal = current.plan_update(&tags(), &policy).unwrap();
+        assert_eq!(proposal.flake_ref.rev(), Some(REV_C));
+        let up_to_date = proposal.flake_ref.plan_update(&tags(), &policy);
+        assert_eq!(up_to_date, None);
+    }
+
+    #[test]
+    fn candidates_with_malformed_revs_are_ignored() {
+        let candidates = [Candidate::new("main", "HEAD")];
+        assert_eq!(
+            parse("github:o/r/main")
+                .plan_update(&candidates, &UpdatePolicy::TrackBranch { branch: None }),
+            None
+        );
+    }
+}
@@ -4,6 +4,8 @@
 //! name or a 40-character commit hash. Nix discriminates on the 40-hex
 //! shape; everything else is treated as a ref name.
 
+use std::fmt;
+
 use crate::error::NixUriError;
 
 /// Parse a boolean query-parameter value. Nix's URL-time coercion is
@@ -79,6 +81,119 @@ pub(crate) fn validated_ref_name(value: &str) -> Result<String, NixUriError> {
     Ok(value.to_string())
 }
 
+/// A rule from `git check-ref-format` that a ref name breaks. Returned by
+/// [`check_ref_format`]; `Display` gives the diagnostic surfaced through
+/// [`NixUriError::InvalidValue`] when strict ref checking is enabled on
+/// [`crate::ParseOptions`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RefFormatViolation {
+    /// The name is empty.
+    Empty,
+    /// The name is the single character `@`.
+    SingleAt,
+    /// The name starts with `/`.
+    LeadingSlash,
+    /// The name ends with `/`.
+    TrailingSlash,
+    /// The name contains `//` (an empty component).
+    ConsecutiveSlashes,
+    /// The name ends with `.`.
+    TrailingDot,
+    /// The name contains `..`.
+    ConsecutiveDots,
+    /// The name contains `@{`.
+    AtBrace,
+    /// The name contains a control character, space, `~`, `^`, `:`, `?`,
+    /// `*`, `[`, or `\`.
+    ForbiddenChar(char),
+    /// A `/`-separated component starts with `.`.
+    ComponentStartsWithDot { component: String },
+    /// A `/`-separated component ends with `.lock`.
+    ComponentEndsWithLock { component: String },
+}
+
+impl fmt::Display for RefFormatViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("ref name is empty"),
+            Self::SingleAt => f.write_str("ref name cannot be the single character '@'"),
+            Self::LeadingSlash => f.write_str("ref name cannot begin with '/'"),
+            Self::TrailingSlash => f.write_str("ref name cannot end with '/'"),
+            Self::ConsecutiveSlashes => f.write_str("ref name cannot contain '//'"),
+            Self::TrailingDot => f.write_str("ref name cannot end with '.'"),
+            Self::ConsecutiveDots => f.write_str("ref name cannot contain '..'"),
+            Self::AtBrace => f.write_str("ref name cannot contain '@{'"),
+            Self::ForbiddenChar(c) => {
+                write!(f, "ref name cannot contain '{}'", c.escape_debug())
+            }
+            Self::ComponentStartsWithDot { component } => {
+                write!(f, "ref component `{component}` cannot begin with '.'")
+            }
+            Self::ComponentEndsWithLock { component } => {
+                write!(f, "ref component `{component}` cannot end with '.lock'")
+            }
+        }
+    }
+}
+
+/// Check `name` against every rule of `git check-ref-format
+/// --allow-onelevel` and report the first one it breaks.
+///
+/// Stricter than the default parse, which only checks Nix's character
+/// class and so accepts names such as `foo..bar`, `a//b`, `x.lock`, or
+/// `main.` that git (and therefore the fetcher) later rejects.
+/// `--allow-onelevel` is implied because flake refs are routinely bare
+/// branch names (`main`) rather than `refs/heads/main`.
+///
+/// Rules are checked whole-name first (empty, `@`, slashes, trailing dot,
+/// `..`, `@{`, forbidden characters) and then per component, so the
+/// reported violation is deterministic for names that break several.
+pub fn check_ref_format(name: &str) -> Result<(), RefFormatViolation> {
+    if name.is_empty() {
+        return Err(RefFormatViolation::Empty);
+    }
+    if name == "@" {
+        return Err(RefFormatViolation::SingleAt);
+    }
+    if name.starts_with('/') {
+        return Err(RefFormatViolation::LeadingSlash);
+    }
+    if name.ends_with('/') {
+        return Err(RefFormatViolation::TrailingSlash);
+    }
+    if name.contains("//") {
+        return Err(RefFormatViolation::ConsecutiveSlashes);
+    }
+    if name.ends_with('.') {
+        return Err(RefFormatViolation::TrailingDot);
+    }
+    if name.contains("..") {
+        return Err(RefFormatViolation::ConsecutiveDots);
+    }
+    if name.contains("@{") {
+        return Err(RefFormatViolation::AtBrace);
+    }
+    if let Some(c) = name.chars().find(|&c| {
+        c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+    }) {
+        return Err(RefFormatViolation::ForbiddenChar(c));
+    }
+    for component in name.split('/') {
+        if component.starts_with('.') {
+            return Err(RefFormatViolation::ComponentStartsWithDot {
+                component: component.to_string(),
+            });
+        }
+        if component.ends_with(".lock") {
+            return Err(RefFormatViolation::ComponentEndsWithLock {
+                component: component.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
 /// Returns `true` if `s` matches Nix's accepted `?host=` value shape:
 /// ASCII alphanumerics, `.`, and `-`. The empty string is accepted
 /// (semantically equivalent to no override; the fetch layer handles the
@@ -107,6 +222,8 @@ pub(crate) fn validated_host_name(value: &str) -> Result<String, NixUriError> {
 
 #[cfg(test)]
 mod tests {
+    use rstest::rstest;
+
     use super::*;
 
     #[test]
@@ -300,4 +417,57 @@ mod tests {
             other => panic!("unexpected error: {other:?}"),
         }
     }
+
+    #[rstest]
+    #[case("main")]
+    #[case("refs/heads/feature/x")]
+    #[case("release-23.11")]
+    #[case("v1.2.3")]
+    #[case("a.b")]
+    #[case("@foo")]
+    #[case("foo@bar")]
+    fn check_ref_format_accepts(#[case] name: &str) {
+        assert_eq!(check_ref_format(name), Ok(()));
+    }
+
+    #[rstest]
+    #[case("", RefFormatViolation::Empty)]
+    #[case("@", RefFormatViolation::SingleAt)]
+    #[case("/main", RefFormatViolation::LeadingSlash)]
+    #[case("main/", RefFormatViolation::TrailingSlash)]
+    #[case("a//b", RefFormatViolation::ConsecutiveSlashes)]
+    #[case("main.", RefFormatViolation::TrailingDot)]
+    #[case("foo..bar", RefFormatViolation::ConsecutiveDots)]
+    #[case("foo@{1}", RefFormatViolation::AtBrace)]
+    #[case("a b", RefFormatViolation::ForbiddenChar(' '))]
+    #[case("a~1", RefFormatViolation::ForbiddenChar('~'))]
+    #[case("a^", RefFormatViolation::ForbiddenChar('^'))]
+    #[case("a:b", RefFormatViolation::ForbiddenChar(':'))]
+    #[case("a?", RefFormatViolation::ForbiddenChar('?'))]
+    #[case("a*", RefFormatViolation::ForbiddenChar('*'))]
+    #[case("a[b", RefFormatViolation::ForbiddenChar('['))]
+    #[case("a\\b", RefFormatViolation::ForbiddenChar('\\'))]
+    #[case("a\x7fb", RefFormatViolation::ForbiddenChar('\x7f'))]
+    #[case(".hidden", RefFormatViolation::ComponentStartsWithDot { component: ".hidden".into() })]
+    #[case("refs/.x/y", RefFormatViolation::ComponentStartsWithDot { component: ".x".into() })]
+    #[case("foo.lock", RefFormatViolation::ComponentEndsWithLock { component: "foo.lock".into() })]
+    #[case("a.lock/b", RefFormatViolation::ComponentEndsWithLock { component: "a.lock".into() })]
+    fn check_ref_format_rejects(#[case] name: &str, #[case] violation: RefFormatViolation) {
+        assert_eq!(check_ref_format(name), Err(violation));
+    }
+
+    #[rstest]
+    #[case(' ', "ref name cannot contain ' '")]
+    #[case('\x7f', "ref name cannot contain '\\u{7f}'")]
+    fn forbidden_chars_are_quoted(#[case] c: char, #[case] expected: &str) {
+        assert_eq!(RefFormatViolation::ForbiddenChar(c).to_string(), expected);
+    }
+
+    #[test]
+    fn character_class_accepts_what_check_ref_format_rejects() {
+        for name in ["foo..bar", "foo.lock", "a//b", "main/", "main."] {
+            assert!(validate_ref_name(name), "{name}");
+            assert!(check_ref_format(name).is_err(), "{name}");
+        }
+    }
 }
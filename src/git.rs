@@ -0,0 +1,838 @@
+//! Read-only access to a local git repository, without the git CLI.
+//!
+//! Covers what locking a `git+file:` ref needs: resolving `HEAD` and named
+//! refs (loose and `packed-refs`), reading commits from loose objects and
+//! packfiles, walking history for `revCount`, and comparing the index
+//! against `HEAD` and the work tree for dirty state. Only SHA-1
+//! repositories are understood.
+
+mod index;
+mod pack;
+mod refs;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use flate2::read::ZlibDecoder;
+
+use crate::error::{NixUriError, NixUriResult};
+
+pub(crate) use refs::ResolvedRef;
+
+/// A SHA-1 object name.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct ObjectId(pub(crate) [u8; 20]);
+
+impl ObjectId {
+    /// Parse a full 40-digit hex name; `None` otherwise.
+    pub(crate) fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != 40 {
+            return None;
+        }
+        let mut id = [0; 20];
+        for (byte, pair) in id.iter_mut().zip(hex.chunks_exact(2)) {
+            *byte = (nibble(pair[0])? << 4) | nibble(pair[1])?;
+        }
+        Some(Self(id))
+    }
+
+    /// Read a raw 20-byte name; `None` when `bytes` is shorter.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self(bytes.get(..20)?.try_into().ok()?))
+    }
+
+    /// The lowercase 40-digit hex name.
+    pub(crate) fn to_hex(self) -> String {
+        self.to_string()
+    }
+
+    /// `true` when the hex name starts with `prefix` (lowercase hex).
+    pub(crate) fn has_hex_prefix(self, prefix: &str) -> bool {
+        self.to_hex().starts_with(prefix)
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+fn nibble(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// The four object types stored in a repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl ObjectKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Commit => "commit",
+            Self::Tree => "tree",
+            Self::Blob => "blob",
+            Self::Tag => "tag",
+        }
+    }
+
+    fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"commit" => Some(Self::Commit),
+            b"tree" => Some(Self::Tree),
+            b"blob" => Some(Self::Blob),
+            b"tag" => Some(Self::Tag),
+            _ => None,
+        }
+    }
+}
+
+/// The object name git assigns to `data` stored as `kind`.
+pub(crate) fn hash_object(kind: ObjectKind, data: &[u8]) -> ObjectId {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(format!("{} {}\0", kind.as_str(), data.len()).as_bytes());
+    hasher.update(data);
+    ObjectId(hasher.digest().bytes())
+}
+
+/// The fields of a commit that locking needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Commit {
+    pub(crate) tree: ObjectId,
+    pub(crate) parents: Vec<ObjectId>,
+    /// Committer timestamp, in seconds since the epoch.
+    pub(crate) committer_time: u64,
+}
+
+impl Commit {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut committer_time = None;
+        for line in data.split(|&b| b == b'\n') {
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = split_once(line, b' ')?;
+            match key {
+                b"tree" => tree = ObjectId::from_hex(std::str::from_utf8(value).ok()?),
+                b"parent" => parents.push(ObjectId::from_hex(std::str::from_utf8(value).ok()?)?),
+                b"committer" => {
+                    // `Name <email> <seconds> <tz>`: the timestamp is the
+                    // second field from the end; names may hold spaces.
+                    let value = std::str::from_utf8(value).ok()?;
+                    let mut fields = value.rsplit(' ');
+                    let _tz = fields.next()?;
+                    committer_time = fields.next()?.parse().ok();
+                }
+                _ => {}
+            }
+        }
+        Some(Self {
+            tree: tree?,
+            parents,
+            committer_time: committer_time?,
+        })
+    }
+}
+
+fn split_once(bytes: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let at = bytes.iter().position(|&b| b == sep)?;
+    Some((&bytes[..at], &bytes[at + 1..]))
+}
+
+/// An open repository: the git directory, its shared (common) directory
+/// for linked work trees, the object stores, and the work tree if any.
+#[derive(Debug)]
+pub(crate) struct Repository {
+    git_dir: PathBuf,
+    common_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    object_dirs: Vec<PathBuf>,
+    packs: Vec<pack::Pack>,
+    shallow: HashSet<ObjectId>,
+}
+
+impl Repository {
+    /// Open the repository at `path`: a work tree holding `.git` (a
+    /// directory, or a `gitdir:` file for linked work trees and
+    /// submodules), or a bare repository.
+    pub(crate) fn open(path: &Path) -> NixUriResult<Self> {
+        let dot_git = path.join(".git");
+        let (git_dir, work_tree) = if dot_git.is_dir() {
+            (dot_git, Some(path.to_path_buf()))
+        } else if dot_git.is_file() {
+            let contents = read_to_string(&dot_git)?;
+            let target = contents
+                .trim_end()
+                .strip_prefix("gitdir: ")
+                .ok_or_else(|| git_error(&dot_git, "expected a `gitdir:` line"))?;
+            (path.join(target), Some(path.to_path_buf()))
+        } else if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+            (path.to_path_buf(), None)
+        } else {
+            return Err(git_error(path, "not a git repository"));
+        };
+
+        let common_dir = match read_optional(&git_dir.join("commondir"))? {
+            Some(dir) => git_dir.join(dir.trim_end()),
+            None => git_dir.clone(),
+        };
+        if let Some(config) = read_optional(&common_dir.join("config"))? {
+            let sha256 = config.lines().any(|line| {
+                let line = line.trim().to_ascii_lowercase();
+                line.starts_with("objectformat")
+                    && line.split('=').nth(1).map(str::trim) == Some("sha256")
+            });
+            if sha256 {
+                return Err(git_error(
+                    &common_dir,
+                    "SHA-256 repositories are not supported",
+                ));
+            }
+        }
+
+        let objects = common_dir.join("objects");
+        let mut object_dirs = vec![objects.clone()];
+        if let Some(alternates) = read_optional(&objects.join("info/alternates"))? {
+            object_dirs.extend(
+                alternates
+                    .lines()
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| objects.join(line)),
+            );
+        }
+        let mut packs = Vec::new();
+        for dir in &object_dirs {
+            packs.extend(pack::Pack::open_all(&dir.join("pack"))?);
+        }
+
+        let shallow = read_optional(&common_dir.join("shallow"))?
+            .unwrap_or_default()
+            .lines()
+            .filter_map(ObjectId::from_hex)
+            .collect();
+
+        Ok(Self {
+            git_dir,
+            common_dir,
+            work_tree,
+            object_dirs,
+            packs,
+            shallow,
+        })
+    }
+
+    /// Read and inflate the object `id`.
+    pub(crate) fn read_object(&self, id: ObjectId) -> NixUriResult<(ObjectKind, Vec<u8>)> {
+        self.find_object(id)?
+            .ok_or_else(|| git_error(&self.common_dir, format!("object {id} not found")))
+    }
+
+    fn find_object(&self, id: ObjectId) -> NixUriResult<Option<(ObjectKind, Vec<u8>)>> {
+        let hex = id.to_hex();
+        for dir in &self.object_dirs {
+            let path = dir.join(&hex[..2]).join(&hex[2..]);
+            let Some(compressed) = read_optional_bytes(&path)? else {
+                continue;
+            };
+            let mut raw = Vec::new();
+            ZlibDecoder::new(compressed.as_slice())
+                .read_to_end(&mut raw)
+                .map_err(|source| NixUriError::Io {
+                    path: path.clone(),
+                    source,
+                })?;
+            let corrupt = || git_error(&path, "corrupt loose object");
+            let (header, data) = split_once(&raw, 0).ok_or_else(corrupt)?;
+            let (kind, size) = split_once(header, b' ').ok_or_else(corrupt)?;
+            let kind = ObjectKind::from_name(kind).ok_or_else(corrupt)?;
+            if std::str::from_utf8(size).ok().and_then(|s| s.parse().ok()) != Some(data.len()) {
+                return Err(corrupt());
+            }
+            return Ok(Some((kind, data.to_vec())));
+        }
+        for pack in &self.packs {
+            if let Some(object) = pack.read(id)? {
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read `id` as a commit, peeling annotated tags.
+    pub(crate) fn commit(&self, mut id: ObjectId) -> NixUriResult<(ObjectId, Commit)> {
+        // Tags may point at tags; git caps nothing here, a small bound keeps
+        // a corrupt cycle from looping.
+        for _ in 0..16 {
+            let (kind, data) = self.read_object(id)?;
+            match kind {
+                ObjectKind::Commit => {
+                    let commit = Commit::parse(&data).ok_or_else(|| {
+                        git_error(&self.common_dir, format!("corrupt commit {id}"))
+                    })?;
+                    return Ok((id, commit));
+                }
+                ObjectKind::Tag => {
+                    id = data
+                        .strip_prefix(b"object ")
+                        .and_then(|rest| std::str::from_utf8(rest.get(..40)?).ok())
+                        .and_then(ObjectId::from_hex)
+                        .ok_or_else(|| git_error(&self.common_dir, format!("corrupt tag {id}")))?;
+                }
+                ObjectKind::Tree | ObjectKind::Blob => {
+                    return Err(git_error(
+                        &self.common_dir,
+                        format!("{id} is a {}, not a commit", kind.as_str()),
+                    ));
+                }
+            }
+        }
+        Err(git_error(
+            &self.common_dir,
+            format!("tag chain at {id} too deep"),
+        ))
+    }
+
+    /// Number of commits reachable from `id`, itself included, as
+    /// `git rev-list --count` prints it. `None` when the walk hits the
+    /// boundary of a shallow clone, where the true count is unknown.
+    pub(crate) fn rev_count(&self, id: ObjectId) -> NixUriResult<Option<u64>> {
+        let mut seen = HashSet::from([id]);
+        let mut pending = vec![id];
+        while let Some(next) = pending.pop() {
+            if self.shallow.contains(&next) {
+                return Ok(None);
+            }
+            let (_, commit) = self.commit(next)?;
+            for parent in commit.parents {
+                if seen.insert(parent) {
+                    pending.push(parent);
+                }
+            }
+        }
+        Ok(Some(seen.len() as u64))
+    }
+
+    /// The single object whose name starts with the lowercase hex
+    /// `prefix`. Errors when none or several match.
+    pub(crate) fn resolve_prefix(&self, prefix: &str) -> NixUriResult<ObjectId> {
+        let mut found = HashSet::new();
+        for dir in &self.object_dirs {
+            let fan = dir.join(&prefix[..2]);
+            let Ok(entries) = std::fs::read_dir(&fan) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                if let Some(id) = ObjectId::from_hex(&format!("{}{name}", &prefix[..2])) {
+                    if id.has_hex_prefix(prefix) {
+                        found.insert(id);
+                    }
+                }
+            }
+        }
+        for pack in &self.packs {
+            found.extend(pack.ids_with_prefix(prefix));
+        }
+        let mut found = found.into_iter();
+        match (found.next(), found.next()) {
+            (Some(id), None) => Ok(id),
+            (None, _) => Err(git_error(
+                &self.common_dir,
+                format!("no object matches `{prefix}`"),
+            )),
+            (Some(_), Some(_)) => Err(git_error(
+                &self.common_dir,
+                format!("abbreviated rev `{prefix}` is ambiguous"),
+            )),
+        }
+    }
+
+    /// Resolve `HEAD`.
+    pub(crate) fn head(&self) -> NixUriResult<ResolvedRef> {
+        self.resolve_ref("HEAD")?
+            .ok_or_else(|| git_error(&self.git_dir, "HEAD does not point at a commit"))
+    }
+
+    /// Resolve a user-supplied ref name the way `git rev-parse` does:
+    /// as written, then under `refs/`, `refs/tags/`, `refs/heads/` and
+    /// `refs/remotes/`.
+    pub(crate) fn resolve_named(&self, name: &str) -> NixUriResult<ResolvedRef> {
+        for candidate in [
+            name.to_string(),
+            format!("refs/{name}"),
+            format!("refs/tags/{name}"),
+            format!("refs/heads/{name}"),
+            format!("refs/remotes/{name}"),
+            format!("refs/remotes/{name}/HEAD"),
+        ] {
+            if let Some(resolved) = self.resolve_ref(&candidate)? {
+                return Ok(resolved);
+            }
+        }
+        Err(git_error(
+            &self.common_dir,
+            format!("ref `{name}` not found"),
+        ))
+    }
+
+    /// `true` when the index or the work tree differs from the tree of
+    /// commit `head`: a tracked file was modified, deleted or changed
+    /// type, a change was staged, or a merge conflict is unresolved.
+    /// Untracked files do not count, matching what Nix copies from a git
+    /// work tree. Bare repositories are never dirty.
+    pub(crate) fn is_dirty(&self, head: ObjectId) -> NixUriResult<bool> {
+        let Some(work_tree) = &self.work_tree else {
+            return Ok(false);
+        };
+        let index_path = self.git_dir.join("index");
+        let Some(index) = index::Index::read(&index_path)? else {
+            // No index yet: dirty only if HEAD has content to lose.
+            return Ok(!self.flat_tree(self.commit(head)?.1.tree)?.is_empty());
+        };
+
+        if index
+            .entries
+            .iter()
+            .any(|e| e.stage != 0 || e.intent_to_add)
+        {
+            return Ok(true);
+        }
+        let tree = self.flat_tree(self.commit(head)?.1.tree)?;
+        if tree.len() != index.entries.len()
+            || index
+                .entries
+                .iter()
+                .any(|e| tree.get(&e.path) != Some(&(e.mode, e.id)))
+        {
+            return Ok(true);
+        }
+        for entry in &index.entries {
+            if entry.skip_worktree || entry.mode == index::MODE_GITLINK {
+                continue;
+            }
+            if index.worktree_differs(work_tree, entry)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Every blob and submodule under `tree`, keyed by `/`-joined path.
+    fn flat_tree(&self, tree: ObjectId) -> NixUriResult<BTreeMap<Vec<u8>, (u32, ObjectId)>> {
+        let mut flat = BTreeMap::new();
+        let mut pending = vec![(Vec::new(), tree)];
+        while let Some((prefix, id)) = pending.pop() {
+            let (kind, data) = self.read_object(id)?;
+            if kind != ObjectKind::Tree {
+                return Err(git_error(&self.common_dir, format!("{id} is not a tree")));
+            }
+            let corrupt = || git_error(&self.common_dir, format!("corrupt tree {id}"));
+            let mut rest = data.as_slice();
+            while !rest.is_empty() {
+                let (mode, tail) = split_once(rest, b' ').ok_or_else(corrupt)?;
+                let (name, tail) = split_once(tail, 0).ok_or_else(corrupt)?;
+                let child = ObjectId::from_bytes(tail).ok_or_else(corrupt)?;
+                rest = &tail[20..];
+                let mode = std::str::from_utf8(mode)
+                    .ok()
+                    .and_then(|m| u32::from_str_radix(m, 8).ok())
+                    .ok_or_else(corrupt)?;
+                let mut path = prefix.clone();
+                if !path.is_empty() {
+                    path.push(b'/');
+                }
+                path.extend_from_slice(name);
+                if mode == 0o040000 {
+                    pending.push((path, child));
+                } else {
+                    flat.insert(path, (mode, child));
+                }
+            }
+        }
+        Ok(flat)
+    }
+}
+
+fn git_error(path: &Path, reason: impl Into<String>) -> NixUriError {
+    NixUriError::Git {
+        path: path.to_path_buf(),
+        reason: reason.into(),
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> NixUriError {
+    let path = path.to_path_buf();
+    move |source| NixUriError::Io { path, source }
+}
+
+fn read_to_string(path: &Path) -> NixUriResult<String> {
+    std::fs::read_to_string(path).map_err(io_error(path))
+}
+
+/// Read `path`, treating a missing file as `None`.
+fn read_optional(path: &Path) -> NixUriResult<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(NixUriError::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+fn read_optional_bytes(path: &Path) -> NixUriResult<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(NixUriError::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+/// Builders for on-disk fixture repositories, shared by the git tests.
+#[cfg(test)]
+pub(crate) mod fixture {
+    use std::{
+        collections::BTreeMap,
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    use flate2::{Compression, write::ZlibEncoder};
+
+    use super::{ObjectId, ObjectKind, hash_object};
+    use crate::test_support::Scratch;
+
+    /// A work tree with a `.git` directory under the system temp dir,
+    /// removed on drop. `HEAD` starts out pointing at `refs/heads/main`.
+    #[derive(Debug)]
+    pub(crate) struct TestRepo(Scratch);
+
+    impl TestRepo {
+        pub(crate) fn new(name: &str) -> Self {
+            let scratch = Scratch::new(&format!("git-{name}"));
+            scratch.mkdir(".git/objects");
+            scratch.mkdir(".git/refs/heads");
+            scratch.write(".git/HEAD", "ref: refs/heads/main\n");
+            Self(scratch)
+        }
+
+        pub(crate) fn git_dir(&self) -> PathBuf {
+            self.0.join(".git")
+        }
+
+        /// Store `data` as a loose object.
+        pub(crate) fn write_object(&self, kind: ObjectKind, data: &[u8]) -> ObjectId {
+            let id = hash_object(kind, data);
+            let hex = id.to_hex();
+            let dir = self.git_dir().join("objects").join(&hex[..2]);
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(format!("{} {}\0", kind.as_str(), data.len()).as_bytes())
+                .unwrap();
+            encoder.write_all(data).unwrap();
+            std::fs::write(dir.join(&hex[2..]), encoder.finish().unwrap()).unwrap();
+            id
+        }
+
+        /// Write `files` into the work tree, store them as blobs and trees,
+        /// commit them on top of `parents` at `time`, and point the index
+        /// at the new tree. Returns the commit id; refs are left alone.
+        pub(crate) fn commit(
+            &self,
+            files: &[(&str, &str)],
+            parents: &[ObjectId],
+            time: u64,
+        ) -> ObjectId {
+            let mut blobs = BTreeMap::new();
+            for (path, content) in files {
+                let file = self.0.join(path);
+                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                std::fs::write(&file, content).unwrap();
+                let id = self.write_object(ObjectKind::Blob, content.as_bytes());
+                blobs.insert(path.to_string(), (id, content.len()));
+            }
+            let tree = self.write_tree(&blobs, "");
+            let mut body = format!("tree {tree}\n");
+            for parent in parents {
+                body.push_str(&format!("parent {parent}\n"));
+            }
+            body.push_str(&format!(
+                "author A U Thor <a@example.com> {time} +0000\n\
+                 committer A U Thor <a@example.com> {time} +0000\n\nmessage {time}\n"
+            ));
+            self.write_index(&blobs);
+            self.write_object(ObjectKind::Commit, body.as_bytes())
+        }
+
+        fn write_tree(
+            &self,
+            blobs: &BTreeMap<String, (ObjectId, usize)>,
+            prefix: &str,
+        ) -> ObjectId {
+            // Git orders tree entries by name, with directories compared
+            // as if they ended in `/`.
+            let mut entries: BTreeMap<String, (&str, ObjectId)> = BTreeMap::new();
+            for path in blobs.keys() {
+                let Some(rest) = path.strip_prefix(prefix) else {
+                    continue;
+                };
+                match rest.split_once('/') {
+                    None => {
+                        entries.insert(rest.to_string(), ("100644", blobs[path].0));
+                    }
+                    Some((dir, _)) => {
+                        let key = format!("{dir}/");
+                        if let std::collections::btree_map::Entry::Vacant(slot) = entries.entry(key)
+                        {
+                            let id = self.write_tree(blobs, &format!("{prefix}{dir}/"));
+                            slot.insert(("40000", id));
+                        }
+                    }
+                }
+            }
+            let mut data = Vec::new();
+            for (name, (mode, id)) in entries {
+                data.extend_from_slice(
+                    format!("{mode} {}\0", name.trim_end_matches('/')).as_bytes(),
+                );
+                data.extend_from_slice(&id.0);
+            }
+            self.write_object(ObjectKind::Tree, &data)
+        }
+
+        /// Write a version 2 index listing `blobs` with zeroed stat data,
+        /// so every entry is verified by content hash.
+        pub(crate) fn write_index(&self, blobs: &BTreeMap<String, (ObjectId, usize)>) {
+            let mut data = b"DIRC".to_vec();
+            data.extend_from_slice(&2u32.to_be_bytes());
+            data.extend_from_slice(&u32::try_from(blobs.len()).unwrap().to_be_bytes());
+            for (path, (id, size)) in blobs {
+                let start = data.len();
+                data.extend_from_slice(&[0; 24]);
+                data.extend_from_slice(&0o100644u32.to_be_bytes());
+                data.extend_from_slice(&[0; 8]);
+                data.extend_from_slice(&u32::try_from(*size).unwrap().to_be_bytes());
+                data.extend_from_slice(&id.0);
+                data.extend_from_slice(&u16::try_from(path.len()).unwrap().to_be_bytes());
+                data.extend_from_slice(path.as_bytes());
+                let padding = 8 - (data.len() - start) % 8;
+                data.extend(std::iter::repeat_n(0, padding));
+            }
+            let checksum = sha1_smol::Sha1::from(&data).digest().bytes();
+            data.extend_from_slice(&checksum);
+            std::fs::write(self.git_dir().join("index"), data).unwrap();
+        }
+
+        /// Point the loose ref `name` at `id`.
+        pub(crate) fn set_ref(&self, name: &str, id: ObjectId) {
+            let path = self.git_dir().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("{id}\n")).unwrap();
+        }
+
+        pub(crate) fn path(&self, rel: &str) -> PathBuf {
+            self.0.join(rel)
+        }
+
+        pub(crate) fn root(&self) -> &Path {
+            self.0.path()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::{fixture::TestRepo, *};
+
+    #[test]
+    fn object_id_hex_round_trip() {
+        let hex = "b2df4e4e80e04cbb33a350f87717f4bd6140d298";
+        assert_eq!(ObjectId::from_hex(hex).unwrap().to_hex(), hex);
+        assert!(ObjectId::from_hex("b2df").is_none());
+        assert!(ObjectId::from_hex(&"g".repeat(40)).is_none());
+    }
+
+    #[test]
+    fn hashes_like_git() {
+        // `printf hello | git hash-object --stdin`
+        assert_eq!(
+            hash_object(ObjectKind::Blob, b"hello").to_hex(),
+            "b6fc4c620b67d95f953a5c1c1230aaab5db5a1b0"
+        );
+    }
+
+    #[test]
+    fn parses_commit_with_spaces_in_name() {
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        let data = format!(
+            "tree {tree}\nauthor Jane Q Doe <j@x> 5 +0000\ncommitter Jane Q Doe <j@x> 1700000000 -0130\n\nmsg\n"
+        );
+        let commit = Commit::parse(data.as_bytes()).unwrap();
+        assert_eq!(commit.committer_time, 1_700_000_000);
+        assert!(commit.parents.is_empty());
+    }
+
+    #[test]
+    fn head_and_rev_count_with_merge() {
+        let repo = TestRepo::new("history");
+        let root = repo.commit(&[("a", "1")], &[], 100);
+        let left = repo.commit(&[("a", "2")], &[root], 200);
+        let right = repo.commit(&[("a", "3")], &[root], 300);
+        let merge = repo.commit(&[("a", "4")], &[left, right], 400);
+        repo.set_ref("refs/heads/main", merge);
+
+        let git = Repository::open(repo.root()).unwrap();
+        let head = git.head().unwrap();
+        assert_eq!(head.id, merge);
+        assert_eq!(head.name.as_deref(), Some("refs/heads/main"));
+        assert_eq!(git.rev_count(merge).unwrap(), Some(4));
+        assert_eq!(git.rev_count(left).unwrap(), Some(2));
+        assert_eq!(git.commit(merge).unwrap().1.committer_time, 400);
+    }
+
+    #[test]
+    fn shallow_boundary_has_no_rev_count() {
+        let repo = TestRepo::new("shallow");
+        let root = repo.commit(&[("a", "1")], &[], 100);
+        let tip = repo.commit(&[("a", "2")], &[root], 200);
+        std::fs::write(repo.git_dir().join("shallow"), format!("{tip}\n")).unwrap();
+        let git = Repository::open(repo.root()).unwrap();
+        assert_eq!(git.rev_count(tip).unwrap(), None);
+    }
+
+    #[test]
+    fn annotated_tags_peel_to_commits() {
+        let repo = TestRepo::new("tags");
+        let commit = repo.commit(&[("a", "1")], &[], 100);
+        let tag = repo.write_object(
+            ObjectKind::Tag,
+            format!("object {commit}\ntype commit\ntag v1\ntagger T <t@x> 1 +0000\n\nv1\n")
+                .as_bytes(),
+        );
+        repo.set_ref("refs/tags/v1", tag);
+        let git = Repository::open(repo.root()).unwrap();
+        let resolved = git.resolve_named("v1").unwrap();
+        assert_eq!(resolved.name.as_deref(), Some("refs/tags/v1"));
+        assert_eq!(git.commit(resolved.id).unwrap().0, commit);
+    }
+
+    #[test]
+    fn unknown_ref_and_missing_head() {
+        let repo = TestRepo::new("empty");
+        let git = Repository::open(repo.root()).unwrap();
+        assert_matches!(git.head(), Err(NixUriError::Git { .. }));
+        assert_matches!(git.resolve_named("nope"), Err(NixUriError::Git { .. }));
+    }
+
+    #[test]
+    fn abbreviated_prefix_lookup() {
+        let repo = TestRepo::new("prefix");
+        let commit = repo.commit(&[("a", "1")], &[], 100);
+        let git = Repository::open(repo.root()).unwrap();
+        assert_eq!(git.resolve_prefix(&commit.to_hex()[..7]).unwrap(), commit);
+        assert_matches!(git.resolve_prefix("0000000"), Err(NixUriError::Git { .. }));
+    }
+
+    #[test]
+    fn rejects_sha256_repositories() {
+        let repo = TestRepo::new("sha256");
+        std::fs::write(
+            repo.git_dir().join("config"),
+            "[extensions]\n\tobjectFormat = sha256\n",
+        )
+        .unwrap();
+        assert_matches!(
+            Repository::open(repo.root()),
+            Err(NixUriError::Git { reason, .. }) => assert!(reason.contains("SHA-256"))
+        );
+    }
+
+    #[test]
+    fn gitdir_file_points_at_repository() {
+        let repo = TestRepo::new("gitdir-file");
+        let commit = repo.commit(&[("a", "1")], &[], 100);
+        repo.set_ref("refs/heads/main", commit);
+        let linked = repo.path("linked");
+        std::fs::create_dir_all(&linked).unwrap();
+        std::fs::write(linked.join(".git"), "gitdir: ../.git\n").unwrap();
+        assert_eq!(
+            Repository::open(&linked).unwrap().head().unwrap().id,
+            commit
+        );
+    }
+
+    #[test]
+    fn clean_work_tree_is_not_dirty() {
+        let repo = TestRepo::new("clean");
+        let commit = repo.commit(&[("a", "1"), ("dir/b", "2")], &[], 100);
+        // Untracked files do not make a tree dirty.
+        std::fs::write(repo.path("untracked"), "x").unwrap();
+        let git = Repository::open(repo.root()).unwrap();
+        assert!(!git.is_dirty(commit).unwrap());
+    }
+
+    #[test]
+    fn modified_same_size_file_is_dirty() {
+        let repo = TestRepo::new("modified");
+        let commit = repo.commit(&[("a", "1"), ("dir/b", "2")], &[], 100);
+        std::fs::write(repo.path("dir/b"), "3").unwrap();
+        let git = Repository::open(repo.root()).unwrap();
+        assert!(git.is_dirty(commit).unwrap());
+    }
+
+    #[test]
+    fn deleted_file_is_dirty() {
+        let repo = TestRepo::new("deleted");
+        let commit = repo.commit(&[("a", "1"), ("b", "2")], &[], 100);
+        std::fs::remove_file(repo.path("b")).unwrap();
+        let git = Repository::open(repo.root()).unwrap();
+        assert!(git.is_dirty(commit).unwrap());
+    }
+
+    #[test]
+    fn staged_change_is_dirty() {
+        let repo = TestRepo::new("staged");
+        let first = repo.commit(&[("a", "1")], &[], 100);
+        // Committing again rewrites the index to the new tree; checking
+        // against the first commit then sees a staged addition.
+        let _second = repo.commit(&[("a", "1"), ("b", "2")], &[first], 200);
+        let git = Repository::open(repo.root()).unwrap();
+        assert!(git.is_dirty(first).unwrap());
+    }
+}
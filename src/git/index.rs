@@ -0,0 +1,291 @@
+//! The staging area (`.git/index`), versions 2 to 4.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{ObjectId, ObjectKind, git_error, hash_object, io_error, read_optional_bytes};
+use crate::error::{NixUriError, NixUriResult};
+
+/// Mode of a submodule entry.
+pub(crate) const MODE_GITLINK: u32 = 0o160000;
+const MODE_SYMLINK: u32 = 0o120000;
+const MODE_REGULAR: u32 = 0o100000;
+const MODE_TYPE_MASK: u32 = 0o170000;
+
+/// Stat data, object id and flags through the 16-bit flags word.
+const ENTRY_FIXED_LEN: usize = 62;
+
+/// One tracked path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    pub(crate) path: Vec<u8>,
+    pub(crate) mode: u32,
+    pub(crate) id: ObjectId,
+    /// File size, truncated to 32 bits as git records it.
+    size: u32,
+    mtime: (u32, u32),
+    /// Merge stage; non-zero while a conflict is unresolved.
+    pub(crate) stage: u8,
+    /// Excluded from the work tree by a sparse checkout.
+    pub(crate) skip_worktree: bool,
+    /// Added with `git add -N`: tracked, but with no staged content.
+    pub(crate) intent_to_add: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct Index {
+    pub(crate) entries: Vec<IndexEntry>,
+    /// When the index file was written. Entries modified at or after this
+    /// instant are "racily clean": their stat data cannot be trusted.
+    written: (u64, u32),
+}
+
+impl Index {
+    /// Read the index at `path`; `Ok(None)` when there is none.
+    pub(crate) fn read(path: &Path) -> NixUriResult<Option<Self>> {
+        let Some(data) = read_optional_bytes(path)? else {
+            return Ok(None);
+        };
+        let written = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(io_error(path))?;
+        let entries =
+            parse(&data).ok_or_else(|| git_error(path, "corrupt or unsupported index"))?;
+        Ok(Some(Self {
+            entries,
+            written: timestamp(written),
+        }))
+    }
+
+    /// `true` when the work tree copy of `entry` no longer matches it.
+    /// Stat data short-circuits unchanged files; anything else is hashed.
+    pub(crate) fn worktree_differs(&self, root: &Path, entry: &IndexEntry) -> NixUriResult<bool> {
+        let path = work_path(root, &entry.path);
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+                ) =>
+            {
+                return Ok(true);
+            }
+            Err(source) => return Err(NixUriError::Io { path, source }),
+        };
+        match entry.mode & MODE_TYPE_MASK {
+            MODE_SYMLINK => {
+                if !metadata.file_type().is_symlink() {
+                    return Ok(true);
+                }
+                let target = std::fs::read_link(&path).map_err(io_error(&path))?;
+                Ok(hash_object(ObjectKind::Blob, &link_bytes(&target)) != entry.id)
+            }
+            MODE_REGULAR => {
+                if !metadata.is_file() || metadata.len() % (1 << 32) != u64::from(entry.size) {
+                    return Ok(true);
+                }
+                if executable_changed(&metadata, entry.mode) {
+                    return Ok(true);
+                }
+                let modified = metadata.modified().map_err(io_error(&path))?;
+                let mtime = timestamp(modified);
+                let recorded = (u64::from(entry.mtime.0), entry.mtime.1);
+                if mtime == recorded && recorded < self.written {
+                    return Ok(false);
+                }
+                let content = std::fs::read(&path).map_err(io_error(&path))?;
+                Ok(hash_object(ObjectKind::Blob, &content) != entry.id)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+fn parse(data: &[u8]) -> Option<Vec<IndexEntry>> {
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be_u32(data, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = be_u32(data, 8)? as usize;
+    let mut entries = Vec::with_capacity(count.min(data.len() / ENTRY_FIXED_LEN));
+    let mut pos = 12;
+    let mut previous: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let start = pos;
+        let mtime = (be_u32(data, pos + 8)?, be_u32(data, pos + 12)?);
+        let mode = be_u32(data, pos + 24)?;
+        let size = be_u32(data, pos + 36)?;
+        let id = ObjectId::from_bytes(data.get(pos + 40..pos + 60)?)?;
+        let flags = be_u16(data, pos + 60)?;
+        pos += ENTRY_FIXED_LEN;
+        let mut extended = 0;
+        if flags & 0x4000 != 0 {
+            if version < 3 {
+                return None;
+            }
+            extended = be_u16(data, pos)?;
+            pos += 2;
+        }
+
+        let path = if version == 4 {
+            // Prefix compression: drop `strip` bytes from the previous
+            // path, then append the NUL-terminated suffix.
+            let strip = offset_varint(data, &mut pos)?;
+            let keep = previous.len().checked_sub(strip)?;
+            let suffix_len = data.get(pos..)?.iter().position(|&b| b == 0)?;
+            let mut path = previous[..keep].to_vec();
+            path.extend_from_slice(&data[pos..pos + suffix_len]);
+            pos += suffix_len + 1;
+            path
+        } else {
+            let len = data.get(pos..)?.iter().position(|&b| b == 0)?;
+            let path = data[pos..pos + len].to_vec();
+            // Entries are NUL-padded to a multiple of eight bytes, with
+            // at least one NUL.
+            let entry_len = pos + len - start;
+            pos = start + (entry_len + 8) / 8 * 8;
+            path
+        };
+        previous.clone_from(&path);
+
+        entries.push(IndexEntry {
+            path,
+            mode,
+            id,
+            size,
+            mtime,
+            stage: ((flags >> 12) & 0x3) as u8,
+            skip_worktree: extended & 0x4000 != 0,
+            intent_to_add: extended & 0x2000 != 0,
+        });
+    }
+    Some(entries)
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+/// Git's offset encoding: big-endian base-128, +1 per continuation byte.
+fn offset_varint(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut byte = *data.get(*pos)?;
+    *pos += 1;
+    let mut value = usize::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = *data.get(*pos)?;
+        *pos += 1;
+        value = (value.checked_add(1)?.checked_mul(128)?) | usize::from(byte & 0x7f);
+    }
+    Some(value)
+}
+
+fn timestamp(time: SystemTime) -> (u64, u32) {
+    time.duration_since(UNIX_EPOCH)
+        .map_or((0, 0), |d| (d.as_secs(), d.subsec_nanos()))
+}
+
+#[cfg(unix)]
+fn work_path(root: &Path, path: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    root.join(std::ffi::OsStr::from_bytes(path))
+}
+
+#[cfg(not(unix))]
+fn work_path(root: &Path, path: &[u8]) -> PathBuf {
+    root.join(String::from_utf8_lossy(path).as_ref())
+}
+
+#[cfg(unix)]
+fn link_bytes(target: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    target.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn link_bytes(target: &Path) -> Vec<u8> {
+    target.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+#[cfg(unix)]
+fn executable_changed(metadata: &std::fs::Metadata, mode: u32) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    (metadata.permissions().mode() & 0o100 != 0) != (mode & 0o100 != 0)
+}
+
+#[cfg(not(unix))]
+fn executable_changed(_metadata: &std::fs::Metadata, _mode: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry_bytes(mode: u32, flags: u16, extended: Option<u16>) -> Vec<u8> {
+        let mut data = vec![0; 24];
+        data.extend_from_slice(&mode.to_be_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&[0xab; 20]);
+        data.extend_from_slice(&flags.to_be_bytes());
+        if let Some(extended) = extended {
+            data.extend_from_slice(&extended.to_be_bytes());
+        }
+        data
+    }
+
+    fn header(version: u32, count: u32) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&count.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn parses_version_four_prefix_compression() {
+        let mut data = header(4, 2);
+        data.extend(entry_bytes(0o100644, 9, None));
+        data.extend_from_slice(b"\x00dir/alpha\0");
+        data.extend(entry_bytes(0o100755, 8, None));
+        // Drop "alpha" (5 bytes), append "beta".
+        data.extend_from_slice(b"\x05beta\0");
+        let entries = parse(&data).unwrap();
+        assert_eq!(entries[0].path, b"dir/alpha");
+        assert_eq!(entries[1].path, b"dir/beta");
+        assert_eq!(entries[1].mode, 0o100755);
+    }
+
+    #[test]
+    fn reads_stage_and_extended_flags() {
+        let mut data = header(3, 2);
+        let mut first = entry_bytes(0o100644, 0x2000 | 1, None);
+        first.extend_from_slice(b"a\0");
+        first.resize(first.len().div_ceil(8) * 8, 0);
+        data.extend(first);
+        let mut second = entry_bytes(0o100644, 0x4000 | 1, Some(0x4000));
+        second.extend_from_slice(b"b\0");
+        second.resize(second.len().div_ceil(8) * 8, 0);
+        data.extend(second);
+
+        let entries = parse(&data).unwrap();
+        assert_eq!(entries[0].stage, 2);
+        assert!(!entries[0].skip_worktree);
+        assert_eq!(entries[1].stage, 0);
+        assert!(entries[1].skip_worktree);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(parse(&header(5, 0)).is_none());
+        assert!(parse(b"NOPE").is_none());
+    }
+}
@@ -0,0 +1,563 @@
+//! Packfile access through version 2 pack indexes.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::ZlibDecoder;
+
+use super::{ObjectId, ObjectKind, git_error, io_error};
+use crate::error::{NixUriError, NixUriResult};
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+/// Magic, version and the 256-entry fan-out table.
+const IDX_HEADER_LEN: usize = 8 + 256 * 4;
+/// Deltas chains are bounded by `pack.depth` (50 by default); anything
+/// far beyond that is a corrupt or hostile pack.
+const MAX_DELTA_CHAIN: usize = 10_000;
+/// Upper bound on buffers sized from headers in the pack; larger objects
+/// grow as they are actually inflated.
+const MAX_PREALLOC: usize = 1 << 20;
+
+/// An opened `pack-*.idx` / `pack-*.pack` pair.
+#[derive(Debug)]
+pub(crate) struct Pack {
+    pack_path: PathBuf,
+    index: Vec<u8>,
+    count: usize,
+    file: File,
+}
+
+/// How a pack entry stores its object.
+enum Stored {
+    Whole(ObjectKind),
+    /// A delta against the entry at this pack offset.
+    OffsetDelta(u64),
+    /// A delta against the named object.
+    RefDelta(ObjectId),
+}
+
+impl Pack {
+    /// Every pack with an index in `dir` (`objects/pack`). A missing
+    /// directory holds no packs.
+    pub(crate) fn open_all(dir: &Path) -> NixUriResult<Vec<Self>> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => {
+                return Err(NixUriError::Io {
+                    path: dir.to_path_buf(),
+                    source,
+                });
+            }
+        };
+        let mut idx_paths: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+            .collect();
+        idx_paths.sort();
+        idx_paths
+            .iter()
+            .filter(|idx| idx.with_extension("pack").is_file())
+            .map(|idx| Self::open(idx))
+            .collect()
+    }
+
+    fn open(idx_path: &Path) -> NixUriResult<Self> {
+        let index = std::fs::read(idx_path).map_err(io_error(idx_path))?;
+        if index.len() < IDX_HEADER_LEN
+            || index[..4] != IDX_MAGIC
+            || index[4..8] != 2u32.to_be_bytes()
+        {
+            return Err(git_error(
+                idx_path,
+                "unsupported pack index (expected version 2)",
+            ));
+        }
+        let pack_path = idx_path.with_extension("pack");
+        let file = File::open(&pack_path).map_err(io_error(&pack_path))?;
+        let mut pack = Self {
+            pack_path,
+            index,
+            count: 0,
+            file,
+        };
+        pack.count = pack.fanout(255);
+        // Every bucket bound is read unchecked from here on, so the table
+        // must never decrease (which also keeps it within `count`).
+        if (1..256).any(|byte| pack.fanout(byte - 1) > pack.fanout(byte)) {
+            return Err(git_error(idx_path, "corrupt pack index fan-out table"));
+        }
+        // Names, CRCs and 4-byte offsets, then the two trailing checksums.
+        let needed = pack
+            .count
+            .checked_mul(28)
+            .and_then(|n| n.checked_add(IDX_HEADER_LEN + 40));
+        if needed.is_none_or(|needed| pack.index.len() < needed) {
+            return Err(git_error(idx_path, "truncated pack index"));
+        }
+        Ok(pack)
+    }
+
+    /// Number of objects whose first name byte is at most `byte`.
+    fn fanout(&self, byte: usize) -> usize {
+        let at = 8 + byte * 4;
+        be_u32(&self.index[at..at + 4]) as usize
+    }
+
+    fn name(&self, i: usize) -> Option<&[u8]> {
+        let at = IDX_HEADER_LEN + i * 20;
+        self.index.get(at..at + 20)
+    }
+
+    fn offset(&self, i: usize) -> NixUriResult<u64> {
+        let table = IDX_HEADER_LEN + self.count * 24;
+        let small = self
+            .index
+            .get(table + i * 4..table + i * 4 + 4)
+            .map(be_u32)
+            .ok_or_else(|| git_error(&self.pack_path, "corrupt pack index offset"))?;
+        if small & 0x8000_0000 == 0 {
+            return Ok(u64::from(small));
+        }
+        // The high bit selects an entry in the 8-byte table for packs
+        // over 2 GiB.
+        let at = table + self.count * 4 + (small & 0x7fff_ffff) as usize * 8;
+        self.index
+            .get(at..at + 8)
+            .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap_or_default()))
+            .ok_or_else(|| git_error(&self.pack_path, "corrupt large offset"))
+    }
+
+    /// The fan-out bucket holding names that start with `first`.
+    fn bucket(&self, first: u8) -> std::ops::Range<usize> {
+        let start = match first {
+            0 => 0,
+            n => self.fanout(usize::from(n) - 1),
+        };
+        start..self.fanout(usize::from(first))
+    }
+
+    /// Binary search the sorted name table within `id`'s bucket.
+    fn position(&self, id: ObjectId) -> Option<usize> {
+        let std::ops::Range { mut start, mut end } = self.bucket(id.0[0]);
+        while start < end {
+            let mid = start + (end - start) / 2;
+            match self.name(mid)?.cmp(id.0.as_slice()) {
+                std::cmp::Ordering::Less => start = mid + 1,
+                std::cmp::Ordering::Greater => end = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Names in this pack starting with the lowercase hex `prefix` (at
+    /// least two digits).
+    pub(crate) fn ids_with_prefix(&self, prefix: &str) -> Vec<ObjectId> {
+        let Ok(first) = u8::from_str_radix(&prefix[..2], 16) else {
+            return Vec::new();
+        };
+        self.bucket(first)
+            .filter_map(|i| ObjectId::from_bytes(self.name(i)?))
+            .filter(|id| id.has_hex_prefix(prefix))
+            .collect()
+    }
+
+    /// Read `id` from this pack, resolving delta chains. `Ok(None)` when
+    /// the pack does not hold it.
+    pub(crate) fn read(&self, id: ObjectId) -> NixUriResult<Option<(ObjectKind, Vec<u8>)>> {
+        let Some(position) = self.position(id) else {
+            return Ok(None);
+        };
+        let mut offset = self.offset(position)?;
+        let mut deltas = Vec::new();
+        let (kind, mut data) = loop {
+            if deltas.len() > MAX_DELTA_CHAIN {
+                return Err(git_error(&self.pack_path, "delta chain too long"));
+            }
+            let (stored, data) = self.entry(offset)?;
+            match stored {
+                Stored::Whole(kind) => break (kind, data),
+                Stored::OffsetDelta(base) => offset = base,
+                Stored::RefDelta(base) => {
+                    offset = self
+                        .position(base)
+                        .map(|i| self.offset(i))
+                        .transpose()?
+                        .ok_or_else(|| {
+                            git_error(&self.pack_path, format!("delta base {base} not in pack"))
+                        })?;
+                }
+            }
+            deltas.push(data);
+        };
+        for delta in deltas.iter().rev() {
+            data = apply_delta(&data, delta)
+                .ok_or_else(|| git_error(&self.pack_path, format!("corrupt delta for {id}")))?;
+        }
+        Ok(Some((kind, data)))
+    }
+
+    /// Decode the entry header at `offset` and inflate its payload.
+    fn entry(&self, offset: u64) -> NixUriResult<(Stored, Vec<u8>)> {
+        let io = io_error(&self.pack_path);
+        let corrupt = || git_error(&self.pack_path, format!("corrupt pack entry at {offset}"));
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset)).map_err(io)?;
+        let mut reader = BufReader::new(file);
+        let mut next = || -> NixUriResult<u8> {
+            let mut byte = [0];
+            reader
+                .read_exact(&mut byte)
+                .map_err(io_error(&self.pack_path))?;
+            Ok(byte[0])
+        };
+
+        let mut byte = next()?;
+        let type_bits = (byte >> 4) & 7;
+        let mut size = u64::from(byte & 0x0f);
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = next()?;
+            if shift > 57 {
+                return Err(corrupt());
+            }
+            size |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+        }
+        let stored = match type_bits {
+            1 => Stored::Whole(ObjectKind::Commit),
+            2 => Stored::Whole(ObjectKind::Tree),
+            3 => Stored::Whole(ObjectKind::Blob),
+            4 => Stored::Whole(ObjectKind::Tag),
+            6 => {
+                // Big-endian base-128 with an implicit +1 per
+                // continuation byte, so every distance has one spelling.
+                let mut byte = next()?;
+                let mut distance = u64::from(byte & 0x7f);
+                while byte & 0x80 != 0 {
+                    byte = next()?;
+                    distance = distance
+                        .checked_add(1)
+                        .and_then(|d| d.checked_mul(128))
+                        .ok_or_else(corrupt)?
+                        | u64::from(byte & 0x7f);
+                }
+                Stored::OffsetDelta(offset.checked_sub(distance).ok_or_else(corrupt)?)
+            }
+            7 => {
+                let mut name = [0; 20];
+                for byte in &mut name {
+                    *byte = next()?;
+                }
+                Stored::RefDelta(ObjectId(name))
+            }
+            _ => return Err(corrupt()),
+        };
+
+        let size = usize::try_from(size).map_err(|_| corrupt())?;
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOC));
+        ZlibDecoder::new(reader)
+            .take(size as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(io_error(&self.pack_path))?;
+        if data.len() != size {
+            return Err(corrupt());
+        }
+        Ok((stored, data))
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap_or_default())
+}
+
+/// Rebuild an object from its delta base: a header with both sizes, then
+/// copy-from-base and insert-literal instructions. `None` on any
+/// inconsistency.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = delta_size(delta, &mut pos)?;
+    let result_size = delta_size(delta, &mut pos)?;
+    if base_size != base.len() {
+        return None;
+    }
+    let mut result = Vec::with_capacity(result_size.min(MAX_PREALLOC));
+    while let Some(&op) = delta.get(pos) {
+        pos += 1;
+        if op & 0x80 != 0 {
+            let mut start = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    start |= usize::from(*delta.get(pos)?) << (8 * i);
+                    pos += 1;
+                }
+            }
+            let mut len = 0usize;
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    len |= usize::from(*delta.get(pos)?) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if len == 0 {
+                len = 0x10000;
+            }
+            result.extend_from_slice(base.get(start..start.checked_add(len)?)?);
+        } else if op != 0 {
+            let len = usize::from(op);
+            result.extend_from_slice(delta.get(pos..pos + len)?);
+            pos += len;
+        } else {
+            return None;
+        }
+    }
+    (result.len() == result_size).then_some(result)
+}
+
+/// Little-endian base-128 size from a delta header.
+fn delta_size(delta: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*pos)?;
+        *pos += 1;
+        size |= usize::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(size);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::Repository, super::fixture::TestRepo, super::hash_object, *};
+
+    /// Assembles a pack and its version 2 index.
+    #[derive(Default)]
+    struct PackWriter {
+        pack: Vec<u8>,
+        objects: Vec<(ObjectId, u64)>,
+    }
+
+    impl PackWriter {
+        fn entry(&mut self, id: ObjectId, type_bits: u8, extra: &[u8], payload: &[u8]) -> u64 {
+            use std::io::Write;
+
+            let offset = self.pack.len() as u64;
+            let mut size = payload.len();
+            let mut byte = (type_bits << 4) | u8::try_from(size & 0x0f).unwrap();
+            size >>= 4;
+            while size > 0 {
+                self.pack.push(byte | 0x80);
+                byte = u8::try_from(size & 0x7f).unwrap();
+                size >>= 7;
+            }
+            self.pack.push(byte);
+            self.pack.extend_from_slice(extra);
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(payload).unwrap();
+            self.pack.extend(encoder.finish().unwrap());
+            self.objects.push((id, offset));
+            offset
+        }
+
+        fn whole(&mut self, kind: ObjectKind, data: &[u8]) -> (ObjectId, u64) {
+            let id = hash_object(kind, data);
+            let type_bits = match kind {
+                ObjectKind::Commit => 1,
+                ObjectKind::Tree => 2,
+                ObjectKind::Blob => 3,
+                ObjectKind::Tag => 4,
+            };
+            (id, self.entry(id, type_bits, &[], data))
+        }
+
+        fn offset_delta(&mut self, id: ObjectId, base: u64, delta: &[u8]) {
+            let mut distance = self.pack.len() as u64 - base;
+            let mut encoded = vec![(distance & 0x7f) as u8];
+            distance >>= 7;
+            while distance > 0 {
+                distance -= 1;
+                encoded.push(0x80 | (distance & 0x7f) as u8);
+                distance >>= 7;
+            }
+            encoded.reverse();
+            self.entry(id, 6, &encoded, delta);
+        }
+
+        fn ref_delta(&mut self, id: ObjectId, base: ObjectId, delta: &[u8]) {
+            self.entry(id, 7, &base.0, delta);
+        }
+
+        fn finish(mut self, dir: &Path) {
+            let mut pack = b"PACK".to_vec();
+            pack.extend_from_slice(&2u32.to_be_bytes());
+            pack.extend_from_slice(&u32::try_from(self.objects.len()).unwrap().to_be_bytes());
+            let header_len = pack.len() as u64;
+            pack.append(&mut self.pack);
+            let pack_sum = sha1_smol::Sha1::from(&pack).digest().bytes();
+            pack.extend_from_slice(&pack_sum);
+
+            self.objects.sort();
+            let mut idx = IDX_MAGIC.to_vec();
+            idx.extend_from_slice(&2u32.to_be_bytes());
+            for byte in 0..=255u8 {
+                let n = self
+                    .objects
+                    .iter()
+                    .filter(|(id, _)| id.0[0] <= byte)
+                    .count();
+                idx.extend_from_slice(&u32::try_from(n).unwrap().to_be_bytes());
+            }
+            for (id, _) in &self.objects {
+                idx.extend_from_slice(&id.0);
+            }
+            idx.extend(std::iter::repeat_n(0, self.objects.len() * 4));
+            for (_, offset) in &self.objects {
+                idx.extend_from_slice(&u32::try_from(offset + header_len).unwrap().to_be_bytes());
+            }
+            idx.extend_from_slice(&pack_sum);
+            let idx_sum = sha1_smol::Sha1::from(&idx).digest().bytes();
+            idx.extend_from_slice(&idx_sum);
+
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join("pack-test.pack"), pack).unwrap();
+            std::fs::write(dir.join("pack-test.idx"), idx).unwrap();
+        }
+    }
+
+    /// A delta that copies all of `base` and appends `tail`.
+    fn append_delta(base: &[u8], tail: &[u8]) -> Vec<u8> {
+        // Every length here is below 0x80, so each size is one byte.
+        let byte = |n: usize| u8::try_from(n).unwrap();
+        let mut delta = vec![byte(base.len()), byte(base.len() + tail.len())];
+        delta.extend_from_slice(&[0x80 | 0x01 | 0x10, 0, byte(base.len())]);
+        delta.push(byte(tail.len()));
+        delta.extend_from_slice(tail);
+        delta
+    }
+
+    #[test]
+    fn applies_copy_and_insert_instructions() {
+        let delta = append_delta(b"hello ", b"world");
+        assert_eq!(apply_delta(b"hello ", &delta).unwrap(), b"hello world");
+        // Base length mismatch and a zero opcode are both rejected.
+        assert!(apply_delta(b"hello", &delta).is_none());
+        assert!(apply_delta(b"hello ", &[6, 6, 0]).is_none());
+    }
+
+    #[test]
+    fn reads_whole_and_deltified_objects() {
+        let repo = TestRepo::new("pack");
+        let mut writer = PackWriter::default();
+        let base = b"hello world\n".as_slice();
+        let (base_id, base_offset) = writer.whole(ObjectKind::Blob, base);
+
+        let ofs_target = b"hello world\nmore\n";
+        let ofs_id = hash_object(ObjectKind::Blob, ofs_target);
+        writer.offset_delta(ofs_id, base_offset, &append_delta(base, b"more\n"));
+
+        let ref_target = b"hello world\nmore\nagain\n";
+        let ref_id = hash_object(ObjectKind::Blob, ref_target);
+        writer.ref_delta(ref_id, ofs_id, &append_delta(ofs_target, b"again\n"));
+
+        let mut tree = b"100644 f\0".to_vec();
+        tree.extend_from_slice(&base_id.0);
+        let (tree_id, _) = writer.whole(ObjectKind::Tree, &tree);
+        let commit = format!("tree {tree_id}\ncommitter C <c@x> 42 +0000\n\nm\n");
+        let (commit_id, _) = writer.whole(ObjectKind::Commit, commit.as_bytes());
+        writer.finish(&repo.git_dir().join("objects/pack"));
+
+        let git = Repository::open(repo.root()).unwrap();
+        assert_eq!(
+            git.read_object(base_id).unwrap(),
+            (ObjectKind::Blob, base.to_vec())
+        );
+        assert_eq!(git.read_object(ofs_id).unwrap().1, ofs_target);
+        assert_eq!(git.read_object(ref_id).unwrap().1, ref_target);
+        assert_eq!(git.commit(commit_id).unwrap().1.committer_time, 42);
+        assert_eq!(git.rev_count(commit_id).unwrap(), Some(1));
+        assert_eq!(git.resolve_prefix(&ref_id.to_hex()[..8]).unwrap(), ref_id);
+    }
+
+    #[test]
+    fn finds_every_name_in_a_shared_bucket() {
+        let repo = TestRepo::new("pack-bucket");
+        let dir = repo.git_dir().join("objects/pack");
+        let mut writer = PackWriter::default();
+        // Five names in the 0xab bucket, with neighbours on either side.
+        let ids: Vec<ObjectId> = [0xaa, 0xab, 0xab, 0xab, 0xab, 0xab, 0xac]
+            .into_iter()
+            .enumerate()
+            .map(|(i, first)| {
+                let mut name = [u8::try_from(i).unwrap(); 20];
+                name[0] = first;
+                ObjectId(name)
+            })
+            .collect();
+        for id in &ids {
+            writer.entry(*id, 3, &[], b"x");
+        }
+        writer.finish(&dir);
+        let pack = Pack::open(&dir.join("pack-test.idx")).unwrap();
+
+        assert_eq!(pack.bucket(0xab), 1..6);
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(pack.position(*id), Some(i), "{id}");
+        }
+        let mut missing = ids[3];
+        missing.0[19] = 0xff;
+        assert_eq!(pack.position(missing), None);
+    }
+
+    #[test]
+    fn rejects_corrupt_fanout_tables() {
+        let repo = TestRepo::new("pack-fanout");
+        let dir = repo.git_dir().join("objects/pack");
+        let mut writer = PackWriter::default();
+        writer.whole(ObjectKind::Blob, b"a");
+        writer.whole(ObjectKind::Blob, b"b");
+        writer.finish(&dir);
+        let idx_path = dir.join("pack-test.idx");
+        let idx = std::fs::read(&idx_path).unwrap();
+
+        // An early bucket claiming more names than the pack holds.
+        let mut corrupt = idx.clone();
+        corrupt[8..12].copy_from_slice(&1000u32.to_be_bytes());
+        std::fs::write(&idx_path, &corrupt).unwrap();
+        assert!(Repository::open(repo.root()).is_err());
+
+        // A bound that decreases.
+        let mut corrupt = idx;
+        corrupt[8 + 254 * 4..8 + 255 * 4].copy_from_slice(&2u32.to_be_bytes());
+        corrupt[8 + 100 * 4..8 + 101 * 4].copy_from_slice(&3u32.to_be_bytes());
+        std::fs::write(&idx_path, &corrupt).unwrap();
+        assert!(Repository::open(repo.root()).is_err());
+    }
+
+    #[test]
+    fn oversized_headers_do_not_preallocate() {
+        // A delta claiming a 2^60-byte result is rejected, not allocated.
+        let mut delta = vec![1];
+        delta.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+        delta.extend_from_slice(&[1, b'x']);
+        assert!(apply_delta(b"a", &delta).is_none());
+    }
+
+    #[test]
+    fn rejects_version_one_index() {
+        let repo = TestRepo::new("pack-v1");
+        let dir = repo.git_dir().join("objects/pack");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("pack-old.idx"), vec![0; IDX_HEADER_LEN + 40]).unwrap();
+        std::fs::write(dir.join("pack-old.pack"), b"PACK").unwrap();
+        assert!(Repository::open(repo.root()).is_err());
+    }
+}
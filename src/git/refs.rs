@@ -0,0 +1,171 @@
+//! Ref resolution: `HEAD`, loose refs and `packed-refs`.
+
+use std::path::PathBuf;
+
+use super::{ObjectId, Repository, git_error, read_optional};
+use crate::{error::NixUriResult, flakeref::check_ref_format};
+
+/// A ref resolved to the object it names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResolvedRef {
+    /// The fully qualified branch or tag the lookup ended on
+    /// (`refs/heads/main`); `None` for a detached `HEAD`.
+    pub(crate) name: Option<String>,
+    /// The object the ref points at; may be an annotated tag.
+    pub(crate) id: ObjectId,
+}
+
+/// One step of a ref lookup.
+enum RefValue {
+    Symbolic(String),
+    Direct(ObjectId),
+}
+
+/// Symbolic refs are followed this many times before giving up, as git
+/// does.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+impl Repository {
+    /// Follow `name` through symbolic refs to an object. `Ok(None)` when
+    /// the ref does not exist (or `HEAD` is unborn).
+    pub(crate) fn resolve_ref(&self, name: &str) -> NixUriResult<Option<ResolvedRef>> {
+        let mut current = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            match self.read_ref(&current)? {
+                None => return Ok(None),
+                Some(RefValue::Symbolic(target)) => current = target,
+                Some(RefValue::Direct(id)) => {
+                    let name = (current != "HEAD").then_some(current);
+                    return Ok(Some(ResolvedRef { name, id }));
+                }
+            }
+        }
+        Err(git_error(
+            &self.git_dir,
+            format!("symbolic ref `{name}` nests too deeply"),
+        ))
+    }
+
+    fn read_ref(&self, name: &str) -> NixUriResult<Option<RefValue>> {
+        // Ref names become paths; refuse anything git itself would not
+        // accept so `..` cannot escape the git directory. Outside `refs/`
+        // only all-caps pseudo refs (`HEAD`, `FETCH_HEAD`) exist, which
+        // keeps `config` or `index` from being read as refs.
+        let pseudo = !name.starts_with("refs/");
+        if check_ref_format(name).is_err()
+            || pseudo && !name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+        {
+            return Ok(None);
+        }
+        for path in self.loose_ref_paths(name) {
+            // `refs/heads` is a directory when asked for as a ref name.
+            if path.is_dir() {
+                continue;
+            }
+            let Some(contents) = read_optional(&path)? else {
+                continue;
+            };
+            let contents = contents.trim_end();
+            if let Some(target) = contents.strip_prefix("ref: ") {
+                return Ok(Some(RefValue::Symbolic(target.to_string())));
+            }
+            return ObjectId::from_hex(contents)
+                .map(|id| Some(RefValue::Direct(id)))
+                .ok_or_else(|| git_error(&path, "malformed ref"));
+        }
+        Ok(self.packed_ref(name)?.map(RefValue::Direct))
+    }
+
+    /// Where a loose `name` may live: pseudo refs such as `HEAD` are per
+    /// work tree, everything else is shared.
+    fn loose_ref_paths(&self, name: &str) -> Vec<PathBuf> {
+        if name.starts_with("refs/") {
+            vec![self.common_dir.join(name)]
+        } else {
+            vec![self.git_dir.join(name), self.common_dir.join(name)]
+        }
+    }
+
+    fn packed_ref(&self, name: &str) -> NixUriResult<Option<ObjectId>> {
+        let path = self.common_dir.join("packed-refs");
+        let Some(packed) = read_optional(&path)? else {
+            return Ok(None);
+        };
+        for line in packed.lines() {
+            // `#` opens the header, `^` lines carry the peeled target of
+            // the annotated tag above them.
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((hex, ref_name)) = line.split_once(' ') {
+                if ref_name == name {
+                    return ObjectId::from_hex(hex)
+                        .map(Some)
+                        .ok_or_else(|| git_error(&path, "malformed packed ref"));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixture::TestRepo;
+    use super::*;
+
+    #[test]
+    fn packed_refs_resolve_after_loose() {
+        let repo = TestRepo::new("packed");
+        let old = repo.commit(&[("a", "1")], &[], 100);
+        let new = repo.commit(&[("a", "2")], &[old], 200);
+        std::fs::write(
+            repo.git_dir().join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n{old} refs/heads/main\n{old} refs/tags/v1\n^{new}\n"
+            ),
+        )
+        .unwrap();
+        let git = Repository::open(repo.root()).unwrap();
+        assert_eq!(git.head().unwrap().id, old);
+        assert_eq!(git.resolve_named("v1").unwrap().id, old);
+
+        // A loose ref shadows its packed entry.
+        repo.set_ref("refs/heads/main", new);
+        assert_eq!(git.head().unwrap().id, new);
+    }
+
+    #[test]
+    fn detached_head_has_no_name() {
+        let repo = TestRepo::new("detached");
+        let commit = repo.commit(&[("a", "1")], &[], 100);
+        std::fs::write(repo.git_dir().join("HEAD"), format!("{commit}\n")).unwrap();
+        let head = Repository::open(repo.root()).unwrap().head().unwrap();
+        assert_eq!(
+            head,
+            ResolvedRef {
+                name: None,
+                id: commit
+            }
+        );
+    }
+
+    #[test]
+    fn refuses_non_ref_files() {
+        let repo = TestRepo::new("escape");
+        std::fs::write(repo.git_dir().join("config"), "[core]\n").unwrap();
+        let git = Repository::open(repo.root()).unwrap();
+        assert_eq!(git.resolve_ref("refs/../HEAD").unwrap(), None);
+        assert_eq!(git.resolve_ref("config").unwrap(), None);
+    }
+
+    #[test]
+    fn symref_cycle_is_an_error() {
+        let repo = TestRepo::new("cycle");
+        std::fs::write(repo.git_dir().join("HEAD"), "ref: refs/heads/a\n").unwrap();
+        std::fs::write(repo.git_dir().join("refs/heads/a"), "ref: refs/heads/b\n").unwrap();
+        std::fs::write(repo.git_dir().join("refs/heads/b"), "ref: refs/heads/a\n").unwrap();
+        let git = Repository::open(repo.root()).unwrap();
+        assert!(git.head().is_err());
+    }
+}
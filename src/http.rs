@@ -0,0 +1,345 @@
+//! The HTTP transport the network-backed resolvers go through.
+//!
+//! The crate ships no HTTP stack. Callers implement [`HttpClient`] over
+//! whatever client they already use (`ureq`, `reqwest`, a proxy-aware
+//! wrapper), and tests implement it over a loopback server replaying
+//! recorded responses.
+
+use crate::error::NixUriResult;
+
+/// A `GET` request: the URL and the headers to send with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HttpRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The first value of header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+/// A response as the transport received it. Non-2xx statuses are
+/// responses, not transport errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The first value of header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// `true` for a 2xx status.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Performs HTTP `GET`s. Redirects should be followed by the
+/// implementation; failures to connect or read surface as errors, while
+/// any status code the server sent comes back as an [`HttpResponse`].
+pub trait HttpClient {
+    fn get(&self, request: &HttpRequest) -> NixUriResult<HttpResponse>;
+}
+
+impl<C: HttpClient + ?Sized> HttpClient for &C {
+    fn get(&self, request: &HttpRequest) -> NixUriResult<HttpResponse> {
+        (**self).get(request)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// A stand-in server for the resolver tests: a loopback HTTP/1.1 server
+/// answering from recorded responses, and an [`HttpClient`] that sends it
+/// every request the way a forward proxy receives them (absolute-form
+/// target plus `Host`). Requests and responses cross a real socket, so
+/// status lines, headers and bodies go through the wire format.
+/// Unrecorded URLs get a 404, and every request the server parsed is
+/// logged.
+#[cfg(test)]
+pub(crate) mod recorded {
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread::JoinHandle,
+    };
+
+    use super::{HttpClient, HttpRequest, HttpResponse};
+    use crate::error::NixUriResult;
+
+    #[derive(Debug, Default)]
+    struct Shared {
+        responses: HashMap<String, HttpResponse>,
+        requests: Vec<HttpRequest>,
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct Recorded {
+        addr: SocketAddr,
+        shared: Arc<Mutex<Shared>>,
+        stop: Arc<AtomicBool>,
+        server: Option<JoinHandle<()>>,
+    }
+
+    impl Default for Recorded {
+        fn default() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let shared = Arc::new(Mutex::new(Shared::default()));
+            let stop = Arc::new(AtomicBool::new(false));
+            let server = std::thread::spawn({
+                let shared = Arc::clone(&shared);
+                let stop = Arc::clone(&stop);
+                move || {
+                    for stream in listener.incoming() {
+                        if stop.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        if let Ok(stream) = stream {
+                            // A client that hangs up early only fails its
+                            // own request.
+                            let _served = serve(&stream, &shared);
+                        }
+                    }
+                }
+            });
+            Self {
+                addr,
+                shared,
+                stop,
+                server: Some(server),
+            }
+        }
+    }
+
+    impl Recorded {
+        pub(crate) fn with(self, url: &str, response: HttpResponse) -> Self {
+            self.shared
+                .lock()
+                .unwrap()
+                .responses
+                .insert(url.to_string(), response);
+            self
+        }
+
+        pub(crate) fn json(self, url: &str, body: &str) -> Self {
+            self.with(url, HttpResponse::new(200, body))
+        }
+
+        /// The requests the server received, in order.
+        pub(crate) fn requests(&self) -> Vec<HttpRequest> {
+            self.shared.lock().unwrap().requests.clone()
+        }
+    }
+
+    impl HttpClient for Recorded {
+        fn get(&self, request: &HttpRequest) -> NixUriResult<HttpResponse> {
+            let authority = request
+                .url
+                .split_once("://")
+                .map_or(request.url.as_str(), |(_, rest)| rest);
+            let host = authority.split('/').next().unwrap_or_default();
+            let mut head = format!(
+                "GET {} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n",
+                request.url
+            );
+            for (name, value) in &request.headers {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+            head.push_str("\r\n");
+            let mut stream = TcpStream::connect(self.addr).unwrap();
+            stream.write_all(head.as_bytes()).unwrap();
+            let mut raw = Vec::new();
+            stream.read_to_end(&mut raw).unwrap();
+            Ok(parse_response(&raw))
+        }
+    }
+
+    impl Drop for Recorded {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+            // Wake the accept loop so it sees the flag.
+            let _wake = TcpStream::connect(self.addr);
+            if let Some(server) = self.server.take() {
+                let _joined = server.join();
+            }
+        }
+    }
+
+    /// Read one request from `stream` and answer it.
+    fn serve(stream: &TcpStream, shared: &Mutex<Shared>) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let target = line.split(' ').nth(1).unwrap_or_default().to_string();
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                break;
+            };
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+
+        let response = {
+            let mut shared = shared.lock().unwrap();
+            let response = shared
+                .responses
+                .get(&target)
+                .cloned()
+                .unwrap_or_else(|| HttpResponse::new(404, "not found"));
+            shared.requests.push(HttpRequest {
+                url: target,
+                headers,
+            });
+            response
+        };
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            response.status,
+            reason_phrase(response.status),
+            response.body.len()
+        );
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        let mut stream = stream;
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&response.body)
+    }
+
+    fn reason_phrase(status: u16) -> &'static str {
+        match status {
+            200 => "OK",
+            304 => "Not Modified",
+            403 => "Forbidden",
+            404 => "Not Found",
+            _ => "Status",
+        }
+    }
+
+    fn parse_response(raw: &[u8]) -> HttpResponse {
+        let split = raw
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        let head = std::str::from_utf8(&raw[..split]).unwrap();
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|status_line| status_line.split(' ').nth(1))
+            .and_then(|status| status.parse().ok())
+            .unwrap();
+        let mut response = HttpResponse::new(status, &raw[split + 4..]);
+        for line in lines {
+            if let Some((name, value)) = line.split_once(':') {
+                response = response.with_header(name, value.trim());
+            }
+        }
+        if let Some(len) = response
+            .header("content-length")
+            .and_then(|len| len.parse().ok())
+        {
+            response.body.truncate(len);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpClient, HttpRequest, HttpResponse, recorded::Recorded};
+
+    #[test]
+    fn headers_match_case_insensitively() {
+        let request = HttpRequest::new("https://example.com").with_header("Authorization", "x");
+        assert_eq!(request.header("authorization"), Some("x"));
+        assert_eq!(request.header("accept"), None);
+    }
+
+    #[test]
+    fn recorded_replays_over_the_wire_and_logs() {
+        let http = Recorded::default()
+            .json("https://example.com/a?x=1", "{}")
+            .with(
+                "https://example.com/cached",
+                HttpResponse::new(304, "").with_header("ETag", "\"v1\""),
+            );
+        let by_ref = &http;
+        let hit = by_ref
+            .get(&HttpRequest::new("https://example.com/a?x=1").with_header("Accept", "a/b"))
+            .unwrap();
+        assert!(hit.is_success());
+        assert_eq!(hit.body, b"{}");
+        assert_eq!(hit.header("content-length"), Some("2"));
+
+        let cached = by_ref
+            .get(&HttpRequest::new("https://example.com/cached"))
+            .unwrap();
+        assert_eq!((cached.status, cached.body.len()), (304, 0));
+        assert_eq!(cached.header("etag"), Some("\"v1\""));
+
+        let miss = by_ref
+            .get(&HttpRequest::new("https://example.com/b"))
+            .unwrap();
+        assert_eq!(
+            (miss.status, miss.body.as_slice()),
+            (404, &b"not found"[..])
+        );
+
+        let requests = http.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].url, "https://example.com/a?x=1");
+        assert_eq!(requests[0].header("host"), Some("example.com"));
+        assert_eq!(requests[0].header("accept"), Some("a/b"));
+    }
+}
@@ -69,10 +69,38 @@
 
 mod error;
 mod flakeref;
+#[cfg(feature = "local-git")]
+mod git;
+mod http;
+mod lockfile;
 pub(crate) mod parser;
+mod policy;
+mod registry;
+#[cfg(test)]
+mod test_support;
+mod time;
 
 pub use error::{NixUriError, NixUriResult, ParseExpected, UnsupportedReason};
+#[cfg(feature = "forge-api")]
+pub use flakeref::ForgeResolver;
 pub use flakeref::{
-    FlakeRef, FlakeRefType, ForgeIdentity, GitForge, GitForgePlatform, LocationParameters, RefKind,
-    RefLocation, ResourceType, ResourceUrl, TransportLayer,
+    Attr, Attrs, Candidate, Channel, ChannelFamily, ChannelRelease, FlakeHubRef, FlakeHubVersion,
+    FlakeRef, FlakeRefType, ForgeIdentity, GitForge, GitForgePlatform, KeyType, LocationParameters,
+    LockedAttrs, MemoryResolver, NotAFlakeReason, ParseOptions, PublicKey, PullNamespace,
+    QualifiedRef, RefFormatViolation, RefKind, RefLocation, RefName, Resolver, ResourceType,
+    ResourceUrl, Rev, RevKind, SchemeHandler, TransportLayer, UpdatePolicy, UpdateProposal,
+    UpdateReason, check_ref_format, immutable_link, lock, normalize_dir,
+};
+#[cfg(feature = "local-git")]
+pub use flakeref::{LocalGitResolver, LocalGitState};
+pub use http::{HttpClient, HttpRequest, HttpResponse};
+pub use lockfile::{
+    Change, DEFAULT_MAX_AGE, DependencyPath, DuplicateGroup, DuplicateNode, EffectiveInput,
+    FollowsSuggestion, Hop, InputChange, InputPath, InputTarget, LockDiagnostic, LockDiff,
+    LockIssue, LockNode, Lockfile, NodeInput, NodeLabel, StaleInput, StaleReason, StalenessPolicy,
+};
+pub use policy::{BranchRule, Policy, SourceMatcher, Violation};
+pub use registry::{
+    DEFAULT_TARBALL_TTL, GLOBAL_REGISTRY_URL, Registry, RegistryEntry, RegistryLoader,
+    resolve_indirect,
 };
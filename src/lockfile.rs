@@ -0,0 +1,468 @@
+//! Typed `flake.lock` files.
+//!
+//! A lock file is a graph: `nodes` maps node keys to [`LockNode`]s, `root`
+//! names the node of the flake itself, and each node's `inputs` point at
+//! other nodes either directly (by key) or through a `follows` path from
+//! the root. [`Lockfile`] keeps that graph as written and exposes the
+//! node `locked` / `original` entries as [`FlakeRef`]s.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{Attrs, FlakeRef},
+};
+
+mod diff;
+pub use diff::{Change, InputChange, InputTarget, LockDiff};
+mod duplicates;
+pub use duplicates::{DuplicateGroup, DuplicateNode, FollowsSuggestion};
+mod follows;
+pub use follows::EffectiveInput;
+mod graph;
+pub use graph::NodeLabel;
+mod staleness;
+pub use staleness::{DEFAULT_MAX_AGE, StaleInput, StaleReason, StalenessPolicy};
+mod validate;
+pub use validate::{LockDiagnostic, LockIssue};
+mod versions;
+mod why_depends;
+pub use why_depends::{DependencyPath, Hop};
+
+/// A path of input names from the root node, as written in `follows`
+/// (`["home-manager", "nixpkgs"]` for `home-manager/nixpkgs`). The empty
+/// path is the root itself.
+pub type InputPath = Vec<String>;
+
+/// The lock file versions Nix reads. This crate also reads and writes
+/// the older version 4 (see [`Lockfile::to_version`]).
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<u64> = 5..=7;
+
+/// A parsed `flake.lock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Lockfile {
+    pub version: u64,
+    /// Key of the root node: the flake the lock file belongs to.
+    pub root: String,
+    pub nodes: BTreeMap<String, LockNode>,
+}
+
+/// One node of the lock graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LockNode {
+    /// The node's own inputs, by input name.
+    pub inputs: BTreeMap<String, NodeInput>,
+    /// The pinned source. Absent on the root node.
+    pub locked: Option<FlakeRef>,
+    /// The ref as the flake declared it. Absent on the root node.
+    pub original: Option<FlakeRef>,
+    /// `false` for `flake = false` inputs, which have no `flake.nix`.
+    pub flake: bool,
+    /// For relative `path:` inputs, the input path of the flake the path
+    /// is relative to.
+    pub parent: Option<InputPath>,
+}
+
+/// Where an input points.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NodeInput {
+    /// Directly at a node, by key.
+    Node(String),
+    /// At whatever the input path from the root resolves to
+    /// (`inputs.x.follows`).
+    Follows(InputPath),
+}
+
+/// The `locked` attributes version 5 writes under `info` instead.
+const INFO_ATTRS: &[&str] = &["lastModified", "narHash"];
+
+#[derive(Serialize, Deserialize)]
+struct RawLockfile {
+    nodes: BTreeMap<String, RawNode>,
+    root: String,
+    version: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawNode {
+    #[serde(default = "yes", skip_serializing_if = "is_yes")]
+    flake: bool,
+    /// Version 5 kept `lastModified` and `narHash` beside `locked`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    info: Option<Attrs>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    inputs: BTreeMap<String, NodeInput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locked: Option<Attrs>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    original: Option<Attrs>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<InputPath>,
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_yes(flake: &bool) -> bool {
+    *flake
+}
+
+impl LockNode {
+    /// A node with no inputs and nothing locked, like a fresh root.
+    pub fn new() -> Self {
+        Self {
+            inputs: BTreeMap::new(),
+            locked: None,
+            original: None,
+            flake: true,
+            parent: None,
+        }
+    }
+
+    pub fn with_input(mut self, name: impl Into<String>, input: NodeInput) -> Self {
+        self.inputs.insert(name.into(), input);
+        self
+    }
+
+    pub fn with_locked(mut self, locked: FlakeRef) -> Self {
+        self.locked = Some(locked);
+        self
+    }
+
+    pub fn with_original(mut self, original: FlakeRef) -> Self {
+        self.original = Some(original);
+        self
+    }
+}
+
+impl Default for LockNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lockfile {
+    /// Parse a `flake.lock` document (versions 4 to 7).
+    ///
+    /// Malformed JSON, an unsupported version, and `locked` / `original`
+    /// entries that are not valid flake refs surface
+    /// [`NixUriError::InvalidValue`] with field `lockfile`. The graph
+    /// itself is not checked here; dangling references show up when
+    /// inputs are resolved.
+    pub fn parse(json: &str) -> NixUriResult<Self> {
+        let value: Value = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        Self::from_value(value)
+    }
+
+    pub(crate) fn from_value(value: Value) -> NixUriResult<Self> {
+        if value.get("version").and_then(Value::as_u64) == Some(versions::LEGACY_VERSION) {
+            return versions::from_legacy(value);
+        }
+        let raw: RawLockfile = serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?;
+        if !SUPPORTED_VERSIONS.contains(&raw.version) {
+            return Err(invalid(format!(
+                "unsupported lock file version {}",
+                raw.version
+            )));
+        }
+        let nodes = raw
+            .nodes
+            .into_iter()
+            .map(|(key, node)| {
+                let flake_ref = |attrs: Option<Attrs>| {
+                    attrs
+                        .map(|attrs| FlakeRef::from_attrs(&attrs))
+                        .transpose()
+                        .map_err(|e| invalid(format!("node `{key}`: {e}")))
+                };
+                let locked = node.locked.map(|mut locked| {
+                    locked.extend(node.info.unwrap_or_default());
+                    locked
+                });
+                let node = LockNode {
+                    locked: flake_ref(locked)?,
+                    original: flake_ref(node.original)?,
+                    inputs: node.inputs,
+                    flake: node.flake,
+                    parent: node.parent,
+                };
+                Ok((key, node))
+            })
+            .collect::<NixUriResult<_>>()?;
+        Ok(Self {
+            version: raw.version,
+            root: raw.root,
+            nodes,
+        })
+    }
+
+    /// The lock file as a JSON value in the shape of its `version`,
+    /// `locked` and `original` in attribute form from version 5 on.
+    pub(crate) fn to_value(&self) -> Value {
+        if self.version == versions::LEGACY_VERSION {
+            return versions::to_legacy(self);
+        }
+        let raw = RawLockfile {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(key, node)| {
+                    let mut locked = node.locked.as_ref().map(FlakeRef::to_attrs);
+                    let info = match &mut locked {
+                        Some(locked) if self.version == 5 => Some(
+                            INFO_ATTRS
+                                .iter()
+                                .filter_map(|name| locked.remove_entry(*name))
+                                .collect(),
+                        ),
+                        _ => None,
+                    };
+                    let node = RawNode {
+                        flake: node.flake,
+                        info,
+                        inputs: node.inputs.clone(),
+                        locked,
+                        original: node.original.as_ref().map(FlakeRef::to_attrs),
+                        parent: node.parent.clone(),
+                    };
+                    (key.clone(), node)
+                })
+                .collect(),
+            root: self.root.clone(),
+            version: self.version,
+        };
+        serde_json::to_value(raw).unwrap_or_default()
+    }
+
+    /// Render the way Nix writes `flake.lock`: sorted keys, two-space
+    /// indentation and a trailing newline.
+    pub fn to_json(&self) -> String {
+        let mut out = serde_json::to_string_pretty(&self.to_value()).unwrap_or_default();
+        out.push('\n');
+        out
+    }
+
+    pub fn node(&self, key: &str) -> Option<&LockNode> {
+        self.nodes.get(key)
+    }
+
+    /// The root node. `None` only for a lock file whose `root` names a
+    /// missing node.
+    pub fn root_node(&self) -> Option<&LockNode> {
+        self.node(&self.root)
+    }
+}
+
+/// `a/b/c` for error messages and reports; Nix's own spelling.
+pub(crate) fn display_path(path: &[String]) -> String {
+    path.join("/")
+}
+
+fn invalid(reason: String) -> NixUriError {
+    NixUriError::InvalidValue {
+        field: "lockfile",
+        reason,
+    }
+}
+
+/// Lock files shared by the lock-graph tests.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::InputPath;
+
+    /// The input path `a/b/c`; `""` is the root.
+    pub(crate) fn path(p: &str) -> InputPath {
+        p.split('/')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// `root` -> `nixpkgs`, `home-manager` (following root's nixpkgs),
+    /// `utils` (with its own `systems`), and `darwin`, whose nixpkgs
+    /// follows `home-manager/nixpkgs`: a follows into a follows.
+    pub(crate) const NESTED: &str = r#"{
+  "nodes": {
+    "darwin": {
+      "inputs": {
+        "nixpkgs": [
+          "home-manager",
+          "nixpkgs"
+        ]
+      },
+      "locked": {
+        "lastModified": 1700000300,
+        "narHash": "sha256-darwin",
+        "owner": "LnL7",
+        "repo": "nix-darwin",
+        "rev": "3333333333333333333333333333333333333333",
+        "type": "github"
+      },
+      "original": {
+        "owner": "LnL7",
+        "repo": "nix-darwin",
+        "type": "github"
+      }
+    },
+    "home-manager": {
+      "inputs": {
+        "nixpkgs": [
+          "nixpkgs"
+        ]
+      },
+      "locked": {
+        "lastModified": 1700000200,
+        "narHash": "sha256-hm",
+        "owner": "nix-community",
+        "repo": "home-manager",
+        "rev": "2222222222222222222222222222222222222222",
+        "type": "github"
+      },
+      "original": {
+        "owner": "nix-community",
+        "repo": "home-manager",
+        "type": "github"
+      }
+    },
+    "nixpkgs": {
+      "locked": {
+        "lastModified": 1700000000,
+        "narHash": "sha256-nixpkgs",
+        "owner": "NixOS",
+        "repo": "nixpkgs",
+        "rev": "1111111111111111111111111111111111111111",
+        "type": "github"
+      },
+      "original": {
+        "owner": "NixOS",
+        "ref": "nixos-unstable",
+        "repo": "nixpkgs",
+        "type": "github"
+      }
+    },
+    "root": {
+      "inputs": {
+        "darwin": "darwin",
+        "home-manager": "home-manager",
+        "nixpkgs": "nixpkgs",
+        "utils": "utils"
+      }
+    },
+    "systems": {
+      "flake": false,
+      "locked": {
+        "lastModified": 1680000000,
+        "narHash": "sha256-systems",
+        "owner": "nix-systems",
+        "repo": "default",
+        "rev": "5555555555555555555555555555555555555555",
+        "type": "github"
+      },
+      "original": {
+        "owner": "nix-systems",
+        "repo": "default",
+        "type": "github"
+      }
+    },
+    "utils": {
+      "inputs": {
+        "systems": "systems"
+      },
+      "locked": {
+        "lastModified": 1690000000,
+        "narHash": "sha256-utils",
+        "owner": "numtide",
+        "repo": "flake-utils",
+        "rev": "4444444444444444444444444444444444444444",
+        "type": "github"
+      },
+      "original": {
+        "owner": "numtide",
+        "repo": "flake-utils",
+        "type": "github"
+      }
+    }
+  },
+  "root": "root",
+  "version": 7
+}
+"#;
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[test]
+    fn parses_nodes_and_inputs() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        assert_eq!(lock.version, 7);
+        assert_eq!(lock.nodes.len(), 6);
+        let root = lock.root_node().unwrap();
+        assert_eq!(root.inputs["nixpkgs"], NodeInput::Node("nixpkgs".into()));
+        assert!(root.locked.is_none());
+        let darwin = lock.node("darwin").unwrap();
+        assert_eq!(
+            darwin.inputs["nixpkgs"],
+            NodeInput::Follows(vec!["home-manager".into(), "nixpkgs".into()])
+        );
+        assert_eq!(
+            lock.node("nixpkgs")
+                .unwrap()
+                .original
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "github:NixOS/nixpkgs/nixos-unstable"
+        );
+        assert!(!lock.node("systems").unwrap().flake);
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        assert_eq!(lock.to_json(), fixtures::NESTED);
+    }
+
+    #[test]
+    fn version_5_info_merges_into_locked() {
+        let lock = Lockfile::parse(
+            r#"{"nodes":{"root":{"inputs":{"n":"n"}},"n":{
+                "info":{"lastModified":7,"narHash":"sha256-x"},
+                "locked":{"owner":"o","repo":"r","rev":"1111111111111111111111111111111111111111","type":"github"},
+                "original":{"owner":"o","repo":"r","type":"github"}}},
+              "root":"root","version":5}"#,
+        )
+        .unwrap();
+        let locked = lock.node("n").unwrap().locked.as_ref().unwrap();
+        assert_eq!(locked.params().nar_hash_value(), Some("sha256-x"));
+        assert!(locked.is_locked());
+    }
+
+    #[rstest]
+    #[case(r#"{"nodes":{},"root":"root","version":3}"#)]
+    #[case(r#"{"nodes":{},"root":"root","version":8}"#)]
+    #[case(r#"{"nodes":{"n":{"locked":{"type":"darcs"}}},"root":"root","version":7}"#)]
+    #[case(r#"{"nodes":{},"version":7}"#)]
+    #[case("[]")]
+    fn rejects(#[case] json: &str) {
+        assert_matches!(
+            Lockfile::parse(json),
+            Err(NixUriError::InvalidValue {
+                field: "lockfile",
+                ..
+            })
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! What changed between two lock files.
+//!
+//! Mirrors Nix's `LockFile::diff`: every input path of both graphs is
+//! compared by where it points (a locked ref, or a `follows` path), and
+//! the differences render in the report `nix flake update` prints.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    flakeref::FlakeRef,
+    lockfile::{InputPath, LockNode, Lockfile, NodeInput, display_path},
+    time::format_date,
+};
+
+/// The changes from one lock file to another, ordered by input path.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct LockDiff {
+    pub changes: Vec<InputChange>,
+}
+
+/// One input that differs between two lock files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InputChange {
+    /// Input path from the root (`["home-manager", "nixpkgs"]`).
+    pub path: InputPath,
+    pub change: Change,
+}
+
+/// How an input changed; each side is where the input points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Change {
+    Added(InputTarget),
+    Removed(InputTarget),
+    Updated { old: InputTarget, new: InputTarget },
+}
+
+/// Where an input points, as a diff compares it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InputTarget {
+    /// The `locked` ref of the node the input names.
+    Locked(FlakeRef),
+    /// The input path the input follows.
+    Follows(InputPath),
+}
+
+impl InputTarget {
+    /// The `lastModified` of a locked target, in seconds since the epoch.
+    pub fn last_modified(&self) -> Option<u64> {
+        match self {
+            Self::Locked(locked) => locked.params().last_modified_value()?.parse().ok(),
+            Self::Follows(_) => None,
+        }
+    }
+
+    fn same_as(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Locked(a), Self::Locked(b)) => a.to_attrs() == b.to_attrs(),
+            (Self::Follows(a), Self::Follows(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Locked(locked) => json!({
+                "locked": locked.to_canonical_string(),
+                "lastModified": self.last_modified(),
+                "date": self.last_modified().map(format_date),
+            }),
+            Self::Follows(path) => json!({ "follows": display_path(path) }),
+        }
+    }
+}
+
+impl Display for InputTarget {
+    /// `'github:NixOS/nixpkgs/<rev>?narHash=...' (2024-05-01)` or
+    /// `follows 'nixpkgs'`, as Nix describes an input.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Locked(locked) => {
+                write!(f, "'{}'", locked.to_canonical_string())?;
+                match self.last_modified() {
+                    Some(seconds) => write!(f, " ({})", format_date(seconds)),
+                    None => Ok(()),
+                }
+            }
+            Self::Follows(path) => write!(f, "follows '{}'", display_path(path)),
+        }
+    }
+}
+
+impl LockDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The diff as a JSON array, one object per change:
+    /// `{"input": "nixpkgs", "change": "updated", "old": {...}, "new": {...}}`.
+    /// A locked side carries `locked` (the canonical URL), `lastModified`
+    /// and `date`; a `follows` side carries `follows`.
+    pub fn to_json(&self) -> String {
+        let changes: Vec<Value> = self
+            .changes
+            .iter()
+            .map(|change| {
+                let input = display_path(&change.path);
+                match &change.change {
+                    Change::Added(new) => {
+                        json!({ "input": input, "change": "added", "new": new.to_json() })
+                    }
+                    Change::Removed(old) => {
+                        json!({ "input": input, "change": "removed", "old": old.to_json() })
+                    }
+                    Change::Updated { old, new } => json!({
+                        "input": input,
+                        "change": "updated",
+                        "old": old.to_json(),
+                        "new": new.to_json(),
+                    }),
+                }
+            })
+            .collect();
+        serde_json::to_string_pretty(&changes).unwrap_or_default()
+    }
+}
+
+impl Display for LockDiff {
+    /// The report `nix flake update` prints, without its colours.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            let input = display_path(&change.path);
+            match &change.change {
+                Change::Added(new) => writeln!(f, "• Added input '{input}':\n    {new}")?,
+                Change::Removed(_) => writeln!(f, "• Removed input '{input}'")?,
+                Change::Updated { old, new } => {
+                    writeln!(f, "• Updated input '{input}':\n    {old}\n  → {new}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Lockfile {
+    /// The changes from `self` to `newer`.
+    ///
+    /// Like Nix, each node's inputs are listed once, under the first path
+    /// that reaches it; inputs whose node is missing or has no `locked`
+    /// entry are left out (see [`Lockfile::validate`]).
+    pub fn diff(&self, newer: &Self) -> LockDiff {
+        let (mut old, new) = (self.all_inputs(), newer.all_inputs());
+        let mut changes = Vec::new();
+        for (path, new) in new {
+            let change = match old.remove(&path) {
+                None => Change::Added(new),
+                Some(old) if old.same_as(&new) => continue,
+                Some(old) => Change::Updated { old, new },
+            };
+            changes.push(InputChange { path, change });
+        }
+        changes.extend(old.into_iter().map(|(path, old)| InputChange {
+            path,
+            change: Change::Removed(old),
+        }));
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        LockDiff { changes }
+    }
+
+    /// Every input path and its target, each node expanded once (Nix's
+    /// `LockFile::getAllInputs`).
+    fn all_inputs(&self) -> BTreeMap<InputPath, InputTarget> {
+        fn collect<'a>(
+            lock: &'a Lockfile,
+            key: &'a str,
+            node: &'a LockNode,
+            prefix: &mut InputPath,
+            done: &mut BTreeSet<&'a str>,
+            out: &mut BTreeMap<InputPath, InputTarget>,
+        ) {
+            if !done.insert(key) {
+                return;
+            }
+            for (name, input) in &node.inputs {
+                prefix.push(name.clone());
+                match input {
+                    NodeInput::Node(key) => {
+                        if let Some(child) = lock.node(key) {
+                            if let Some(locked) = &child.locked {
+                                out.insert(prefix.clone(), InputTarget::Locked(locked.clone()));
+                            }
+                            collect(lock, key, child, prefix, done, out);
+                        }
+                    }
+                    NodeInput::Follows(target) => {
+                        out.insert(prefix.clone(), InputTarget::Follows(target.clone()));
+                    }
+                }
+                prefix.pop();
+            }
+        }
+
+        let mut out = BTreeMap::new();
+        if let Some(root) = self.root_node() {
+            collect(
+                self,
+                &self.root,
+                root,
+                &mut Vec::new(),
+                &mut BTreeSet::new(),
+                &mut out,
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockfile::fixtures;
+
+    fn updated() -> Lockfile {
+        let json = fixtures::NESTED
+            .replace(
+                "1111111111111111111111111111111111111111",
+                "6666666666666666666666666666666666666666",
+            )
+            .replace("1700000000", "1714521600")
+            .replace("sha256-nixpkgs", "sha256-nixpkgs2")
+            // darwin now follows the top-level nixpkgs directly.
+            .replace(
+                "\"home-manager\",\n          \"nixpkgs\"\n        ]",
+                "\"nixpkgs\"\n        ]",
+            )
+            // utils is dropped, agenix added.
+            .replace("\"utils\": \"utils\"", "\"agenix\": \"utils\"");
+        Lockfile::parse(&json).unwrap()
+    }
+
+    #[test]
+    fn identical_lock_files_do_not_differ() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        assert!(lock.diff(&lock).is_empty());
+        assert_eq!(lock.diff(&lock).to_string(), "");
+    }
+
+    #[test]
+    fn reports_like_nix_flake_update() {
+        let old = Lockfile::parse(fixtures::NESTED).unwrap();
+        let report = old.diff(&updated()).to_string();
+        assert_eq!(
+            report,
+            "\
+• Added input 'agenix':
+    'github:numtide/flake-utils/4444444444444444444444444444444444444444?narHash=sha256-utils' (2023-07-22)
+• Added input 'agenix/systems':
+    'github:nix-systems/default/5555555555555555555555555555555555555555?narHash=sha256-systems' (2023-03-28)
+• Updated input 'darwin/nixpkgs':
+    follows 'home-manager/nixpkgs'
+  → follows 'nixpkgs'
+• Updated input 'nixpkgs':
+    'github:NixOS/nixpkgs/1111111111111111111111111111111111111111?narHash=sha256-nixpkgs' (2023-11-14)
+  → 'github:NixOS/nixpkgs/6666666666666666666666666666666666666666?narHash=sha256-nixpkgs2' (2024-05-01)
+• Removed input 'utils'
+• Removed input 'utils/systems'
+"
+        );
+    }
+
+    #[test]
+    fn renders_json() {
+        let old = Lockfile::parse(fixtures::NESTED).unwrap();
+        let diff = old.diff(&updated());
+        let json: Value = serde_json::from_str(&diff.to_json()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 6);
+        assert_eq!(
+            json[3],
+            json!({
+                "input": "nixpkgs",
+                "change": "updated",
+                "old": {
+                    "locked": "github:NixOS/nixpkgs/1111111111111111111111111111111111111111?narHash=sha256-nixpkgs",
+                    "lastModified": 1_700_000_000,
+                    "date": "2023-11-14",
+                },
+                "new": {
+                    "locked": "github:NixOS/nixpkgs/6666666666666666666666666666666666666666?narHash=sha256-nixpkgs2",
+                    "lastModified": 1_714_521_600,
+                    "date": "2024-05-01",
+                },
+            })
+        );
+        assert_eq!(json[2]["new"], json!({ "follows": "nixpkgs" }));
+        assert_eq!(json[4]["change"], "removed");
+    }
+}
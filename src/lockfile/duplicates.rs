@@ -0,0 +1,395 @@
+//! Duplicate inputs in a lock graph and the `follows` that remove them.
+//!
+//! Two lock nodes are duplicates when their `locked` refs fetch the same
+//! source: the same forge repository (whether spelled `github:` or
+//! `git+https://github.com/...`) or the same normalised URL, and the same
+//! subflake `dir`. Large flakes routinely carry several nixpkgs this way,
+//! one per input that did not `follows` the top-level one.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::{
+    error::NixUriResult,
+    flakeref::{FlakeRef, FlakeRefType, ResourceType, TransportLayer, normalize_dir},
+    lockfile::{EffectiveInput, InputPath, Lockfile, display_path},
+};
+
+/// Lock nodes that fetch the same source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DuplicateGroup {
+    /// The shared source, normalised: `git:github.com/nixos/nixpkgs`.
+    pub source: String,
+    /// The nodes, in node-key order. Always at least two.
+    pub nodes: Vec<DuplicateNode>,
+}
+
+/// One node of a [`DuplicateGroup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DuplicateNode {
+    pub node: String,
+    pub locked: FlakeRef,
+    /// The input paths that declare the node (not those that `follows`
+    /// it), shortest first.
+    pub paths: Vec<InputPath>,
+}
+
+/// A `follows` declaration to add to the root `flake.nix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FollowsSuggestion {
+    /// The nested input to redirect (`["home-manager", "nixpkgs"]`).
+    pub input: InputPath,
+    /// The input path it should follow (`["nixpkgs"]`).
+    pub follows: InputPath,
+}
+
+impl Display for FollowsSuggestion {
+    /// Nix syntax: `inputs.home-manager.inputs.nixpkgs.follows = "nixpkgs";`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for name in &self.input {
+            write!(f, "inputs.{}.", nix_attr_name(name))?;
+        }
+        write!(f, "follows = \"{}\";", display_path(&self.follows))
+    }
+}
+
+impl Lockfile {
+    /// Groups of lock nodes whose `locked` refs fetch the same source,
+    /// ordered by source. Nodes without a `locked` ref are ignored.
+    /// Errors as [`Lockfile::effective_inputs`].
+    pub fn duplicate_inputs(&self) -> NixUriResult<Vec<DuplicateGroup>> {
+        let mut paths: BTreeMap<&str, Vec<InputPath>> = BTreeMap::new();
+        let tree = self.effective_inputs()?;
+        for input in tree.iter().flat_map(EffectiveInput::walk) {
+            if input.follows.is_none() {
+                paths
+                    .entry(&input.node)
+                    .or_default()
+                    .push(input.path.clone());
+            }
+        }
+
+        let mut groups: BTreeMap<String, Vec<DuplicateNode>> = BTreeMap::new();
+        for (key, node) in &self.nodes {
+            let Some(locked) = &node.locked else {
+                continue;
+            };
+            let mut node_paths = paths.remove(key.as_str()).unwrap_or_default();
+            node_paths.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+            groups
+                .entry(source_key(locked))
+                .or_default()
+                .push(DuplicateNode {
+                    node: key.clone(),
+                    locked: locked.clone(),
+                    paths: node_paths,
+                });
+        }
+        Ok(groups
+            .into_iter()
+            .filter(|(_, nodes)| nodes.len() > 1)
+            .map(|(source, nodes)| DuplicateGroup { source, nodes })
+            .collect())
+    }
+
+    /// The `follows` declarations that collapse every duplicate onto one
+    /// node, as few as possible.
+    ///
+    /// Each group's shortest declared path is the target and every other
+    /// nested declaration follows it. A second top-level input is only
+    /// redirected when its `original` matches the target's; two top-level
+    /// inputs tracking different refs are deliberate. Declarations inside
+    /// an input that is itself redirected are neither suggested nor used
+    /// as targets, since the redirect removes them.
+    pub fn suggest_follows(&self) -> NixUriResult<Vec<FollowsSuggestion>> {
+        let groups = self.duplicate_inputs()?;
+        let first = self.plan_follows(&groups, &[]);
+        let redirected: Vec<InputPath> = first.into_iter().map(|s| s.input).collect();
+        Ok(self.plan_follows(&groups, &redirected))
+    }
+
+    fn plan_follows(
+        &self,
+        groups: &[DuplicateGroup],
+        redirected: &[InputPath],
+    ) -> Vec<FollowsSuggestion> {
+        let inside_redirect = |path: &InputPath| {
+            redirected
+                .iter()
+                .any(|r| r.len() < path.len() && path.starts_with(r))
+        };
+        let mut suggestions = Vec::new();
+        for group in groups {
+            let mut declared: Vec<(&InputPath, Option<&FlakeRef>)> = group
+                .nodes
+                .iter()
+                .flat_map(|node| {
+                    let original = self.node(&node.node).and_then(|n| n.original.as_ref());
+                    node.paths.iter().map(move |path| (path, original))
+                })
+                .filter(|(path, _)| !inside_redirect(path))
+                .collect();
+            declared.sort_by(|a, b| a.0.len().cmp(&b.0.len()).then_with(|| a.0.cmp(b.0)));
+            let Some(((target, target_original), rest)) = declared.split_first() else {
+                continue;
+            };
+            for (path, original) in rest {
+                if path.len() == 1 && original != target_original {
+                    continue;
+                }
+                suggestions.push(FollowsSuggestion {
+                    input: (*path).clone(),
+                    follows: (*target).clone(),
+                });
+            }
+        }
+        suggestions.sort_by(|a, b| a.input.cmp(&b.input));
+        suggestions
+    }
+}
+
+/// Normalised source of a locked ref: forge refs and git URLs on the same
+/// host share `git:<host>/<path>`, other kinds keep their URL. A subflake
+/// `dir` is part of the source, as `?dir=<dir>`.
+fn source_key(flake_ref: &FlakeRef) -> String {
+    let source = match flake_ref.kind() {
+        FlakeRefType::GitForge(forge) => repo_key(
+            "git",
+            flake_ref.domain().unwrap_or_default(),
+            &format!("{}/{}", forge.owner, forge.repo),
+        ),
+        FlakeRefType::Resource(res) => match (&res.res_type, &res.transport_type) {
+            (ResourceType::Git | ResourceType::Mercurial, Some(transport))
+                if *transport != TransportLayer::File =>
+            {
+                let authority_end = res.location.find('/').unwrap_or(res.location.len());
+                let (authority, path) = res.location.split_at(authority_end);
+                let host = authority.rsplit('@').next().unwrap_or(authority);
+                let path = path.trim_matches('/');
+                let path = path.strip_suffix(".git").unwrap_or(path);
+                repo_key(&res.res_type.to_string(), host, path)
+            }
+            (res_type, transport) => match transport {
+                Some(transport) => format!("{res_type}:{transport}://{}", res.location),
+                None => format!("{res_type}:{}", res.location),
+            },
+        },
+        FlakeRefType::Path { path, .. } => format!("path:{path}"),
+        FlakeRefType::Indirect { id, .. } => format!("indirect:{id}"),
+    };
+    let dir = flake_ref
+        .params()
+        .dir_value()
+        .map(|dir| normalize_dir(dir).unwrap_or_else(|_| dir.to_string()))
+        .unwrap_or_default();
+    if dir.is_empty() {
+        source
+    } else {
+        format!("{source}?dir={dir}")
+    }
+}
+
+fn repo_key(kind: &str, host: &str, path: &str) -> String {
+    let host = host.to_ascii_lowercase();
+    // GitHub and GitLab resolve owner and repository names
+    // case-insensitively.
+    if matches!(host.as_str(), "github.com" | "gitlab.com") {
+        format!("{kind}:{host}/{}", path.to_ascii_lowercase())
+    } else {
+        format!("{kind}:{host}/{path}")
+    }
+}
+
+/// `name` as a Nix attribute name: bare when it is an identifier, quoted
+/// otherwise.
+fn nix_attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'));
+    if identifier {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Four nixpkgs (one spelled as a git URL, one a deliberate second
+    /// channel), two flake-utils and two nix-systems.
+    const CROWDED: &str = r#"{
+      "nodes": {
+        "root": { "inputs": {
+          "devshell": "devshell", "hm": "hm", "nixpkgs": "nixpkgs",
+          "nixpkgs-stable": "nixpkgs_2", "utils": "utils"
+        } },
+        "nixpkgs": {
+          "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "1111111111111111111111111111111111111111", "narHash": "sha256-a" },
+          "original": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "ref": "nixos-unstable" }
+        },
+        "nixpkgs_2": {
+          "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "2222222222222222222222222222222222222222", "narHash": "sha256-b" },
+          "original": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "ref": "nixos-24.05" }
+        },
+        "hm": {
+          "inputs": { "nixpkgs": "nixpkgs_3" },
+          "locked": { "type": "github", "owner": "nix-community", "repo": "home-manager", "rev": "3333333333333333333333333333333333333333", "narHash": "sha256-c" },
+          "original": { "type": "github", "owner": "nix-community", "repo": "home-manager" }
+        },
+        "nixpkgs_3": {
+          "locked": { "type": "github", "owner": "nixos", "repo": "nixpkgs", "rev": "4444444444444444444444444444444444444444", "narHash": "sha256-d" },
+          "original": { "type": "github", "owner": "nixos", "repo": "nixpkgs", "ref": "nixos-unstable" }
+        },
+        "devshell": {
+          "inputs": { "nixpkgs": "nixpkgs_4", "utils": "utils_2" },
+          "locked": { "type": "github", "owner": "numtide", "repo": "devshell", "rev": "5555555555555555555555555555555555555555", "narHash": "sha256-e" },
+          "original": { "type": "github", "owner": "numtide", "repo": "devshell" }
+        },
+        "nixpkgs_4": {
+          "locked": { "type": "git", "url": "https://github.com/NixOS/nixpkgs.git", "rev": "6666666666666666666666666666666666666666", "narHash": "sha256-f" },
+          "original": { "type": "git", "url": "https://github.com/NixOS/nixpkgs.git" }
+        },
+        "utils": {
+          "inputs": { "systems": "systems" },
+          "locked": { "type": "github", "owner": "numtide", "repo": "flake-utils", "rev": "7777777777777777777777777777777777777777", "narHash": "sha256-g" },
+          "original": { "type": "github", "owner": "numtide", "repo": "flake-utils" }
+        },
+        "utils_2": {
+          "inputs": { "systems": "systems_2" },
+          "locked": { "type": "github", "owner": "numtide", "repo": "flake-utils", "rev": "8888888888888888888888888888888888888888", "narHash": "sha256-h" },
+          "original": { "type": "github", "owner": "numtide", "repo": "flake-utils" }
+        },
+        "systems": {
+          "locked": { "type": "github", "owner": "nix-systems", "repo": "default", "rev": "9999999999999999999999999999999999999999", "narHash": "sha256-i" },
+          "original": { "type": "github", "owner": "nix-systems", "repo": "default" }
+        },
+        "systems_2": {
+          "locked": { "type": "github", "owner": "nix-systems", "repo": "default", "rev": "9999999999999999999999999999999999999999", "narHash": "sha256-i" },
+          "original": { "type": "github", "owner": "nix-systems", "repo": "default" }
+        }
+      },
+      "root": "root",
+      "version": 7
+    }"#;
+
+    #[test]
+    fn groups_same_source_across_spellings() {
+        let lock = Lockfile::parse(CROWDED).unwrap();
+        let groups = lock.duplicate_inputs().unwrap();
+        let summary: Vec<(&str, Vec<&str>)> = groups
+            .iter()
+            .map(|g| {
+                (
+                    g.source.as_str(),
+                    g.nodes.iter().map(|n| n.node.as_str()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "git:github.com/nix-systems/default",
+                    vec!["systems", "systems_2"]
+                ),
+                (
+                    "git:github.com/nixos/nixpkgs",
+                    vec!["nixpkgs", "nixpkgs_2", "nixpkgs_3", "nixpkgs_4"]
+                ),
+                (
+                    "git:github.com/numtide/flake-utils",
+                    vec!["utils", "utils_2"]
+                ),
+            ]
+        );
+        assert_eq!(groups[1].nodes[3].paths, [vec!["devshell", "nixpkgs"]]);
+    }
+
+    #[test]
+    fn suggests_minimal_follows() {
+        let lock = Lockfile::parse(CROWDED).unwrap();
+        let rendered: Vec<String> = lock
+            .suggest_follows()
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        // `nixpkgs-stable` tracks another channel on purpose, and
+        // `devshell/utils/systems` goes away with `devshell/utils`.
+        assert_eq!(
+            rendered,
+            [
+                r#"inputs.devshell.inputs.nixpkgs.follows = "nixpkgs";"#,
+                r#"inputs.devshell.inputs.utils.follows = "utils";"#,
+                r#"inputs.hm.inputs.nixpkgs.follows = "nixpkgs";"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn subflakes_are_not_duplicates_of_their_repository() {
+        let lock = Lockfile::parse(
+            r#"{"nodes":{
+              "root":{"inputs":{"lib":"lib","nixpkgs":"nixpkgs"}},
+              "lib":{
+                "locked":{"type":"github","owner":"NixOS","repo":"nixpkgs","dir":"lib","rev":"1111111111111111111111111111111111111111","narHash":"sha256-a"},
+                "original":{"type":"github","owner":"NixOS","repo":"nixpkgs","dir":"lib"}},
+              "nixpkgs":{
+                "locked":{"type":"github","owner":"NixOS","repo":"nixpkgs","rev":"1111111111111111111111111111111111111111","narHash":"sha256-b"},
+                "original":{"type":"github","owner":"NixOS","repo":"nixpkgs"}}
+            },"root":"root","version":7}"#,
+        )
+        .unwrap();
+        assert!(lock.duplicate_inputs().unwrap().is_empty());
+        assert!(lock.suggest_follows().unwrap().is_empty());
+    }
+
+    #[test]
+    fn followed_inputs_are_not_duplicates() {
+        let lock = Lockfile::parse(crate::lockfile::fixtures::NESTED).unwrap();
+        assert!(lock.duplicate_inputs().unwrap().is_empty());
+        assert!(lock.suggest_follows().unwrap().is_empty());
+    }
+
+    #[rstest]
+    #[case("github:NixOS/nixpkgs", "git:github.com/nixos/nixpkgs")]
+    #[case(
+        "git+ssh://git@github.com/NixOS/nixpkgs.git",
+        "git:github.com/nixos/nixpkgs"
+    )]
+    #[case("gitlab:g%2Fsub/r?host=Git.Example", "git:git.example/g/sub/r")]
+    #[case("git+https://git.example/g/sub/r/", "git:git.example/g/sub/r")]
+    #[case("sourcehut:~o/R", "git:git.sr.ht/~o/R")]
+    #[case("hg+https://hg.example/r", "hg:hg.example/r")]
+    #[case("git+file:///srv/r", "git:file:///srv/r")]
+    #[case("https://h/x.tar.gz", "tarball:https://h/x.tar.gz")]
+    #[case("path:/p", "path:/p")]
+    #[case(
+        "github:NixOS/nixpkgs?dir=./lib/",
+        "git:github.com/nixos/nixpkgs?dir=lib"
+    )]
+    #[case("github:NixOS/nixpkgs?dir=.", "git:github.com/nixos/nixpkgs")]
+    fn normalises_sources(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(source_key(&input.parse().unwrap()), expected);
+    }
+
+    #[rstest]
+    #[case(&["nixpkgs"], r#"inputs.nixpkgs.follows = "x";"#)]
+    #[case(&["a.b", "c"], r#"inputs."a.b".inputs.c.follows = "x";"#)]
+    #[case(&["1st"], r#"inputs."1st".follows = "x";"#)]
+    fn renders_nix_attribute_paths(#[case] input: &[&str], #[case] expected: &str) {
+        let suggestion = FollowsSuggestion {
+            input: input.iter().map(ToString::to_string).collect(),
+            follows: vec!["x".into()],
+        };
+        assert_eq!(suggestion.to_string(), expected);
+    }
+}
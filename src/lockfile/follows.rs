@@ -0,0 +1,305 @@
+//! `follows` resolution over the lock graph.
+//!
+//! An input either names its node directly or follows an input path from
+//! the root; that path may itself pass through inputs that follow
+//! something else. Resolution walks those hops the way Nix's
+//! `LockFile::findInput` does and reports where they lead.
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    lockfile::{InputPath, LockNode, Lockfile, NodeInput, display_path},
+};
+
+/// An input as the lock graph makes it effective: the node it ends up
+/// using after every `follows` hop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EffectiveInput {
+    /// The input name within its parent.
+    pub name: String,
+    /// Input path from the root (`["utils", "systems"]`).
+    pub path: InputPath,
+    /// Key of the node the input resolves to.
+    pub node: String,
+    /// The path the input was declared to follow, if it was.
+    pub follows: Option<InputPath>,
+    /// The node's own inputs. Empty for an input reached through
+    /// `follows`: its target's inputs are listed where the target is
+    /// declared.
+    pub inputs: Vec<EffectiveInput>,
+}
+
+impl EffectiveInput {
+    /// This input and every input below it, depth first.
+    pub fn walk(&self) -> Vec<&Self> {
+        let mut out = vec![self];
+        for input in &self.inputs {
+            out.extend(input.walk());
+        }
+        out
+    }
+}
+
+impl Lockfile {
+    /// The key of the node the input path `path` resolves to, following
+    /// every `follows` on the way. The empty path is the root.
+    ///
+    /// An undeclared input, a reference to a node that does not exist, or
+    /// a `follows` chain that loops back on itself surfaces
+    /// [`NixUriError::Lock`] naming the path that failed.
+    pub fn resolve_input(&self, path: &[String]) -> NixUriResult<&str> {
+        self.try_resolve(path).map_err(Unresolved::into_error)
+    }
+
+    /// The input tree of the root, with every `follows` resolved. Errors
+    /// as [`Lockfile::resolve_input`]; a node that (through direct
+    /// references) contains itself surfaces [`NixUriError::Lock`] too.
+    pub fn effective_inputs(&self) -> NixUriResult<Vec<EffectiveInput>> {
+        let root = self.existing_node(&self.root, &[])?;
+        let mut ancestors = vec![self.root.as_str()];
+        self.expand(root, &mut Vec::new(), &mut ancestors)
+    }
+
+    /// [`Lockfile::resolve_input`], with the failure kept typed.
+    pub(crate) fn try_resolve(&self, path: &[String]) -> Result<&str, Unresolved> {
+        self.resolve_from_root(path, &mut Vec::new())
+    }
+
+    fn resolve_from_root<'a>(
+        &'a self,
+        path: &[String],
+        following: &mut Vec<InputPath>,
+    ) -> Result<&'a str, Unresolved> {
+        let mut key = self.root.as_str();
+        for (depth, name) in path.iter().enumerate() {
+            let here = &path[..=depth];
+            let node = self.node(key).ok_or_else(|| Unresolved::MissingNode {
+                input: path[..depth].to_vec(),
+                node: key.to_string(),
+            })?;
+            key = match node.inputs.get(name) {
+                Some(NodeInput::Node(target)) => {
+                    if self.node(target).is_none() {
+                        return Err(Unresolved::MissingNode {
+                            input: here.to_vec(),
+                            node: target.clone(),
+                        });
+                    }
+                    target
+                }
+                Some(NodeInput::Follows(target)) => {
+                    if following.iter().any(|seen| seen == target) {
+                        return Err(Unresolved::Cycle {
+                            input: here.to_vec(),
+                            through: target.clone(),
+                        });
+                    }
+                    following.push(target.clone());
+                    let resolved = self.resolve_from_root(target, following)?;
+                    following.pop();
+                    resolved
+                }
+                None => return Err(Unresolved::NoSuchInput(here.to_vec())),
+            };
+        }
+        Ok(key)
+    }
+
+    fn expand<'a>(
+        &'a self,
+        node: &'a LockNode,
+        path: &mut InputPath,
+        ancestors: &mut Vec<&'a str>,
+    ) -> NixUriResult<Vec<EffectiveInput>> {
+        let mut out = Vec::with_capacity(node.inputs.len());
+        for (name, input) in &node.inputs {
+            path.push(name.clone());
+            let effective = match input {
+                NodeInput::Node(key) => {
+                    let child = self.existing_node(key, path)?;
+                    if ancestors.contains(&key.as_str()) {
+                        return Err(lock_error(path, format!("node `{key}` contains itself")));
+                    }
+                    ancestors.push(key);
+                    let inputs = self.expand(child, path, ancestors)?;
+                    ancestors.pop();
+                    EffectiveInput {
+                        name: name.clone(),
+                        path: path.clone(),
+                        node: key.clone(),
+                        follows: None,
+                        inputs,
+                    }
+                }
+                NodeInput::Follows(target) => EffectiveInput {
+                    name: name.clone(),
+                    path: path.clone(),
+                    node: self.resolve_input(target)?.to_string(),
+                    follows: Some(target.clone()),
+                    inputs: Vec::new(),
+                },
+            };
+            out.push(effective);
+            path.pop();
+        }
+        Ok(out)
+    }
+
+    pub(super) fn existing_node(&self, key: &str, path: &[String]) -> NixUriResult<&LockNode> {
+        self.node(key)
+            .ok_or_else(|| lock_error(path, format!("node `{key}` does not exist")))
+    }
+}
+
+/// Why an input path does not resolve, each naming the input path that
+/// failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Unresolved {
+    /// The path names an input its node does not declare.
+    NoSuchInput(InputPath),
+    /// The input points at a node key that is not in the lock file.
+    MissingNode { input: InputPath, node: String },
+    /// The input's `follows` leads back to a `follows` already being
+    /// resolved.
+    Cycle {
+        input: InputPath,
+        through: InputPath,
+    },
+}
+
+impl Unresolved {
+    fn into_error(self) -> NixUriError {
+        match self {
+            Self::NoSuchInput(input) => lock_error(&input, "no such input".into()),
+            Self::MissingNode { input, node } => {
+                lock_error(&input, format!("node `{node}` does not exist"))
+            }
+            Self::Cycle { input, through } => lock_error(
+                &input,
+                format!("`follows` cycle through `{}`", display_path(&through)),
+            ),
+        }
+    }
+}
+
+fn lock_error(path: &[String], reason: String) -> NixUriError {
+    NixUriError::Lock {
+        input: display_path(path),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::lockfile::fixtures::{self, path};
+
+    #[rstest]
+    #[case("", "root")]
+    #[case("nixpkgs", "nixpkgs")]
+    #[case("home-manager/nixpkgs", "nixpkgs")]
+    #[case("darwin/nixpkgs", "nixpkgs")]
+    #[case("utils/systems", "systems")]
+    fn resolves_follows_chains(#[case] input: &str, #[case] node: &str) {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        assert_eq!(lock.resolve_input(&path(input)).unwrap(), node);
+    }
+
+    #[test]
+    fn effective_tree_reports_physical_nodes() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let tree = lock.effective_inputs().unwrap();
+        let flat: Vec<_> = tree
+            .iter()
+            .flat_map(EffectiveInput::walk)
+            .map(|input| {
+                (
+                    display_path(&input.path),
+                    input.node.as_str(),
+                    input.follows.as_deref().map(display_path),
+                )
+            })
+            .collect();
+        assert_eq!(
+            flat,
+            [
+                ("darwin".into(), "darwin", None),
+                (
+                    "darwin/nixpkgs".into(),
+                    "nixpkgs",
+                    Some("home-manager/nixpkgs".into())
+                ),
+                ("home-manager".into(), "home-manager", None),
+                (
+                    "home-manager/nixpkgs".into(),
+                    "nixpkgs",
+                    Some("nixpkgs".into())
+                ),
+                ("nixpkgs".into(), "nixpkgs", None),
+                ("utils".into(), "utils", None),
+                ("utils/systems".into(), "systems", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_to_root_is_the_flake_itself() {
+        let lock = Lockfile::parse(
+            r#"{"nodes":{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"parent":[]},
+                "locked":{"type":"path","path":"/a","narHash":"sha256-a"},
+                "original":{"type":"path","path":"/a"}}},"root":"root","version":7}"#,
+        )
+        .unwrap();
+        assert_eq!(lock.resolve_input(&path("a/parent")).unwrap(), "root");
+        assert!(lock.effective_inputs().is_ok());
+    }
+
+    #[rstest]
+    #[case(
+        r#"{"root":{"inputs":{"a":["missing"]}}}"#,
+        "a",
+        "missing",
+        "no such input"
+    )]
+    #[case(r#"{"root":{"inputs":{"a":"gone"}}}"#, "a", "a", "does not exist")]
+    #[case(r#"{"root":{"inputs":{"a":["b"],"b":["a"]}}}"#, "a", "a", "cycle")]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"b":["a","c"]}}}"#,
+        "a/b",
+        "a/c",
+        "no such input"
+    )]
+    fn dangling_targets_are_errors(
+        #[case] nodes: &str,
+        #[case] query: &str,
+        #[case] failing: &str,
+        #[case] reason_part: &str,
+    ) {
+        let lock =
+            Lockfile::parse(&format!(r#"{{"nodes":{nodes},"root":"root","version":7}}"#)).unwrap();
+        assert_matches!(
+            lock.resolve_input(&path(query)),
+            Err(NixUriError::Lock { input, reason }) => {
+                assert_eq!(input, failing);
+                assert!(reason.contains(reason_part), "{reason}");
+            }
+        );
+        assert_matches!(lock.effective_inputs(), Err(NixUriError::Lock { .. }));
+    }
+
+    #[test]
+    fn self_containing_nodes_are_errors() {
+        let lock = Lockfile::parse(
+            r#"{"nodes":{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"b":"a"}}},
+                "root":"root","version":7}"#,
+        )
+        .unwrap();
+        assert_matches!(
+            lock.effective_inputs(),
+            Err(NixUriError::Lock { input, .. }) => assert_eq!(input, "a/b")
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! The lock graph as a diagram: Graphviz DOT, Mermaid, or a JSON
+//! adjacency list.
+//!
+//! Every node of the lock file becomes a vertex and every input an edge
+//! labelled with the input name. An input that `follows` another is drawn
+//! to the node the `follows` resolves to, dashed (dotted in Mermaid).
+
+use serde_json::{Map, Value, json};
+
+use crate::{
+    flakeref::{FlakeRef, FlakeRefType},
+    lockfile::{InputPath, Lockfile, NodeInput, display_path},
+};
+
+/// How diagram vertices are labelled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NodeLabel {
+    /// A compact form of the locked ref: `NixOS/nixpkgs@1111111` for a
+    /// forge, the URL or path otherwise, with the rev shortened to seven
+    /// characters.
+    #[default]
+    Short,
+    /// The locked ref's [`FlakeRef::to_canonical_string`].
+    Canonical,
+}
+
+/// One input of the lock graph, as drawn.
+struct Edge<'a> {
+    from: &'a str,
+    name: &'a str,
+    to: &'a str,
+    follows: Option<&'a InputPath>,
+}
+
+impl Lockfile {
+    /// The graph in Graphviz DOT. Inputs that `follows` are dashed edges.
+    pub fn to_dot(&self, label: NodeLabel) -> String {
+        let mut out = String::from("digraph flake_lock {\n");
+        for key in self.nodes.keys() {
+            out.push_str(&format!(
+                "  {} [label={}];\n",
+                dot_string(key),
+                dot_string(&self.vertex_label(key, label))
+            ));
+        }
+        for edge in self.edges() {
+            let style = if edge.follows.is_some() {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            out.push_str(&format!(
+                "  {} -> {} [label={}{style}];\n",
+                dot_string(edge.from),
+                dot_string(edge.to),
+                dot_string(edge.name)
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The graph as a Mermaid flowchart. Vertices are numbered in node-key
+    /// order (`n0`, `n1`, ...); inputs that `follows` are dotted edges.
+    pub fn to_mermaid(&self, label: NodeLabel) -> String {
+        let id = |key: &str| {
+            let index = self.nodes.keys().position(|k| k == key).unwrap_or_default();
+            format!("n{index}")
+        };
+        let mut out = String::from("graph LR\n");
+        for key in self.nodes.keys() {
+            out.push_str(&format!(
+                "  {}[\"{}\"]\n",
+                id(key),
+                mermaid_text(&self.vertex_label(key, label))
+            ));
+        }
+        for edge in self.edges() {
+            let arrow = if edge.follows.is_some() {
+                "-.->"
+            } else {
+                "-->"
+            };
+            out.push_str(&format!(
+                "  {} {arrow}|{}| {}\n",
+                id(edge.from),
+                mermaid_text(edge.name),
+                id(edge.to)
+            ));
+        }
+        out
+    }
+
+    /// The graph as a JSON adjacency list:
+    /// `{"root": "root", "nodes": {"darwin": {"label": "...", "inputs":
+    /// [{"name": "nixpkgs", "node": "nixpkgs", "follows": "home-manager/nixpkgs"}]}}}`.
+    /// `follows` is present only on inputs that follow.
+    pub fn to_graph_json(&self, label: NodeLabel) -> String {
+        let mut nodes = Map::new();
+        for key in self.nodes.keys() {
+            nodes.insert(
+                key.clone(),
+                json!({ "label": self.vertex_label(key, label), "inputs": [] }),
+            );
+        }
+        for edge in self.edges() {
+            let mut input = json!({ "name": edge.name, "node": edge.to });
+            if let Some(path) = edge.follows {
+                input["follows"] = Value::String(display_path(path));
+            }
+            if let Some(Value::Array(inputs)) = nodes
+                .get_mut(edge.from)
+                .and_then(|node| node.get_mut("inputs"))
+            {
+                inputs.push(input);
+            }
+        }
+        let graph = json!({ "root": self.root, "nodes": nodes });
+        serde_json::to_string_pretty(&graph).unwrap_or_default()
+    }
+
+    /// Every input whose target exists, in node-key then input-name order.
+    /// A `follows` that does not resolve is left out.
+    fn edges(&self) -> Vec<Edge<'_>> {
+        let mut edges = Vec::new();
+        for (from, node) in &self.nodes {
+            for (name, input) in &node.inputs {
+                let (to, follows) = match input {
+                    NodeInput::Node(key) => {
+                        (self.nodes.get_key_value(key).map(|(k, _)| k.as_str()), None)
+                    }
+                    NodeInput::Follows(path) => (self.resolve_input(path).ok(), Some(path)),
+                };
+                if let Some(to) = to {
+                    edges.push(Edge {
+                        from,
+                        name,
+                        to,
+                        follows,
+                    });
+                }
+            }
+        }
+        edges
+    }
+
+    /// The label of node `key`: the key itself for the root and for nodes
+    /// with nothing locked.
+    fn vertex_label(&self, key: &str, label: NodeLabel) -> String {
+        let locked = self.node(key).and_then(|node| node.locked.as_ref());
+        match (locked, label) {
+            (Some(locked), NodeLabel::Canonical) => locked.to_canonical_string(),
+            (Some(locked), NodeLabel::Short) => short_label(locked),
+            (None, _) => key.to_string(),
+        }
+    }
+}
+
+fn short_label(locked: &FlakeRef) -> String {
+    let mut out = match locked.kind() {
+        FlakeRefType::GitForge(forge) => format!("{}/{}", forge.owner, forge.repo),
+        FlakeRefType::Resource(res) => res.location.clone(),
+        FlakeRefType::Path { path, .. } => path.clone(),
+        FlakeRefType::Indirect { id, .. } => id.clone(),
+    };
+    if let Some(rev) = locked.rev() {
+        out.push('@');
+        out.push_str(rev.get(..7).unwrap_or(rev));
+    }
+    out
+}
+
+/// A double-quoted DOT string.
+fn dot_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Text safe inside a Mermaid `["..."]` label or `|...|` edge label.
+fn mermaid_text(s: &str) -> String {
+    s.replace('"', "#quot;").replace('|', "#124;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockfile::fixtures;
+
+    #[test]
+    fn renders_dot() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        assert_eq!(
+            lock.to_dot(NodeLabel::Short),
+            r#"digraph flake_lock {
+  "darwin" [label="LnL7/nix-darwin@3333333"];
+  "home-manager" [label="nix-community/home-manager@2222222"];
+  "nixpkgs" [label="NixOS/nixpkgs@1111111"];
+  "root" [label="root"];
+  "systems" [label="nix-systems/default@5555555"];
+  "utils" [label="numtide/flake-utils@4444444"];
+  "darwin" -> "nixpkgs" [label="nixpkgs", style=dashed];
+  "home-manager" -> "nixpkgs" [label="nixpkgs", style=dashed];
+  "root" -> "darwin" [label="darwin"];
+  "root" -> "home-manager" [label="home-manager"];
+  "root" -> "nixpkgs" [label="nixpkgs"];
+  "root" -> "utils" [label="utils"];
+  "utils" -> "systems" [label="systems"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn renders_mermaid() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let mermaid = lock.to_mermaid(NodeLabel::Canonical);
+        let lines: Vec<_> = mermaid.lines().collect();
+        assert_eq!(lines[0], "graph LR");
+        assert_eq!(
+            lines[3],
+            "  n2[\"github:NixOS/nixpkgs/1111111111111111111111111111111111111111?narHash=sha256-nixpkgs\"]"
+        );
+        assert_eq!(lines[4], "  n3[\"root\"]");
+        assert_eq!(lines[7], "  n0 -.->|nixpkgs| n2");
+        assert_eq!(lines[9], "  n3 -->|darwin| n0");
+    }
+
+    #[test]
+    fn renders_json_adjacency() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let graph: Value = serde_json::from_str(&lock.to_graph_json(NodeLabel::Short)).unwrap();
+        assert_eq!(graph["root"], "root");
+        assert_eq!(
+            graph["nodes"]["darwin"],
+            json!({
+                "label": "LnL7/nix-darwin@3333333",
+                "inputs": [
+                    { "name": "nixpkgs", "node": "nixpkgs", "follows": "home-manager/nixpkgs" },
+                ],
+            })
+        );
+        assert_eq!(
+            graph["nodes"]["root"]["inputs"][0],
+            json!({ "name": "darwin", "node": "darwin" })
+        );
+        assert_eq!(graph["nodes"]["systems"]["inputs"], json!([]));
+    }
+
+    #[test]
+    fn escapes_labels() {
+        assert_eq!(dot_string(r#"a"b\c"#), r#""a\"b\\c""#);
+        assert_eq!(mermaid_text(r#"a"b|c"#), "a#quot;b#124;c");
+    }
+}
@@ -0,0 +1,286 @@
+//! Staleness of locked inputs.
+//!
+//! Each node's `lastModified` says how old its pinned source is; an input
+//! nobody has updated in months misses security fixes. Nixpkgs gets a
+//! second check: a pin on a release branch past its end of life (see
+//! [`crate::ChannelRelease::end_of_life`]) receives no fixes at all, however
+//! recently it was updated. The checks mirror those of `flake-checker`.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    error::NixUriResult,
+    flakeref::{Channel, FlakeRef, FlakeRefType},
+    lockfile::{EffectiveInput, InputPath, LockNode, Lockfile, display_path},
+    time::format_date,
+};
+
+/// `flake-checker`'s default limit on the age of a locked input.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30 * 86_400);
+
+/// Age limits for [`Lockfile::staleness`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StalenessPolicy {
+    max_age: Duration,
+    nixpkgs_max_age: Option<Duration>,
+}
+
+/// An input [`Lockfile::staleness`] flagged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StaleInput {
+    /// The first input path that declares the node.
+    pub path: InputPath,
+    pub node: String,
+    pub reason: StaleReason,
+}
+
+/// Why a [`StaleInput`] was flagged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StaleReason {
+    /// `lastModified` is older than the limit.
+    Outdated {
+        /// `lastModified`, in seconds since the epoch.
+        last_modified: u64,
+        age: Duration,
+        max_age: Duration,
+    },
+    /// Nixpkgs pinned to a release branch past its end of life.
+    EndOfLife {
+        channel: Channel,
+        /// The release's last supported day, `YYYY-MM-DD`; `None` for
+        /// releases too old for the crate to track the date.
+        end_of_life: Option<&'static str>,
+    },
+}
+
+impl StalenessPolicy {
+    /// Flag inputs last modified more than `max_age` ago.
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            nixpkgs_max_age: None,
+        }
+    }
+
+    /// A separate limit for nixpkgs, which moves faster than most inputs.
+    pub fn with_nixpkgs_max_age(mut self, max_age: Duration) -> Self {
+        self.nixpkgs_max_age = Some(max_age);
+        self
+    }
+}
+
+impl Default for StalenessPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_AGE)
+    }
+}
+
+impl Display for StaleInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "input `{}`: ", display_path(&self.path))?;
+        match &self.reason {
+            StaleReason::Outdated {
+                last_modified,
+                age,
+                max_age,
+            } => write!(
+                f,
+                "last modified {} ({} days ago, limit {})",
+                format_date(*last_modified),
+                age.as_secs() / 86_400,
+                max_age.as_secs() / 86_400
+            ),
+            StaleReason::EndOfLife {
+                channel,
+                end_of_life,
+            } => {
+                write!(f, "tracks `{channel}`, end of life")?;
+                match end_of_life {
+                    Some(date) => write!(f, " since {date}"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Lockfile {
+    /// The inputs that are stale under `policy` at `now`, in input-path
+    /// order.
+    ///
+    /// Each node is checked once, at the first path that declares it;
+    /// inputs that `follows` are judged where their target is declared.
+    /// Nodes without `lastModified` pass the age check. Nixpkgs (a locked
+    /// `NixOS/nixpkgs`, or an indirect `nixpkgs` original) tracking a
+    /// release branch is also flagged once `now` is past the release's end
+    /// of life; releases newer than [`crate::ChannelRelease::end_of_life`]
+    /// knows are never flagged. Errors as [`Lockfile::effective_inputs`].
+    pub fn staleness(
+        &self,
+        policy: &StalenessPolicy,
+        now: SystemTime,
+    ) -> NixUriResult<Vec<StaleInput>> {
+        let now = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let today = format_date(now);
+        let mut seen = BTreeSet::new();
+        let mut out = Vec::new();
+        let tree = self.effective_inputs()?;
+        for input in tree.iter().flat_map(EffectiveInput::walk) {
+            if input.follows.is_some() || !seen.insert(input.node.as_str()) {
+                continue;
+            }
+            let Some(node) = self.node(&input.node) else {
+                continue;
+            };
+            let nixpkgs = is_nixpkgs(node);
+            let max_age = match policy.nixpkgs_max_age {
+                Some(max_age) if nixpkgs => max_age,
+                _ => policy.max_age,
+            };
+            let mut flag = |reason| {
+                out.push(StaleInput {
+                    path: input.path.clone(),
+                    node: input.node.clone(),
+                    reason,
+                });
+            };
+
+            if let Some(last_modified) = node
+                .locked
+                .as_ref()
+                .and_then(|locked| locked.params().last_modified_value()?.parse::<u64>().ok())
+            {
+                let age = Duration::from_secs(now.saturating_sub(last_modified));
+                if age > max_age {
+                    flag(StaleReason::Outdated {
+                        last_modified,
+                        age,
+                        max_age,
+                    });
+                }
+            }
+            let end_of_life = tracked_channel(node)
+                .filter(|_| nixpkgs)
+                .and_then(|channel| match channel.release.end_of_life() {
+                    Some(date) => (today.as_str() > date).then_some((channel, Some(date))),
+                    None => channel
+                        .release
+                        .predates_end_of_life_dates()
+                        .then_some((channel, None)),
+                });
+            if let Some((channel, end_of_life)) = end_of_life {
+                flag(StaleReason::EndOfLife {
+                    channel,
+                    end_of_life,
+                });
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn is_nixpkgs(node: &LockNode) -> bool {
+    let upstream = |flake_ref: &FlakeRef| {
+        flake_ref
+            .owner()
+            .is_some_and(|owner| owner.eq_ignore_ascii_case("nixos"))
+            && flake_ref
+                .repo()
+                .is_some_and(|repo| repo.eq_ignore_ascii_case("nixpkgs"))
+    };
+    node.locked.as_ref().is_some_and(upstream)
+        || node.original.as_ref().is_some_and(|original| {
+            matches!(original.kind(), FlakeRefType::Indirect { id, .. } if id == "nixpkgs")
+        })
+}
+
+/// The release branch the flake declared, from `original`'s ref.
+fn tracked_channel(node: &LockNode) -> Option<Channel> {
+    node.original.as_ref()?.ref_name()?.channel().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockfile::fixtures;
+
+    const DAY: u64 = 86_400;
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn reasons(found: &[StaleInput]) -> Vec<String> {
+        found.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn flags_inputs_past_the_limit() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        // 40 days after nixpkgs' lastModified; systems and utils are older.
+        let found = lock
+            .staleness(&StalenessPolicy::default(), at(1_700_000_000 + 40 * DAY))
+            .unwrap();
+        assert_eq!(
+            reasons(&found),
+            [
+                "input `darwin`: last modified 2023-11-14 (39 days ago, limit 30)",
+                "input `home-manager`: last modified 2023-11-14 (39 days ago, limit 30)",
+                "input `nixpkgs`: last modified 2023-11-14 (40 days ago, limit 30)",
+                "input `utils`: last modified 2023-07-22 (155 days ago, limit 30)",
+                "input `utils/systems`: last modified 2023-03-28 (271 days ago, limit 30)",
+            ]
+        );
+        assert!(
+            lock.staleness(&StalenessPolicy::default(), at(1_700_000_000))
+                .unwrap()
+                .iter()
+                .all(|stale| stale.node == "systems" || stale.node == "utils")
+        );
+    }
+
+    #[test]
+    fn nixpkgs_has_its_own_limit() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let policy = StalenessPolicy::new(Duration::from_secs(365 * DAY))
+            .with_nixpkgs_max_age(Duration::from_secs(7 * DAY));
+        let found = lock
+            .staleness(&policy, at(1_700_000_000 + 10 * DAY))
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].node, "nixpkgs");
+    }
+
+    #[test]
+    fn flags_end_of_life_nixpkgs_branches() {
+        let lock =
+            Lockfile::parse(&fixtures::NESTED.replace("nixos-unstable", "nixos-23.05")).unwrap();
+        let policy = StalenessPolicy::new(Duration::from_secs(10_000 * DAY));
+        // 2023-12-31 is 23.05's last day.
+        assert_eq!(lock.staleness(&policy, at(1_704_067_199)).unwrap(), []);
+        let found = lock.staleness(&policy, at(1_704_067_200)).unwrap();
+        assert_eq!(
+            reasons(&found),
+            ["input `nixpkgs`: tracks `nixos-23.05`, end of life since 2023-12-31"]
+        );
+
+        let unstable = Lockfile::parse(fixtures::NESTED).unwrap();
+        assert_eq!(unstable.staleness(&policy, at(1_704_067_200)).unwrap(), []);
+
+        let ancient =
+            Lockfile::parse(&fixtures::NESTED.replace("nixos-unstable", "nixos-19.09")).unwrap();
+        assert_eq!(
+            reasons(&ancient.staleness(&policy, at(1_600_000_000)).unwrap()),
+            ["input `nixpkgs`: tracks `nixos-19.09`, end of life"]
+        );
+    }
+}
@@ -0,0 +1,407 @@
+//! Structural checks over a lock file.
+//!
+//! [`Lockfile::parse`] accepts any well-formed graph; Nix only finds out
+//! that an input points nowhere, or that a `locked` entry pins nothing,
+//! once it evaluates the flake. [`Lockfile::validate`] walks the graph up
+//! front and reports every such problem at once.
+
+use std::{collections::BTreeSet, fmt::Display};
+
+use crate::{
+    flakeref::{FlakeRef, FlakeRefType, ResourceType},
+    lockfile::{
+        InputPath, LockNode, Lockfile, NodeInput, SUPPORTED_VERSIONS, display_path,
+        follows::Unresolved,
+    },
+};
+
+/// A problem [`Lockfile::validate`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LockDiagnostic {
+    /// The input path the problem was found at. The first path that
+    /// reaches a node stands for all of them; the empty path is the root
+    /// (and the file as a whole).
+    pub path: InputPath,
+    /// Key of the node concerned: the node itself, or for a broken input
+    /// the node declaring it. `None` for file-level problems.
+    pub node: Option<String>,
+    pub issue: LockIssue,
+}
+
+/// What is wrong, for a [`LockDiagnostic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LockIssue {
+    /// `version` is outside what Nix reads (5 to 7).
+    UnsupportedVersion(u64),
+    /// `root` names a node that does not exist.
+    MissingRoot,
+    /// An input refers to a node key that does not exist.
+    DanglingInput { target: String },
+    /// An input `follows` a path that does not resolve.
+    DanglingFollows { target: InputPath },
+    /// An input's `follows` chain loops back on itself.
+    FollowsCycle { through: InputPath },
+    /// The node contains itself through direct (non-`follows`) inputs.
+    NodeCycle,
+    /// A non-root node without a `locked` entry.
+    MissingLocked,
+    /// A non-root node without an `original` entry.
+    MissingOriginal,
+    /// The `locked` entry is itself an indirect (registry) ref.
+    IndirectLocked,
+    /// The `locked` entry lacks the attribute that pins it: a full `rev`
+    /// for forge, git and Mercurial inputs, a `narHash` for all but
+    /// relative paths inside a `parent`.
+    Unlocked { missing: &'static str },
+    /// `locked` and `original` are different input types. An indirect
+    /// `original` may lock to any type.
+    TypeMismatch { locked: String, original: String },
+    /// A `parent` entry in a lock file older than version 7. Relative
+    /// path inputs postdate version 7, so Nix never wrote one.
+    ParentBeforeVersion7,
+}
+
+impl Display for LockIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported lock file version {version}")
+            }
+            Self::MissingRoot => write!(f, "root node does not exist"),
+            Self::DanglingInput { target } => write!(f, "node `{target}` does not exist"),
+            Self::DanglingFollows { target } => {
+                write!(
+                    f,
+                    "follows `{}`, which does not resolve",
+                    display_path(target)
+                )
+            }
+            Self::FollowsCycle { through } => {
+                write!(f, "`follows` cycle through `{}`", display_path(through))
+            }
+            Self::NodeCycle => write!(f, "node contains itself"),
+            Self::MissingLocked => write!(f, "no `locked` entry"),
+            Self::MissingOriginal => write!(f, "no `original` entry"),
+            Self::IndirectLocked => write!(f, "`locked` is an indirect ref"),
+            Self::Unlocked { missing } => write!(f, "`locked` has no `{missing}`"),
+            Self::TypeMismatch { locked, original } => write!(
+                f,
+                "`locked` is a `{locked}` input but `original` is `{original}`"
+            ),
+            Self::ParentBeforeVersion7 => write!(f, "`parent` requires lock file version 7"),
+        }
+    }
+}
+
+impl Display for LockDiagnostic {
+    /// The issue, prefixed with the input path and node key.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.node, self.path.is_empty()) {
+            (_, true) => write!(f, "root: {}", self.issue),
+            (Some(node), false) => write!(
+                f,
+                "input `{}` (node `{node}`): {}",
+                display_path(&self.path),
+                self.issue
+            ),
+            (None, false) => write!(f, "input `{}`: {}", display_path(&self.path), self.issue),
+        }
+    }
+}
+
+impl Lockfile {
+    /// Check the lock graph the way Nix will use it, returning every
+    /// problem found; an empty list means the file is sound.
+    ///
+    /// The walk starts at the root and follows direct inputs, so nodes no
+    /// input reaches are not checked (Nix ignores them too). `follows`
+    /// may legally point back up the graph, even at the root; a node
+    /// containing itself through direct inputs may not.
+    pub fn validate(&self) -> Vec<LockDiagnostic> {
+        let mut walk = Walk {
+            lock: self,
+            ancestors: Vec::new(),
+            visited: BTreeSet::new(),
+            out: Vec::new(),
+        };
+        if !SUPPORTED_VERSIONS.contains(&self.version) {
+            walk.report(&[], None, LockIssue::UnsupportedVersion(self.version));
+        }
+        match self.root_node() {
+            Some(root) => walk.visit(&self.root, root, &mut Vec::new()),
+            None => walk.report(&[], None, LockIssue::MissingRoot),
+        }
+        walk.out
+    }
+}
+
+struct Walk<'a> {
+    lock: &'a Lockfile,
+    ancestors: Vec<&'a str>,
+    visited: BTreeSet<&'a str>,
+    out: Vec<LockDiagnostic>,
+}
+
+impl<'a> Walk<'a> {
+    fn visit(&mut self, key: &'a str, node: &'a LockNode, path: &mut InputPath) {
+        self.visited.insert(key);
+        if !path.is_empty() {
+            self.check_node(key, node, path);
+        }
+        self.ancestors.push(key);
+        for (name, input) in &node.inputs {
+            path.push(name.clone());
+            match input {
+                NodeInput::Node(target) => match self.lock.nodes.get_key_value(target) {
+                    None => self.report(
+                        path,
+                        Some(key),
+                        LockIssue::DanglingInput {
+                            target: target.clone(),
+                        },
+                    ),
+                    Some(_) if self.ancestors.contains(&target.as_str()) => {
+                        self.report(path, Some(target), LockIssue::NodeCycle);
+                    }
+                    Some(_) if self.visited.contains(target.as_str()) => {}
+                    Some((target, child)) => self.visit(target, child, path),
+                },
+                NodeInput::Follows(target) => match self.lock.try_resolve(target) {
+                    Ok(_) => {}
+                    Err(Unresolved::Cycle { through, .. }) => {
+                        self.report(path, Some(key), LockIssue::FollowsCycle { through });
+                    }
+                    Err(Unresolved::NoSuchInput(_) | Unresolved::MissingNode { .. }) => {
+                        self.report(
+                            path,
+                            Some(key),
+                            LockIssue::DanglingFollows {
+                                target: target.clone(),
+                            },
+                        );
+                    }
+                },
+            }
+            path.pop();
+        }
+        self.ancestors.pop();
+    }
+
+    fn check_node(&mut self, key: &str, node: &LockNode, path: &[String]) {
+        if node.parent.is_some() && self.lock.version < 7 {
+            self.report(path, Some(key), LockIssue::ParentBeforeVersion7);
+        }
+        let Some(locked) = &node.locked else {
+            self.report(path, Some(key), LockIssue::MissingLocked);
+            if node.original.is_none() {
+                self.report(path, Some(key), LockIssue::MissingOriginal);
+            }
+            return;
+        };
+        if matches!(locked.kind(), FlakeRefType::Indirect { .. }) {
+            self.report(path, Some(key), LockIssue::IndirectLocked);
+        } else {
+            if pinned_by_rev(locked) && !locked.is_locked() {
+                self.report(path, Some(key), LockIssue::Unlocked { missing: "rev" });
+            }
+            // Relative inputs live inside their parent's tree, so Nix
+            // records no hash of their own.
+            let in_parent = node.parent.is_some() && locked.is_relative_path();
+            if !in_parent && locked.params().nar_hash_value().is_none() {
+                self.report(path, Some(key), LockIssue::Unlocked { missing: "narHash" });
+            }
+        }
+        match &node.original {
+            None => self.report(path, Some(key), LockIssue::MissingOriginal),
+            Some(original) => {
+                let (locked, original) = (input_type(locked), input_type(original));
+                if original != "indirect" && locked != original {
+                    self.report(
+                        path,
+                        Some(key),
+                        LockIssue::TypeMismatch { locked, original },
+                    );
+                }
+            }
+        }
+    }
+
+    fn report(&mut self, path: &[String], node: Option<&str>, issue: LockIssue) {
+        self.out.push(LockDiagnostic {
+            path: path.to_vec(),
+            node: node.map(str::to_string),
+            issue,
+        });
+    }
+}
+
+/// Whether a locked ref of this kind is pinned by a commit.
+fn pinned_by_rev(flake_ref: &FlakeRef) -> bool {
+    match flake_ref.kind() {
+        FlakeRefType::GitForge(_) => true,
+        FlakeRefType::Resource(res) => {
+            matches!(res.res_type, ResourceType::Git | ResourceType::Mercurial)
+        }
+        FlakeRefType::Path { .. } | FlakeRefType::Indirect { .. } => false,
+    }
+}
+
+/// The attribute-form `type` of a ref.
+fn input_type(flake_ref: &FlakeRef) -> String {
+    match flake_ref.kind() {
+        FlakeRefType::Indirect { .. } => "indirect".into(),
+        FlakeRefType::GitForge(forge) => forge.platform.to_string(),
+        FlakeRefType::Resource(res) => res.res_type.to_string(),
+        FlakeRefType::Path { .. } => "path".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::lockfile::fixtures::{self, path};
+
+    const GOOD: &str = r#""locked":{"narHash":"sha256-a","owner":"o","repo":"a",
+        "rev":"1111111111111111111111111111111111111111","type":"github"},
+        "original":{"owner":"o","repo":"a","type":"github"}"#;
+
+    fn lock(nodes: &str, version: u64) -> Lockfile {
+        let nodes = nodes.replace("GOOD", GOOD);
+        Lockfile::parse(&format!(
+            r#"{{"nodes":{nodes},"root":"root","version":{version}}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn sound_lock_files_have_no_diagnostics() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        assert_eq!(lock.validate(), []);
+    }
+
+    #[rstest]
+    #[case(r#"{"top":{}}"#, "", LockIssue::MissingRoot)]
+    #[case(
+        r#"{"root":{"inputs":{"a":"gone"}}}"#,
+        "a",
+        LockIssue::DanglingInput { target: "gone".into() }
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"b":["x","y"]},GOOD}}"#,
+        "a/b",
+        LockIssue::DanglingFollows { target: path("x/y") }
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":["a"]}}}"#,
+        "a",
+        LockIssue::FollowsCycle { through: path("a") }
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"b":"a"},GOOD}}"#,
+        "a/b",
+        LockIssue::NodeCycle
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{"original":{"id":"a","type":"indirect"}}}"#,
+        "a",
+        LockIssue::MissingLocked
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{
+            "locked":{"narHash":"sha256-a","owner":"o","repo":"a","type":"github"},
+            "original":{"owner":"o","repo":"a","type":"github"}}}"#,
+        "a",
+        LockIssue::Unlocked { missing: "rev" }
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{
+            "locked":{"type":"tarball","url":"https://example.com/a.tar.gz"},
+            "original":{"type":"tarball","url":"https://example.com/a.tar.gz"}}}"#,
+        "a",
+        LockIssue::Unlocked { missing: "narHash" }
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{
+            "locked":{"path":"./sub","type":"path"},"original":{"path":"./sub","type":"path"}}}"#,
+        "a",
+        LockIssue::Unlocked { missing: "narHash" }
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{
+            "locked":{"id":"a","type":"indirect"},"original":{"id":"a","type":"indirect"}}}"#,
+        "a",
+        LockIssue::IndirectLocked
+    )]
+    #[case(
+        r#"{"root":{"inputs":{"a":"a"}},"a":{
+            "locked":{"narHash":"sha256-a","owner":"o","repo":"a",
+                "rev":"1111111111111111111111111111111111111111","type":"github"},
+            "original":{"owner":"o","repo":"a","type":"gitlab"}}}"#,
+        "a",
+        LockIssue::TypeMismatch { locked: "github".into(), original: "gitlab".into() }
+    )]
+    fn reports(#[case] nodes: &str, #[case] at: &str, #[case] issue: LockIssue) {
+        let diagnostics = lock(nodes, 7).validate();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (display_path(&d.path), &d.issue))
+            .collect();
+        assert_eq!(found, [(at.to_string(), &issue)]);
+    }
+
+    #[test]
+    fn indirect_original_may_lock_to_any_type() {
+        let lock = lock(
+            r#"{"root":{"inputs":{"a":"a"}},"a":{
+                "locked":{"narHash":"sha256-a","owner":"o","repo":"a",
+                    "rev":"1111111111111111111111111111111111111111","type":"github"},
+                "original":{"id":"a","type":"indirect"}}}"#,
+            7,
+        );
+        assert_eq!(lock.validate(), []);
+    }
+
+    #[test]
+    fn version_rules() {
+        // A relative input as Nix writes it: no narHash, only `parent`.
+        let nodes = r#"{"root":{"inputs":{"a":"a"}},"a":{"parent":[],
+            "locked":{"path":"./sub","type":"path"},
+            "original":{"path":"./sub","type":"path"}}}"#;
+        assert_eq!(lock(nodes, 7).validate(), []);
+        let diagnostics = lock(nodes, 6).validate();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "input `a` (node `a`): `parent` requires lock file version 7"
+        );
+
+        let mut lock = lock(r#"{"root":{}}"#, 7);
+        lock.version = 8;
+        assert_eq!(
+            lock.validate()[0].to_string(),
+            "root: unsupported lock file version 8"
+        );
+    }
+
+    #[test]
+    fn shared_nodes_are_checked_once_at_their_first_path() {
+        let lock = lock(
+            r#"{"root":{"inputs":{"a":"a","b":"b"}},
+                "a":{"inputs":{"s":"s"},GOOD},"b":{"inputs":{"s":"s"},GOOD},
+                "s":{"original":{"owner":"o","repo":"s","type":"github"}}}"#,
+            7,
+        );
+        assert_eq!(
+            lock.validate(),
+            [LockDiagnostic {
+                path: path("a/s"),
+                node: Some("s".into()),
+                issue: LockIssue::MissingLocked,
+            }]
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! Conversions between lock file versions.
+//!
+//! Versions 5 to 7 share the node graph; version 5 keeps `lastModified`
+//! and `narHash` in an `info` object beside `locked`, and 6 and 7 differ
+//! only in the number. Version 4 predates the graph: inputs nest as a
+//! tree, each spelling its refs as URL strings (`url`, `originalUrl`) with
+//! `narHash` and `lastModified` beside them, and an input that follows
+//! another is the array of names of the input path it follows.
+//!
+//! Version 4 files parse into the graph, so [`Lockfile::to_version`] and
+//! [`Lockfile::to_json`] are all a migration needs:
+//!
+//! ```
+//! # use nix_uri::Lockfile;
+//! let v4 = r#"{"inputs":{"nixpkgs":{"narHash":"sha256-x",
+//!     "originalUrl":"nixpkgs","url":"github:NixOS/nixpkgs/1111111111111111111111111111111111111111"}},
+//!     "version":4}"#;
+//! let lock = Lockfile::parse(v4).unwrap().to_version(7).unwrap();
+//! assert_eq!(lock.version, 7);
+//! assert!(lock.to_json().contains(r#""type": "indirect""#));
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::NixUriResult,
+    flakeref::FlakeRef,
+    lockfile::{InputPath, LockNode, Lockfile, NodeInput, invalid, is_yes, yes},
+};
+
+/// The tree-shaped version this module converts from and to.
+pub(crate) const LEGACY_VERSION: u64 = 4;
+
+/// The versions [`Lockfile::to_version`] converts between.
+const CONVERTIBLE_VERSIONS: std::ops::RangeInclusive<u64> = LEGACY_VERSION..=7;
+
+/// Key of the root node Nix gives a graph built from a tree.
+const ROOT_KEY: &str = "root";
+
+#[derive(Serialize, Deserialize)]
+struct LegacyLockfile {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    inputs: BTreeMap<String, LegacyInput>,
+    version: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum LegacyInput {
+    Follows(InputPath),
+    Node(LegacyNode),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyNode {
+    #[serde(default = "yes", skip_serializing_if = "is_yes")]
+    flake: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    inputs: BTreeMap<String, LegacyInput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nar_hash: Option<String>,
+    original_url: String,
+    url: String,
+}
+
+impl Lockfile {
+    /// The same lock graph, written as lock file `version`.
+    ///
+    /// Any of versions 4 to 7 can be converted to any other. Version 4 is
+    /// a tree, so a node several inputs share is written once per input
+    /// (and reads back as separate nodes); a node containing itself or
+    /// missing `locked` or `original` has no version 4 form, and a
+    /// `parent` entry none before version 7. Those cases, and versions
+    /// outside 4 to 7, surface
+    /// [`crate::NixUriError::InvalidValue`] with field `lockfile`.
+    pub fn to_version(&self, version: u64) -> NixUriResult<Self> {
+        if !CONVERTIBLE_VERSIONS.contains(&version) {
+            return Err(invalid(format!("unsupported lock file version {version}")));
+        }
+        if version < 7 {
+            if let Some((key, _)) = self.nodes.iter().find(|(_, node)| node.parent.is_some()) {
+                return Err(invalid(format!(
+                    "node `{key}`: `parent` requires lock file version 7"
+                )));
+            }
+        }
+        if version == LEGACY_VERSION {
+            self.check_tree(&self.root, &mut Vec::new())?;
+        }
+        let mut converted = self.clone();
+        converted.version = version;
+        Ok(converted)
+    }
+
+    /// Fail unless the graph below `key` is a tree of fully locked nodes.
+    fn check_tree<'a>(&'a self, key: &'a str, ancestors: &mut Vec<&'a str>) -> NixUriResult<()> {
+        let node = self
+            .node(key)
+            .ok_or_else(|| invalid(format!("node `{key}` does not exist")))?;
+        if !ancestors.is_empty() && (node.locked.is_none() || node.original.is_none()) {
+            return Err(invalid(format!(
+                "node `{key}` needs `locked` and `original` for version {LEGACY_VERSION}"
+            )));
+        }
+        if ancestors.contains(&key) {
+            return Err(invalid(format!(
+                "node `{key}` contains itself, which version {LEGACY_VERSION} cannot express"
+            )));
+        }
+        ancestors.push(key);
+        for input in node.inputs.values() {
+            if let NodeInput::Node(child) = input {
+                self.check_tree(child, ancestors)?;
+            }
+        }
+        ancestors.pop();
+        Ok(())
+    }
+}
+
+/// Build the graph from a version 4 tree. Nodes are keyed by input name,
+/// with `_2`, `_3`, ... appended where names repeat, as Nix keys them.
+pub(crate) fn from_legacy(value: Value) -> NixUriResult<Lockfile> {
+    let legacy: LegacyLockfile =
+        serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?;
+    let mut nodes = BTreeMap::new();
+    // Reserve the root's key before any input can take it.
+    nodes.insert(ROOT_KEY.to_string(), LockNode::new());
+    let root = LockNode {
+        inputs: graph_inputs(legacy.inputs, &mut nodes)?,
+        ..LockNode::new()
+    };
+    nodes.insert(ROOT_KEY.to_string(), root);
+    Ok(Lockfile {
+        version: LEGACY_VERSION,
+        root: ROOT_KEY.to_string(),
+        nodes,
+    })
+}
+
+fn graph_inputs(
+    inputs: BTreeMap<String, LegacyInput>,
+    nodes: &mut BTreeMap<String, LockNode>,
+) -> NixUriResult<BTreeMap<String, NodeInput>> {
+    let mut out = BTreeMap::new();
+    for (name, input) in inputs {
+        let input = match input {
+            LegacyInput::Follows(path) => NodeInput::Follows(path),
+            LegacyInput::Node(legacy) => {
+                let key = fresh_key(nodes, &name);
+                // Claim the key before the children pick theirs.
+                nodes.insert(key.clone(), LockNode::new());
+                let parse = |url: &str| {
+                    url.parse::<FlakeRef>()
+                        .map_err(|e| invalid(format!("input `{name}`: {e}")))
+                };
+                let mut locked = parse(&legacy.url)?;
+                locked.set_nar_hash(legacy.nar_hash);
+                locked.set_last_modified(legacy.last_modified.map(|t| t.to_string()));
+                let node = LockNode {
+                    inputs: graph_inputs(legacy.inputs, nodes)?,
+                    locked: Some(locked),
+                    original: Some(parse(&legacy.original_url)?),
+                    flake: legacy.flake,
+                    parent: None,
+                };
+                nodes.insert(key.clone(), node);
+                NodeInput::Node(key)
+            }
+        };
+        out.insert(name, input);
+    }
+    Ok(out)
+}
+
+fn fresh_key(nodes: &BTreeMap<String, LockNode>, name: &str) -> String {
+    if !nodes.contains_key(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| format!("{name}_{n}"))
+        .find(|key| !nodes.contains_key(key))
+        .unwrap_or_default()
+}
+
+/// The version 4 tree of `lock`. Inputs [`Lockfile::to_version`] would
+/// reject (missing nodes, cycles, nodes without `locked` or `original`)
+/// are left out.
+pub(crate) fn to_legacy(lock: &Lockfile) -> Value {
+    let inputs = lock
+        .root_node()
+        .map(|root| legacy_inputs(lock, root, &mut vec![lock.root.as_str()]))
+        .unwrap_or_default();
+    let legacy = LegacyLockfile {
+        inputs,
+        version: lock.version,
+    };
+    serde_json::to_value(legacy).unwrap_or_default()
+}
+
+fn legacy_inputs<'a>(
+    lock: &'a Lockfile,
+    node: &'a LockNode,
+    ancestors: &mut Vec<&'a str>,
+) -> BTreeMap<String, LegacyInput> {
+    let mut out = BTreeMap::new();
+    for (name, input) in &node.inputs {
+        let input = match input {
+            NodeInput::Follows(path) => LegacyInput::Follows(path.clone()),
+            NodeInput::Node(key) => {
+                let Some(child) = lock.node(key) else {
+                    continue;
+                };
+                let (Some(locked), Some(original)) = (&child.locked, &child.original) else {
+                    continue;
+                };
+                if ancestors.contains(&key.as_str()) {
+                    continue;
+                }
+                ancestors.push(key);
+                let inputs = legacy_inputs(lock, child, ancestors);
+                ancestors.pop();
+
+                let mut url = locked.clone();
+                url.set_nar_hash(None);
+                url.set_last_modified(None);
+                LegacyInput::Node(LegacyNode {
+                    flake: child.flake,
+                    inputs,
+                    last_modified: locked
+                        .params()
+                        .last_modified_value()
+                        .and_then(|t| t.parse().ok()),
+                    nar_hash: locked.params().nar_hash_value().map(str::to_string),
+                    original_url: original.to_string(),
+                    url: url.to_string(),
+                })
+            }
+        };
+        out.insert(name.clone(), input);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::{error::NixUriError, lockfile::fixtures};
+
+    const V4: &str = r#"{
+  "inputs": {
+    "home-manager": {
+      "inputs": {
+        "nixpkgs": {
+          "lastModified": 1690000000,
+          "narHash": "sha256-hm-nixpkgs",
+          "originalUrl": "github:NixOS/nixpkgs",
+          "url": "github:NixOS/nixpkgs/2222222222222222222222222222222222222222"
+        },
+        "utils": [
+          "utils"
+        ]
+      },
+      "narHash": "sha256-hm",
+      "originalUrl": "github:nix-community/home-manager",
+      "url": "github:nix-community/home-manager/1111111111111111111111111111111111111111"
+    },
+    "nixpkgs": {
+      "lastModified": 1700000000,
+      "narHash": "sha256-nixpkgs",
+      "originalUrl": "flake:nixpkgs",
+      "url": "github:NixOS/nixpkgs/3333333333333333333333333333333333333333"
+    },
+    "utils": {
+      "flake": false,
+      "narHash": "sha256-utils",
+      "originalUrl": "github:numtide/flake-utils",
+      "url": "github:numtide/flake-utils/4444444444444444444444444444444444444444"
+    }
+  },
+  "version": 4
+}
+"#;
+
+    #[test]
+    fn version_4_parses_into_the_graph() {
+        let lock = Lockfile::parse(V4).unwrap();
+        assert_eq!(lock.version, 4);
+        assert_eq!(
+            lock.nodes.keys().collect::<Vec<_>>(),
+            ["home-manager", "nixpkgs", "nixpkgs_2", "root", "utils"]
+        );
+        let hm = lock.node("home-manager").unwrap();
+        assert_eq!(hm.inputs["nixpkgs"], NodeInput::Node("nixpkgs".into()));
+        assert_eq!(hm.inputs["utils"], NodeInput::Follows(vec!["utils".into()]));
+        let nixpkgs = lock.node("nixpkgs_2").unwrap();
+        assert_eq!(
+            nixpkgs.original.as_ref().unwrap().to_string(),
+            "flake:nixpkgs"
+        );
+        let locked = nixpkgs.locked.as_ref().unwrap();
+        assert!(locked.is_locked());
+        assert_eq!(locked.params().nar_hash_value(), Some("sha256-nixpkgs"));
+        assert!(!lock.node("utils").unwrap().flake);
+        assert_eq!(lock.to_version(7).unwrap().validate(), []);
+    }
+
+    #[test]
+    fn version_4_writes_what_it_reads() {
+        let lock = Lockfile::parse(V4).unwrap();
+        assert_eq!(lock.to_json(), V4);
+    }
+
+    #[test]
+    fn version_4_upgrades_and_back() {
+        let upgraded = Lockfile::parse(V4).unwrap().to_version(7).unwrap();
+        let json = upgraded.to_json();
+        assert!(json.contains(r#""version": 7"#));
+        let reread = Lockfile::parse(&json).unwrap();
+        assert_eq!(reread, upgraded);
+        assert_eq!(reread.to_version(4).unwrap().to_json(), V4);
+    }
+
+    #[test]
+    fn version_7_survives_a_trip_through_4() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let v4 = Lockfile::parse(&lock.to_version(4).unwrap().to_json()).unwrap();
+        assert_eq!(v4.to_version(7).unwrap(), lock);
+    }
+
+    #[test]
+    fn version_5_splits_info() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let json = lock.to_version(5).unwrap().to_json();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let nixpkgs = &value["nodes"]["nixpkgs"];
+        assert_eq!(nixpkgs["info"]["narHash"], "sha256-nixpkgs");
+        assert_eq!(nixpkgs["info"]["lastModified"], 1_700_000_000);
+        assert!(nixpkgs["locked"].get("narHash").is_none());
+        assert_eq!(Lockfile::parse(&json).unwrap().to_version(7).unwrap(), lock);
+    }
+
+    #[test]
+    fn shared_nodes_are_copied_into_the_tree() {
+        let lock = Lockfile::parse(
+            r#"{"nodes":{"root":{"inputs":{"a":"s","b":"b"}},
+                "b":{"inputs":{"s":"s"},"locked":{"narHash":"sha256-b","path":"/b","type":"path"},
+                    "original":{"path":"/b","type":"path"}},
+                "s":{"locked":{"narHash":"sha256-s","path":"/s","type":"path"},
+                    "original":{"path":"/s","type":"path"}}},
+              "root":"root","version":7}"#,
+        )
+        .unwrap();
+        let v4 = Lockfile::parse(&lock.to_version(4).unwrap().to_json()).unwrap();
+        assert_eq!(v4.node("a").unwrap().locked, v4.node("s").unwrap().locked);
+        assert_eq!(v4.resolve_input(&["b".into(), "s".into()]).unwrap(), "s");
+    }
+
+    #[rstest]
+    #[case(fixtures::NESTED, 3)]
+    #[case(fixtures::NESTED, 8)]
+    #[case(
+        r#"{"nodes":{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"b":"a"},
+            "locked":{"narHash":"sha256-a","path":"/a","type":"path"},
+            "original":{"path":"/a","type":"path"}}},"root":"root","version":7}"#,
+        4
+    )]
+    #[case(
+        r#"{"nodes":{"root":{"inputs":{"a":"a"}},"a":{"parent":[],
+            "locked":{"narHash":"sha256-a","path":"/a","type":"path"},
+            "original":{"path":"./a","type":"path"}}},"root":"root","version":7}"#,
+        6
+    )]
+    #[case(
+        r#"{"nodes":{"root":{"inputs":{"a":"a"}},"a":{}},"root":"root","version":7}"#,
+        4
+    )]
+    fn rejects(#[case] json: &str, #[case] version: u64) {
+        let lock = Lockfile::parse(json).unwrap();
+        assert_matches!(
+            lock.to_version(version),
+            Err(NixUriError::InvalidValue {
+                field: "lockfile",
+                ..
+            })
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! "Why depends" queries: every way the root reaches a given input.
+//!
+//! ```
+//! # use nix_uri::Lockfile;
+//! # let json = r#"{"nodes":{"root":{"inputs":{"hm":"hm"}},
+//! #   "hm":{"inputs":{"nixpkgs":"nixpkgs"},
+//! #     "locked":{"narHash":"sha256-a","owner":"nix-community","repo":"home-manager",
+//! #       "rev":"1111111111111111111111111111111111111111","type":"github"},
+//! #     "original":{"owner":"nix-community","repo":"home-manager","type":"github"}},
+//! #   "nixpkgs":{"locked":{"narHash":"sha256-b","owner":"NixOS","repo":"nixpkgs",
+//! #       "rev":"2222222222222222222222222222222222222222","type":"github"},
+//! #     "original":{"owner":"NixOS","repo":"nixpkgs","type":"github"}}},
+//! #   "root":"root","version":7}"#;
+//! let lock = Lockfile::parse(json).unwrap();
+//! let paths = lock
+//!     .why_depends(|locked| {
+//!         locked.owner().is_some_and(|o| o.eq_ignore_ascii_case("nixos"))
+//!             && locked.repo() == Some("nixpkgs")
+//!     })
+//!     .unwrap();
+//! assert_eq!(paths[0].direct_input(), "hm");
+//! assert_eq!(paths[0].to_string(), "hm → nixpkgs");
+//! ```
+
+use std::fmt::Display;
+
+use crate::{
+    error::NixUriResult,
+    flakeref::FlakeRef,
+    lockfile::{InputPath, LockNode, Lockfile, NodeInput, display_path},
+};
+
+/// One way the root reaches a matching node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DependencyPath {
+    /// The inputs walked from the root, in order. Never empty.
+    pub hops: Vec<Hop>,
+}
+
+/// One input along a [`DependencyPath`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Hop {
+    /// The input name within the previous node.
+    pub name: String,
+    /// Key of the node the input leads to.
+    pub node: String,
+    /// The input path the input follows, when it got to `node` that way.
+    pub follows: Option<InputPath>,
+}
+
+impl DependencyPath {
+    /// The root's input the path starts with: the direct dependency that
+    /// pulled the match in.
+    pub fn direct_input(&self) -> &str {
+        self.hops.first().map_or("", |hop| hop.name.as_str())
+    }
+
+    /// Key of the matching node the path ends at.
+    pub fn node(&self) -> &str {
+        self.hops.last().map_or("", |hop| hop.node.as_str())
+    }
+
+    /// The input path the hops spell (`["darwin", "nixpkgs"]`).
+    pub fn input_path(&self) -> InputPath {
+        self.hops.iter().map(|hop| hop.name.clone()).collect()
+    }
+}
+
+impl Display for DependencyPath {
+    /// `darwin → nixpkgs (follows home-manager/nixpkgs)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, hop) in self.hops.iter().enumerate() {
+            if i > 0 {
+                write!(f, " → ")?;
+            }
+            write!(f, "{}", hop.name)?;
+            if let Some(follows) = &hop.follows {
+                write!(f, " (follows {})", display_path(follows))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Lockfile {
+    /// Every path from the root to a node whose `locked` ref satisfies
+    /// `matches`, in input-name order.
+    ///
+    /// Inputs that `follows` are walked to the node they resolve to and
+    /// annotated with the path they follow, but not below it: that node's
+    /// own inputs are reported where it is declared, so the number of
+    /// paths stays linear in the follows a lock file uses. The walk
+    /// continues below a match, and never re-enters a node already on the
+    /// current path. A broken graph errors as
+    /// [`Lockfile::effective_inputs`].
+    pub fn why_depends(
+        &self,
+        matches: impl Fn(&FlakeRef) -> bool,
+    ) -> NixUriResult<Vec<DependencyPath>> {
+        let root = self.existing_node(&self.root, &[])?;
+        let mut query = Query {
+            lock: self,
+            matches: &matches,
+            hops: Vec::new(),
+            on_path: vec![self.root.as_str()],
+            found: Vec::new(),
+        };
+        query.walk(root)?;
+        Ok(query.found)
+    }
+}
+
+struct Query<'a, F> {
+    lock: &'a Lockfile,
+    matches: &'a F,
+    hops: Vec<Hop>,
+    on_path: Vec<&'a str>,
+    found: Vec<DependencyPath>,
+}
+
+impl<'a, F: Fn(&FlakeRef) -> bool> Query<'a, F> {
+    fn walk(&mut self, node: &'a LockNode) -> NixUriResult<()> {
+        for (name, input) in &node.inputs {
+            let mut path: InputPath = self.hops.iter().map(|hop| hop.name.clone()).collect();
+            path.push(name.clone());
+            let (key, follows) = match input {
+                NodeInput::Node(key) => (key.as_str(), None),
+                NodeInput::Follows(target) => {
+                    (self.lock.resolve_input(target)?, Some(target.clone()))
+                }
+            };
+            let child = self.lock.existing_node(key, &path)?;
+            if self.on_path.contains(&key) {
+                continue;
+            }
+            let descend = follows.is_none();
+            self.hops.push(Hop {
+                name: name.clone(),
+                node: key.to_string(),
+                follows,
+            });
+            if child.locked.as_ref().is_some_and(self.matches) {
+                self.found.push(DependencyPath {
+                    hops: self.hops.clone(),
+                });
+            }
+            if descend {
+                self.on_path.push(key);
+                self.walk(child)?;
+                self.on_path.pop();
+            }
+            self.hops.pop();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+
+    use super::*;
+    use crate::{error::NixUriError, lockfile::fixtures};
+
+    fn repo(name: &'static str) -> impl Fn(&FlakeRef) -> bool {
+        move |locked| locked.repo() == Some(name)
+    }
+
+    #[test]
+    fn finds_every_path_with_follows_annotated() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let paths = lock.why_depends(repo("nixpkgs")).unwrap();
+        let rendered: Vec<_> = paths.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            rendered,
+            [
+                "darwin → nixpkgs (follows home-manager/nixpkgs)",
+                "home-manager → nixpkgs (follows nixpkgs)",
+                "nixpkgs",
+            ]
+        );
+        assert!(paths.iter().all(|path| path.node() == "nixpkgs"));
+        assert_eq!(paths[0].direct_input(), "darwin");
+        assert_eq!(paths[0].input_path(), ["darwin", "nixpkgs"]);
+    }
+
+    #[test]
+    fn walks_below_direct_inputs() {
+        let lock = Lockfile::parse(fixtures::NESTED).unwrap();
+        let paths = lock.why_depends(repo("default")).unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(
+            paths[0].hops,
+            [
+                Hop {
+                    name: "utils".into(),
+                    node: "utils".into(),
+                    follows: None,
+                },
+                Hop {
+                    name: "systems".into(),
+                    node: "systems".into(),
+                    follows: None,
+                },
+            ]
+        );
+        assert!(lock.why_depends(repo("absent")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn follows_targets_are_not_walked_again() {
+        // A diamond: `a` and `b` both follow the root's `nixpkgs`, which
+        // has an input of its own. `lib` is reached once, not once per
+        // follows.
+        let node = |name: &str, inputs: &str| {
+            format!(
+                r#""{name}":{{"inputs":{{{inputs}}},
+                    "locked":{{"type":"path","path":"/{name}","narHash":"sha256-{name}"}},
+                    "original":{{"type":"path","path":"/{name}"}}}}"#
+            )
+        };
+        let lock = Lockfile::parse(&format!(
+            r#"{{"nodes":{{"root":{{"inputs":{{"a":"a","b":"b","nixpkgs":"nixpkgs"}}}},
+                {},{},{},{}}},"root":"root","version":7}}"#,
+            node("a", r#""nixpkgs":["nixpkgs"]"#),
+            node("b", r#""nixpkgs":["a","nixpkgs"]"#),
+            node("nixpkgs", r#""lib":"lib""#),
+            node("lib", ""),
+        ))
+        .unwrap();
+        let rendered = |paths: Vec<DependencyPath>| -> Vec<String> {
+            paths.iter().map(ToString::to_string).collect()
+        };
+        assert_eq!(
+            rendered(
+                lock.why_depends(|locked| locked.local_path() == Some("/lib"))
+                    .unwrap()
+            ),
+            ["nixpkgs → lib"]
+        );
+        assert_eq!(
+            rendered(
+                lock.why_depends(|locked| locked.local_path() == Some("/nixpkgs"))
+                    .unwrap()
+            ),
+            [
+                "a → nixpkgs (follows nixpkgs)",
+                "b → nixpkgs (follows a/nixpkgs)",
+                "nixpkgs",
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_back_to_the_root_end_the_walk() {
+        let lock = Lockfile::parse(
+            r#"{"nodes":{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"parent":[]},
+                "locked":{"type":"path","path":"/a","narHash":"sha256-a"},
+                "original":{"type":"path","path":"/a"}}},"root":"root","version":7}"#,
+        )
+        .unwrap();
+        let paths = lock.why_depends(|_| true).unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].to_string(), "a");
+    }
+
+    #[test]
+    fn broken_graphs_are_errors() {
+        let lock = Lockfile::parse(
+            r#"{"nodes":{"root":{"inputs":{"a":"a"}},"a":{"inputs":{"b":"gone"}}},
+                "root":"root","version":7}"#,
+        )
+        .unwrap();
+        assert_matches!(
+            lock.why_depends(|_| true),
+            Err(NixUriError::Lock { input, .. }) => assert_eq!(input, "a/b")
+        );
+    }
+}
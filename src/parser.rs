@@ -8,10 +8,14 @@ use winnow::{
 use crate::{
     error::{NixUriError, NixUriResult, run_partial},
     flakeref::{
-        FlakeRef, FlakeRefType, GitForge, LocationParamKeys, LocationParameters, RefLocation,
+        FlakeRef, FlakeRefType, GitForge, LocationParamKeys, LocationParameters, RefLocation, Rev,
         TransportLayer, encoding,
         location_params::ParamRefRev,
-        validators::{looks_like_rev, parse_bool_param, validated_host_name, validated_ref_name},
+        parse_options::ParseOptions,
+        validators::{
+            check_ref_format, looks_like_rev, parse_bool_param, validated_host_name,
+            validated_ref_name,
+        },
     },
 };
 
@@ -74,6 +78,10 @@ pub(crate) fn parse_params<'i>(
 /// `shallow`) carries a value outside Nix's URL-time coercion, which
 /// is strictly `value == "1"` (i.e. `"1"` or `"0"`; `"true"` /
 /// `"false"` are rejected so the parse-time diagnostic is preserved).
+///
+/// With `verifyCommit=1`, the key parameters (`keytype`, `publicKey`,
+/// `publicKeys`) are validated as well; see
+/// [`LocationParameters::verification_keys`].
 pub(crate) fn route_location_params(
     values: RawParamValues<'_>,
 ) -> Result<(LocationParameters, ParamRefRev), NixUriError> {
@@ -125,6 +133,7 @@ pub(crate) fn route_location_params(
             }
         }
     }
+    params.validate_verification_keys()?;
     Ok((params, ref_rev))
 }
 
@@ -138,7 +147,9 @@ pub(crate) fn route_location_params(
 /// and anything else into `ref_`, but the query side has no such
 /// classifier and would otherwise accept `?rev=main` verbatim. Returns
 /// [`NixUriError::InvalidValue`] `{ field: "rev", .. }` for values that
-/// are not a 40- or 64-character hex string.
+/// are not a 40- or 64-character hex string. With `short_revs` (see
+/// [`ParseOptions::with_short_revs`]) an abbreviated hex prefix of at
+/// least four digits is accepted as well.
 ///
 /// Validates `?ref=` against
 /// [`validate_ref_name`](crate::flakeref::validators::validate_ref_name),
@@ -158,12 +169,15 @@ pub(crate) fn route_location_params(
 pub(crate) fn apply_param_ref_rev(
     flake_ref: &mut FlakeRef,
     ref_rev: ParamRefRev,
+    short_revs: bool,
 ) -> Result<(), NixUriError> {
     if ref_rev.r#ref.is_none() && ref_rev.rev.is_none() {
         return Ok(());
     }
     if let Some(rev) = ref_rev.rev.as_deref() {
-        if !looks_like_rev(rev) {
+        if short_revs {
+            Rev::parse(rev)?;
+        } else if !looks_like_rev(rev) {
             return Err(NixUriError::InvalidValue {
                 field: "rev",
                 reason: "expected 40-hex (SHA-1) or 64-hex (SHA-256) commit".to_string(),
@@ -332,6 +346,13 @@ pub(crate) fn parse_scp_style(input: &str) -> Option<String> {
 }
 
 pub(crate) fn parse_nix_uri(input: &str) -> NixUriResult<FlakeRef> {
+    parse_nix_uri_with(input, &ParseOptions::default())
+}
+
+/// [`parse_nix_uri`] with caller-supplied [`ParseOptions`]. Aliases are
+/// expanded before the SCP-style rewrite so a registered alias always wins
+/// over the host-like `<host>:<path>` heuristic.
+pub(crate) fn parse_nix_uri_with(input: &str, options: &ParseOptions) -> NixUriResult<FlakeRef> {
     // Basic sanity checks.
     if input.trim().is_empty()
         || (input.trim() == "/")
@@ -346,6 +367,9 @@ pub(crate) fn parse_nix_uri(input: &str) -> NixUriResult<FlakeRef> {
         return Err(NixUriError::InvalidUrl(input.into()));
     }
 
+    let expanded = options.expand_alias(input);
+    let input = expanded.as_deref().unwrap_or(input);
+
     let rewritten = parse_scp_style(input);
     let input = rewritten.as_deref().unwrap_or(input);
 
@@ -363,13 +387,25 @@ pub(crate) fn parse_nix_uri(input: &str) -> NixUriResult<FlakeRef> {
     };
 
     let (_, (type_prefix, raw_values)) = run_partial(input, head, parse_params)?;
-    let mut flake_ref = FlakeRef::default().with_kind(FlakeRefType::parse_type(type_prefix)?);
+    let mut flake_ref =
+        FlakeRef::default().with_kind(FlakeRefType::parse_type_with(type_prefix, options)?);
     if let Some(values) = raw_values {
         let (params, ref_rev) = route_location_params(values)?;
         flake_ref.replace_params(params);
-        apply_param_ref_rev(&mut flake_ref, ref_rev)?;
+        apply_param_ref_rev(&mut flake_ref, ref_rev, options.short_revs())?;
     }
     validate_gitforge_ref_rev_exclusion(&flake_ref)?;
+    if options.strict_refs() {
+        if let Some(ref_) = flake_ref.ref_() {
+            check_ref_format(ref_).map_err(|violation| NixUriError::InvalidValue {
+                field: "ref",
+                reason: violation.to_string(),
+            })?;
+        }
+    }
+    if options.normalized_dirs() {
+        flake_ref = flake_ref.with_normalized_dir()?;
+    }
     flake_ref.set_fragment(fragment);
 
     Ok(flake_ref)
@@ -0,0 +1,494 @@
+//! Source policies: which flake inputs a project accepts.
+//!
+//! A [`Policy`] allowlists and denylists sources by matching fields of a
+//! [`FlakeRef`] against glob patterns, and can require that inputs from a
+//! given source track particular branches. It derives `Deserialize`, so it
+//! loads from JSON ([`Policy::from_json`]) or any other serde format, for
+//! example TOML:
+//!
+//! ```toml
+//! [[allow]]
+//! platform = "github"
+//! owner = "NixOS"
+//!
+//! [[deny]]
+//! transport = "http"
+//!
+//! [[branches]]
+//! match = { owner = "NixOS", repo = "nixpkgs" }
+//! branches = ["nixos-*", "nixpkgs-unstable"]
+//! ```
+//!
+//! Patterns support `*` (any run of characters) and `?` (one character).
+//! Branch patterns are case-sensitive and match a ref's short name
+//! (`refs/heads/nixos-24.05` as `nixos-24.05`); every other field compares
+//! ASCII-case-insensitively, as forges and hosts do.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{FlakeRef, FlakeRefType, RefName},
+};
+
+/// A source policy. The default policy allows everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Policy {
+    /// When non-empty, a ref must match at least one of these.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<SourceMatcher>,
+    /// A ref matching any of these is rejected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<SourceMatcher>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<BranchRule>,
+}
+
+/// Glob patterns over the fields of a ref. A matcher matches when every
+/// pattern it sets matches; a field the ref does not have (an owner on a
+/// `path:` ref) never matches. The empty matcher matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SourceMatcher {
+    /// The explicit URL scheme: `github`, `gitlab`, `sourcehut`, `flake`
+    /// (indirect), `path`, or `<type>+<transport>` such as `git+https`
+    /// or `tarball+https`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    /// The git forge of `github:`, `gitlab:` and `sourcehut:` refs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// The host: a forge's domain (honouring `?host=`) or a URL's
+    /// authority, without any port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// `http`, `https`, `ssh` or `file`, for URL refs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<String>,
+    /// The branch or tag (case-sensitive), by its short name.
+    #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub ref_: Option<String>,
+}
+
+/// Refs matching `matches` must track a branch matching one of
+/// `branches`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct BranchRule {
+    #[serde(rename = "match")]
+    pub matches: SourceMatcher,
+    /// Branch patterns (case-sensitive).
+    pub branches: Vec<String>,
+}
+
+/// A way a ref breaks a [`Policy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// The ref matches `deny[rule]`.
+    Denied { rule: usize },
+    /// The policy has an allowlist and the ref matches none of it.
+    NotAllowed,
+    /// The ref matches `branches[rule]` but tracks another branch, or
+    /// none (`ref_` is `None`).
+    Branch {
+        rule: usize,
+        ref_: Option<String>,
+        allowed: Vec<String>,
+    },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Denied { rule } => write!(f, "source is denied by deny rule {rule}"),
+            Self::NotAllowed => write!(f, "source matches no allow rule"),
+            Self::Branch {
+                rule,
+                ref_,
+                allowed,
+            } => {
+                match ref_ {
+                    Some(ref_) => write!(f, "tracks `{ref_}`")?,
+                    None => write!(f, "tracks no branch")?,
+                }
+                write!(f, ", but branch rule {rule} requires ")?;
+                for (i, branch) in allowed.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " or ")?;
+                    }
+                    write!(f, "`{branch}`")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_allow(mut self, matcher: SourceMatcher) -> Self {
+        self.allow.push(matcher);
+        self
+    }
+
+    pub fn with_deny(mut self, matcher: SourceMatcher) -> Self {
+        self.deny.push(matcher);
+        self
+    }
+
+    pub fn with_branch_rule(mut self, rule: BranchRule) -> Self {
+        self.branches.push(rule);
+        self
+    }
+
+    /// Parse a policy from JSON. Malformed JSON and unknown fields surface
+    /// [`NixUriError::InvalidValue`] with field `policy`.
+    pub fn from_json(json: &str) -> NixUriResult<Self> {
+        serde_json::from_str(json).map_err(|e| NixUriError::InvalidValue {
+            field: "policy",
+            reason: e.to_string(),
+        })
+    }
+
+    /// Every way `flake_ref` breaks the policy; empty when it complies.
+    ///
+    /// Branch rules look at the ref as written, so check the `original`
+    /// of a lock node (its `locked` ref is pinned to a rev instead).
+    pub fn check(&self, flake_ref: &FlakeRef) -> Vec<Violation> {
+        let mut out: Vec<_> = self
+            .deny
+            .iter()
+            .enumerate()
+            .filter(|(_, matcher)| matcher.matches(flake_ref))
+            .map(|(rule, _)| Violation::Denied { rule })
+            .collect();
+        if !self.allow.is_empty() && !self.allow.iter().any(|m| m.matches(flake_ref)) {
+            out.push(Violation::NotAllowed);
+        }
+        for (rule, branch_rule) in self.branches.iter().enumerate() {
+            if !branch_rule.matches.matches(flake_ref) {
+                continue;
+            }
+            if !short_ref(flake_ref).is_some_and(|ref_| {
+                branch_rule
+                    .branches
+                    .iter()
+                    .any(|pattern| glob(pattern, &ref_, false))
+            }) {
+                out.push(Violation::Branch {
+                    rule,
+                    ref_: flake_ref.ref_().map(str::to_string),
+                    allowed: branch_rule.branches.clone(),
+                });
+            }
+        }
+        out
+    }
+}
+
+impl SourceMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_scheme(mut self, pattern: impl Into<String>) -> Self {
+        self.scheme = Some(pattern.into());
+        self
+    }
+
+    pub fn with_platform(mut self, pattern: impl Into<String>) -> Self {
+        self.platform = Some(pattern.into());
+        self
+    }
+
+    pub fn with_owner(mut self, pattern: impl Into<String>) -> Self {
+        self.owner = Some(pattern.into());
+        self
+    }
+
+    pub fn with_repo(mut self, pattern: impl Into<String>) -> Self {
+        self.repo = Some(pattern.into());
+        self
+    }
+
+    pub fn with_host(mut self, pattern: impl Into<String>) -> Self {
+        self.host = Some(pattern.into());
+        self
+    }
+
+    pub fn with_transport(mut self, pattern: impl Into<String>) -> Self {
+        self.transport = Some(pattern.into());
+        self
+    }
+
+    pub fn with_ref(mut self, pattern: impl Into<String>) -> Self {
+        self.ref_ = Some(pattern.into());
+        self
+    }
+
+    /// Whether every pattern set on the matcher matches `flake_ref`.
+    pub fn matches(&self, flake_ref: &FlakeRef) -> bool {
+        let forge = flake_ref.forge_identity();
+        let transport = match flake_ref.kind() {
+            FlakeRefType::Resource(res) => res.transport_type.as_ref().map(ToString::to_string),
+            _ => None,
+        };
+        let fields = [
+            (&self.scheme, Some(scheme(flake_ref)), true),
+            (
+                &self.platform,
+                forge.as_ref().map(|f| f.platform.to_string()),
+                true,
+            ),
+            (&self.owner, flake_ref.owner().map(str::to_string), true),
+            (&self.repo, flake_ref.repo().map(str::to_string), true),
+            (&self.host, host(flake_ref), true),
+            (&self.transport, transport, true),
+            (&self.ref_, short_ref(flake_ref), false),
+        ];
+        fields.into_iter().all(|(pattern, value, fold_case)| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| value.is_some_and(|value| glob(pattern, &value, fold_case)))
+        })
+    }
+}
+
+impl BranchRule {
+    pub fn new(matches: SourceMatcher, branches: Vec<String>) -> Self {
+        Self { matches, branches }
+    }
+}
+
+/// See [`SourceMatcher::scheme`].
+fn scheme(flake_ref: &FlakeRef) -> String {
+    match flake_ref.kind() {
+        FlakeRefType::GitForge(forge) => forge.platform.to_string(),
+        FlakeRefType::Indirect { .. } => "flake".into(),
+        FlakeRefType::Path { .. } => "path".into(),
+        FlakeRefType::Resource(res) => match &res.transport_type {
+            Some(transport) => format!("{}+{transport}", res.res_type),
+            None => res.res_type.to_string(),
+        },
+    }
+}
+
+/// The ref without a `refs/heads/` or `refs/tags/` prefix.
+fn short_ref(flake_ref: &FlakeRef) -> Option<String> {
+    flake_ref.ref_name().map(|name| match name {
+        RefName::Qualified(qualified) => qualified.short_name(),
+        other => other.to_string(),
+    })
+}
+
+/// A forge's domain, or the authority of a URL ref, without user info or
+/// port: a rule on `example.com` must not be sidestepped by spelling out
+/// `example.com:443`.
+fn host(flake_ref: &FlakeRef) -> Option<String> {
+    let authority = match (flake_ref.domain(), flake_ref.kind()) {
+        (Some(domain), _) => domain,
+        (None, FlakeRefType::Resource(res)) if res.transport_type.is_some() => {
+            let authority = res.location.split('/').next()?;
+            authority.rsplit_once('@').map_or(authority, |(_, h)| h)
+        }
+        _ => return None,
+    };
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => authority,
+    };
+    let host = host.trim_end_matches('.');
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// Match `text` against a pattern of literal characters, `*` and `?`.
+fn glob(pattern: &str, text: &str, fold_case: bool) -> bool {
+    let (pattern, text) = if fold_case {
+        (pattern.to_ascii_lowercase(), text.to_ascii_lowercase())
+    } else {
+        (pattern.to_string(), text.to_string())
+    };
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it is matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+
+    fn flake_ref(uri: &str) -> FlakeRef {
+        uri.parse().unwrap()
+    }
+
+    #[rstest]
+    #[case("nixos-*", "nixos-24.05", true)]
+    #[case("nixos-*", "nixpkgs-unstable", false)]
+    #[case("*-unstable", "nixos-unstable", true)]
+    #[case("a*b*c", "aXXbYYc", true)]
+    #[case("a*b*c", "aXXbYY", false)]
+    #[case("v?.?", "v1.2", true)]
+    #[case("*", "", true)]
+    #[case("", "x", false)]
+    fn globs(#[case] pattern: &str, #[case] text: &str, #[case] expected: bool) {
+        assert_eq!(glob(pattern, text, false), expected);
+    }
+
+    #[rstest]
+    #[case(SourceMatcher::new().with_platform("github").with_owner("nixos"), "github:NixOS/nixpkgs", true)]
+    #[case(SourceMatcher::new().with_owner("nix-*"), "github:nix-community/home-manager", true)]
+    #[case(SourceMatcher::new().with_owner("NixOS"), "path:/srv/flake", false)]
+    #[case(SourceMatcher::new().with_scheme("git+*"), "git+ssh://git@example.com/r", true)]
+    #[case(SourceMatcher::new().with_scheme("github"), "git+https://github.com/o/r", false)]
+    #[case(SourceMatcher::new().with_host("github.com"), "git+https://github.com/o/r", true)]
+    #[case(SourceMatcher::new().with_host("*.corp"), "gitlab:g/r?host=git.corp", true)]
+    #[case(SourceMatcher::new().with_host("example.com"), "git+ssh://git@example.com/r", true)]
+    #[case(SourceMatcher::new().with_host("example.com"), "https://example.com/x.tar.gz", true)]
+    #[case(SourceMatcher::new().with_host("example.com"), "https://example.com:443/x.tar.gz", true)]
+    #[case(SourceMatcher::new().with_host("example.com"), "git+http://example.com:8080/o/r", true)]
+    #[case(SourceMatcher::new().with_host("example.com"), "file+https://u@example.com.:8443/x", true)]
+    #[case(SourceMatcher::new().with_host("example.com:*"), "https://example.com:443/x.tar.gz", false)]
+    #[case(SourceMatcher::new().with_transport("http"), "https://example.com/x.tar.gz", false)]
+    #[case(SourceMatcher::new().with_transport("http"), "http://example.com/x.tar.gz", true)]
+    #[case(SourceMatcher::new().with_ref("release-*"), "github:o/r/release-1", true)]
+    #[case(SourceMatcher::new().with_ref("Release-*"), "github:o/r/release-1", false)]
+    #[case(SourceMatcher::new().with_ref("release-*"), "git+https://h/r?ref=refs/tags/release-1", true)]
+    #[case(SourceMatcher::new().with_ref("refs/*"), "git+https://h/r?ref=refs/heads/main", false)]
+    #[case(SourceMatcher::new(), "flake:nixpkgs", true)]
+    fn matchers(#[case] matcher: SourceMatcher, #[case] uri: &str, #[case] expected: bool) {
+        assert_eq!(matcher.matches(&flake_ref(uri)), expected);
+    }
+
+    fn nixpkgs_policy() -> Policy {
+        Policy::from_json(
+            r#"{
+                "allow": [{"platform": "github"}, {"scheme": "path"}],
+                "deny": [{"owner": "evil-*"}],
+                "branches": [{
+                    "match": {"owner": "NixOS", "repo": "nixpkgs"},
+                    "branches": ["nixos-*", "nixpkgs-unstable"]
+                }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case("github:NixOS/nixpkgs/nixos-24.05")]
+    #[case("github:nixos/nixpkgs/nixpkgs-unstable")]
+    #[case("github:NixOS/nixpkgs?ref=refs/heads/nixos-24.05")]
+    #[case("github:numtide/flake-utils")]
+    #[case("path:./sub")]
+    fn complies(#[case] uri: &str) {
+        assert_eq!(nixpkgs_policy().check(&flake_ref(uri)), []);
+    }
+
+    #[test]
+    fn reports_violations() {
+        let policy = nixpkgs_policy();
+        assert_eq!(
+            policy.check(&flake_ref("github:evil-corp/x")),
+            [Violation::Denied { rule: 0 }]
+        );
+        assert_eq!(
+            policy.check(&flake_ref("git+https://example.com/r")),
+            [Violation::NotAllowed]
+        );
+        let violations = policy.check(&flake_ref("github:NixOS/nixpkgs/master"));
+        assert_eq!(
+            violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["tracks `master`, but branch rule 0 requires `nixos-*` or `nixpkgs-unstable`"]
+        );
+        assert_matches!(
+            &policy.check(&flake_ref("github:NixOS/nixpkgs"))[..],
+            [Violation::Branch { ref_: None, .. }]
+        );
+    }
+
+    #[rstest]
+    #[case("https://evil.com/x.tar.gz")]
+    #[case("https://evil.com:443/x.tar.gz")]
+    #[case("https://user@EVIL.com:8443/x.tar.gz")]
+    #[case("git+https://evil.com:8443/o/r")]
+    fn deny_rules_ignore_ports(#[case] uri: &str) {
+        let policy = Policy::new().with_deny(SourceMatcher::new().with_host("evil.com"));
+        assert_eq!(
+            policy.check(&flake_ref(uri)),
+            [Violation::Denied { rule: 0 }]
+        );
+    }
+
+    #[test]
+    fn builders_match_json() {
+        let built = Policy::new()
+            .with_allow(SourceMatcher::new().with_platform("github"))
+            .with_allow(SourceMatcher::new().with_scheme("path"))
+            .with_deny(SourceMatcher::new().with_owner("evil-*"))
+            .with_branch_rule(BranchRule::new(
+                SourceMatcher::new()
+                    .with_owner("NixOS")
+                    .with_repo("nixpkgs"),
+                vec!["nixos-*".into(), "nixpkgs-unstable".into()],
+            ));
+        assert_eq!(built, nixpkgs_policy());
+        let json = serde_json::to_string(&built).unwrap();
+        assert_eq!(Policy::from_json(&json).unwrap(), built);
+    }
+
+    #[rstest]
+    #[case(r#"{"allow": [{"owners": "x"}]}"#)]
+    #[case(r#"{"branches": [{"match": {}}]}"#)]
+    #[case(r#"{"deny": {"owner": "x"}}"#)]
+    fn rejects(#[case] json: &str) {
+        assert_matches!(
+            Policy::from_json(json),
+            Err(NixUriError::InvalidValue {
+                field: "policy",
+                ..
+            })
+        );
+    }
+}
@@ -0,0 +1,597 @@
+//! Flake registries: resolving `flake:` (indirect) refs.
+//!
+//! A registry maps indirect refs such as `flake:nixpkgs` to concrete ones.
+//! [`Registry`] reads and writes Nix's `flake-registry.json` (version 2)
+//! and resolves with Nix's lookup rules; [`RegistryLoader`] fetches the
+//! global registry through an [`HttpClient`] and caches it on disk with
+//! `tarball-ttl` semantics.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{NixUriError, NixUriResult},
+    flakeref::{Attrs, FlakeRef, FlakeRefType},
+    http::{HttpClient, HttpRequest},
+};
+
+/// Where Nix fetches the global registry from by default (the
+/// `flake-registry` setting).
+pub const GLOBAL_REGISTRY_URL: &str = "https://channels.nixos.org/flake-registry.json";
+
+/// Nix's default `tarball-ttl`: how long a fetched registry is used
+/// without asking the server again.
+pub const DEFAULT_TARBALL_TTL: Duration = Duration::from_secs(3600);
+
+/// Indirections followed before [`resolve_indirect`] gives up, as in Nix.
+const MAX_INDIRECTIONS: usize = 100;
+
+/// One registry mapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RegistryEntry {
+    pub from: FlakeRef,
+    pub to: FlakeRef,
+    /// Match `from` exactly. Otherwise a `ref` or `rev` on the looked-up
+    /// ref is ignored for matching and carried over onto `to`.
+    pub exact: bool,
+}
+
+impl RegistryEntry {
+    pub fn new(from: FlakeRef, to: FlakeRef) -> Self {
+        Self {
+            from,
+            to,
+            exact: false,
+        }
+    }
+
+    pub fn with_exact(mut self, exact: bool) -> Self {
+        self.exact = exact;
+        self
+    }
+}
+
+/// A flake registry: an ordered list of [`RegistryEntry`]s, first match
+/// wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Registry {
+    pub entries: Vec<RegistryEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawRegistry {
+    flakes: Vec<RawEntry>,
+    version: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawEntry {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    exact: bool,
+    from: Attrs,
+    to: Attrs,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_entry(mut self, entry: RegistryEntry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Parse a `flake-registry.json` document. Only version 2, the one
+    /// Nix reads and writes, is accepted; a malformed document or entry
+    /// surfaces [`NixUriError::InvalidValue`] with field `registry`.
+    pub fn parse(json: &str) -> NixUriResult<Self> {
+        let invalid = |reason: String| NixUriError::InvalidValue {
+            field: "registry",
+            reason,
+        };
+        let raw: RawRegistry = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+        if raw.version != 2 {
+            return Err(invalid(format!(
+                "unsupported registry version {}",
+                raw.version
+            )));
+        }
+        let entries = raw
+            .flakes
+            .iter()
+            .map(|entry| {
+                Ok(RegistryEntry {
+                    from: FlakeRef::from_attrs(&entry.from)?,
+                    to: FlakeRef::from_attrs(&entry.to)?,
+                    exact: entry.exact,
+                })
+            })
+            .collect::<NixUriResult<_>>()
+            .map_err(|e| invalid(format!("invalid entry: {e}")))?;
+        Ok(Self { entries })
+    }
+
+    /// Render as a version 2 `flake-registry.json` document.
+    pub fn to_json(&self) -> String {
+        let raw = RawRegistry {
+            flakes: self
+                .entries
+                .iter()
+                .map(|entry| RawEntry {
+                    exact: entry.exact,
+                    from: entry.from.to_attrs(),
+                    to: entry.to.to_attrs(),
+                })
+                .collect(),
+            version: 2,
+        };
+        serde_json::to_string_pretty(&raw).unwrap_or_default()
+    }
+
+    /// Resolve `flake_ref` through this registry alone; see
+    /// [`resolve_indirect`].
+    pub fn resolve(&self, flake_ref: &FlakeRef) -> NixUriResult<FlakeRef> {
+        resolve_indirect(flake_ref, &[self])
+    }
+}
+
+/// Resolve an indirect ref through `registries`, searched in order (Nix
+/// consults the flag, user, system and global registries in that order).
+///
+/// Follows Nix's lookup: a non-`exact` entry matches when its `from`
+/// equals the ref with `ref` and `rev` removed, and the ref's `ref`/`rev`
+/// are then applied to `to`; lookups repeat while the result is still
+/// indirect. A `dir` on the entry's `to` replaces the ref's own. Direct
+/// refs come back unchanged. An indirect ref no registry resolves
+/// surfaces [`NixUriError::Resolve`].
+pub fn resolve_indirect(flake_ref: &FlakeRef, registries: &[&Registry]) -> NixUriResult<FlakeRef> {
+    let fail = |reason: String| NixUriError::Resolve {
+        input: flake_ref.to_string(),
+        reason,
+    };
+    let mut current = flake_ref.clone();
+    for _ in 0..MAX_INDIRECTIONS {
+        if !matches!(current.kind(), FlakeRefType::Indirect { .. }) {
+            return Ok(current);
+        }
+        let wanted = input_attrs(&current);
+        let mut pinless = wanted.clone();
+        pinless.remove("ref");
+        pinless.remove("rev");
+        let Some(entry) = registries
+            .iter()
+            .flat_map(|registry| &registry.entries)
+            .find(|entry| {
+                let from = input_attrs(&entry.from);
+                from == wanted || (!entry.exact && from == pinless)
+            })
+        else {
+            return Err(fail("cannot find flake in the flake registries".into()));
+        };
+        current = apply_overrides(entry, &current).map_err(|e| fail(e.to_string()))?;
+    }
+    Err(fail(format!(
+        "more than {MAX_INDIRECTIONS} registry indirections"
+    )))
+}
+
+/// The attributes that identify an input: everything but `dir`, which
+/// selects a subflake rather than a source.
+fn input_attrs(flake_ref: &FlakeRef) -> Attrs {
+    let mut attrs = flake_ref.to_attrs();
+    attrs.remove("dir");
+    attrs
+}
+
+/// The entry's `to` with `from`'s `dir` (unless `to` sets one) and
+/// fragment carried over, plus its `ref` and `rev` for non-`exact`
+/// entries.
+fn apply_overrides(entry: &RegistryEntry, from: &FlakeRef) -> NixUriResult<FlakeRef> {
+    let mut resolved = entry.to.clone();
+    if entry.exact {
+        // The entry named the ref and rev it maps; nothing to carry over.
+    } else if matches!(resolved.kind(), FlakeRefType::GitForge(_)) {
+        match (from.ref_(), from.rev()) {
+            (Some(_), Some(_)) => {
+                return Err(NixUriError::FieldConflict {
+                    left: "ref",
+                    right: "rev",
+                });
+            }
+            (Some(ref_), None) => resolved = resolved.without_pin().with_ref(Some(ref_.into())),
+            (None, Some(rev)) => resolved = resolved.pin_to_rev(rev.into()),
+            (None, None) => {}
+        }
+    } else {
+        if let Some(ref_) = from.ref_() {
+            resolved.set_ref(Some(ref_.to_string()));
+        }
+        if let Some(rev) = from.rev() {
+            resolved.set_rev(Some(rev.to_string()));
+        }
+    }
+    if resolved.params().dir_value().is_none() {
+        resolved.set_dir(from.params().dir_value().map(str::to_string));
+    }
+    if from.fragment().is_some() {
+        resolved.set_fragment(from.fragment().map(str::to_string));
+    }
+    Ok(resolved)
+}
+
+/// Fetches a registry over HTTP and keeps a copy on disk.
+///
+/// A cached copy younger than the TTL is used without a request; an
+/// older one is revalidated (with `If-None-Match` when the server sent an
+/// `ETag`). When the fetch fails, the stale copy is used instead, as Nix
+/// does offline. A `flake-registry` setting that is a local path
+/// (`/etc/nix/registry.json` or `file://...`) is read directly.
+#[derive(Debug, Clone)]
+pub struct RegistryLoader<C> {
+    client: C,
+    url: String,
+    cache_file: PathBuf,
+    ttl: Duration,
+}
+
+impl<C: HttpClient> RegistryLoader<C> {
+    /// A loader for [`GLOBAL_REGISTRY_URL`], caching at `cache_file` with
+    /// [`DEFAULT_TARBALL_TTL`]. The `ETag` is kept next to it, in
+    /// `<cache_file>.etag`.
+    pub fn new(client: C, cache_file: impl Into<PathBuf>) -> Self {
+        Self {
+            client,
+            url: GLOBAL_REGISTRY_URL.into(),
+            cache_file: cache_file.into(),
+            ttl: DEFAULT_TARBALL_TTL,
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// How long a cached copy is used without revalidating. Zero always
+    /// asks the server.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Load the registry. Surfaces [`NixUriError::Resolve`] when the fetch
+    /// fails and nothing is cached, [`NixUriError::Io`] when the cache
+    /// cannot be written, and [`NixUriError::InvalidValue`] for a malformed
+    /// registry.
+    pub fn load(&self) -> NixUriResult<Registry> {
+        if let Some(path) = local_path(&self.url) {
+            return Registry::parse(&read(path)?);
+        }
+
+        let cached = fs::read_to_string(&self.cache_file)
+            .ok()
+            .and_then(|json| Registry::parse(&json).ok());
+        if let Some(registry) = &cached {
+            if self.cache_age().is_some_and(|age| age < self.ttl) {
+                return Ok(registry.clone());
+            }
+        }
+
+        let etag_file = self.etag_file();
+        let mut request = HttpRequest::new(self.url.as_str());
+        if cached.is_some() {
+            if let Ok(etag) = fs::read_to_string(&etag_file) {
+                request = request.with_header("If-None-Match", etag.trim());
+            }
+        }
+        let fetched = match self.client.get(&request) {
+            Ok(response) if response.status == 304 && cached.is_some() => {
+                fs::File::options()
+                    .append(true)
+                    .open(&self.cache_file)
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+                    .map_err(|source| io_error(&self.cache_file, source))?;
+                return Ok(cached.unwrap_or_default());
+            }
+            Ok(response) if response.is_success() => Ok(response),
+            Ok(response) => Err(format!("HTTP {} from {}", response.status, self.url)),
+            Err(e) => Err(e.to_string()),
+        };
+        let response = match (fetched, cached) {
+            (Ok(response), _) => response,
+            (Err(_), Some(stale)) => return Ok(stale),
+            (Err(reason), None) => {
+                return Err(NixUriError::Resolve {
+                    input: self.url.clone(),
+                    reason,
+                });
+            }
+        };
+
+        let json = String::from_utf8_lossy(&response.body);
+        let registry = Registry::parse(&json)?;
+        write_atomically(&self.cache_file, json.as_bytes())?;
+        match response.header("ETag") {
+            Some(etag) => write_atomically(&etag_file, etag.as_bytes())?,
+            None => match fs::remove_file(&etag_file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(io_error(&etag_file, e));
+                }
+                _ => {}
+            },
+        }
+        Ok(registry)
+    }
+
+    fn cache_age(&self) -> Option<Duration> {
+        let modified = fs::metadata(&self.cache_file).ok()?.modified().ok()?;
+        Some(
+            SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default(),
+        )
+    }
+
+    fn etag_file(&self) -> PathBuf {
+        let mut name = self.cache_file.clone().into_os_string();
+        name.push(".etag");
+        name.into()
+    }
+}
+
+/// The file a `flake-registry` setting names, if it is not a URL.
+fn local_path(setting: &str) -> Option<&Path> {
+    if let Some(path) = setting.strip_prefix("file://") {
+        return Some(Path::new(path));
+    }
+    setting.starts_with('/').then(|| Path::new(setting))
+}
+
+fn read(path: &Path) -> NixUriResult<String> {
+    fs::read_to_string(path).map_err(|source| io_error(path, source))
+}
+
+/// Write through a temporary sibling and rename, so a concurrent reader
+/// never sees a partial file.
+fn write_atomically(path: &Path, contents: &[u8]) -> NixUriResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|source| io_error(parent, source))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, contents).map_err(|source| io_error(&tmp, source))?;
+    fs::rename(&tmp, path).map_err(|source| io_error(path, source))
+}
+
+fn io_error(path: &Path, source: std::io::Error) -> NixUriError {
+    NixUriError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cool_asserts::assert_matches;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::{
+        http::{HttpResponse, recorded::Recorded},
+        test_support::Scratch,
+    };
+
+    const SHA: &str = "b2df4e4e80e04cbb33a350f87717f4bd6140d298";
+
+    const GLOBAL: &str = r#"{
+      "flakes": [
+        {
+          "from": { "id": "nixpkgs", "type": "indirect" },
+          "to": { "owner": "NixOS", "ref": "nixpkgs-unstable", "repo": "nixpkgs", "type": "github" }
+        },
+        {
+          "from": { "id": "templates", "type": "indirect" },
+          "to": { "id": "nix-templates", "type": "indirect" }
+        },
+        {
+          "from": { "id": "nix-templates", "type": "indirect" },
+          "to": { "owner": "NixOS", "repo": "templates", "type": "github", "dir": "sub" }
+        },
+        {
+          "exact": true,
+          "from": { "id": "pinned", "ref": "stable", "type": "indirect" },
+          "to": { "type": "git", "url": "https://example.com/pinned" }
+        }
+      ],
+      "version": 2
+    }"#;
+
+    fn parse(input: &str) -> FlakeRef {
+        input.parse().unwrap()
+    }
+
+    #[rstest]
+    #[case("flake:nixpkgs", "github:NixOS/nixpkgs/nixpkgs-unstable")]
+    #[case("nixpkgs", "github:NixOS/nixpkgs/nixpkgs-unstable")]
+    #[case(
+        "flake:nixpkgs/nixos-24.05#hello",
+        "github:NixOS/nixpkgs/nixos-24.05#hello"
+    )]
+    #[case(
+        "flake:nixpkgs?dir=lib",
+        "github:NixOS/nixpkgs/nixpkgs-unstable?dir=lib"
+    )]
+    #[case("flake:templates", "github:NixOS/templates?dir=sub")]
+    #[case("flake:pinned/stable", "git+https://example.com/pinned")]
+    #[case("github:o/r", "github:o/r")]
+    fn resolves_like_nix(#[case] input: &str, #[case] expected: &str) {
+        let registry = Registry::parse(GLOBAL).unwrap();
+        assert_eq!(
+            registry.resolve(&parse(input)).unwrap().to_string(),
+            expected
+        );
+    }
+
+    #[test]
+    fn rev_replaces_forge_ref() {
+        let registry = Registry::parse(GLOBAL).unwrap();
+        let resolved = registry
+            .resolve(&parse(&format!("flake:nixpkgs/{SHA}")))
+            .unwrap();
+        assert_eq!(resolved.to_string(), format!("github:NixOS/nixpkgs/{SHA}"));
+    }
+
+    #[test]
+    fn earlier_registries_win_and_misses_fail() {
+        let global = Registry::parse(GLOBAL).unwrap();
+        let user = Registry::new().with_entry(RegistryEntry::new(
+            parse("flake:nixpkgs"),
+            parse("path:/src/nixpkgs"),
+        ));
+        assert_eq!(
+            resolve_indirect(&parse("flake:nixpkgs"), &[&user, &global])
+                .unwrap()
+                .to_string(),
+            "path:/src/nixpkgs"
+        );
+        // `exact` entries ignore refs they were not written with.
+        assert_matches!(
+            resolve_indirect(&parse("flake:pinned"), &[&user, &global]),
+            Err(NixUriError::Resolve { .. })
+        );
+        let looping =
+            Registry::new().with_entry(RegistryEntry::new(parse("flake:a"), parse("flake:a")));
+        assert_matches!(
+            looping.resolve(&parse("flake:a")),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("indirections"))
+        );
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let registry = Registry::parse(GLOBAL).unwrap();
+        assert_eq!(Registry::parse(&registry.to_json()).unwrap(), registry);
+        assert!(registry.to_json().contains(r#""exact": true"#));
+    }
+
+    #[rstest]
+    #[case(r#"{"flakes":[],"version":1}"#)]
+    #[case(
+        r#"{"flakes":[{"from":{"type":"indirect"},"to":{"type":"path","path":"/p"}}],"version":2}"#
+    )]
+    #[case("not json")]
+    fn rejects_malformed_registries(#[case] json: &str) {
+        assert_matches!(
+            Registry::parse(json),
+            Err(NixUriError::InvalidValue {
+                field: "registry",
+                ..
+            })
+        );
+    }
+
+    fn cache(scratch: &Scratch) -> PathBuf {
+        scratch.join("cache/flake-registry.json")
+    }
+
+    fn age_cache(scratch: &Scratch, by: Duration) {
+        fs::File::options()
+            .append(true)
+            .open(cache(scratch))
+            .unwrap()
+            .set_modified(SystemTime::now() - by)
+            .unwrap();
+    }
+
+    #[test]
+    fn caches_within_ttl_and_revalidates_after() {
+        let scratch = Scratch::new("registry-ttl");
+        let http = Recorded::default().with(
+            GLOBAL_REGISTRY_URL,
+            HttpResponse::new(200, GLOBAL).with_header("ETag", "\"v1\""),
+        );
+        let loader = RegistryLoader::new(&http, cache(&scratch));
+
+        let first = loader.load().unwrap();
+        assert_eq!(first.entries.len(), 4);
+        assert_eq!(fs::read_to_string(cache(&scratch)).unwrap(), GLOBAL);
+        loader.load().unwrap();
+        assert_eq!(http.requests().len(), 1);
+
+        age_cache(&scratch, DEFAULT_TARBALL_TTL + Duration::from_secs(1));
+        assert_eq!(loader.load().unwrap(), first);
+        let requests = http.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].header("if-none-match"), Some("\"v1\""));
+    }
+
+    #[test]
+    fn not_modified_refreshes_the_cache() {
+        let scratch = Scratch::new("registry-304");
+        RegistryLoader::new(
+            &Recorded::default().json(GLOBAL_REGISTRY_URL, GLOBAL),
+            cache(&scratch),
+        )
+        .load()
+        .unwrap();
+        age_cache(&scratch, Duration::from_secs(7200));
+
+        let http = Recorded::default().with(GLOBAL_REGISTRY_URL, HttpResponse::new(304, ""));
+        let loader = RegistryLoader::new(&http, cache(&scratch));
+        assert_eq!(loader.load().unwrap().entries.len(), 4);
+        loader.load().unwrap();
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[test]
+    fn offline_falls_back_to_stale_copy() {
+        let scratch = Scratch::new("registry-offline");
+        let url = "https://registry.example/flake-registry.json";
+        RegistryLoader::new(&Recorded::default().json(url, GLOBAL), cache(&scratch))
+            .with_url(url)
+            .load()
+            .unwrap();
+
+        let offline = Recorded::default();
+        let loader = RegistryLoader::new(&offline, cache(&scratch))
+            .with_url(url)
+            .with_ttl(Duration::ZERO);
+        assert_eq!(loader.load().unwrap().entries.len(), 4);
+
+        let empty = Scratch::new("registry-offline-empty");
+        assert_matches!(
+            RegistryLoader::new(&offline, cache(&empty)).load(),
+            Err(NixUriError::Resolve { reason, .. }) => assert!(reason.contains("404"))
+        );
+    }
+
+    #[test]
+    fn malformed_download_is_not_cached() {
+        let scratch = Scratch::new("registry-malformed");
+        let http = Recorded::default().json(GLOBAL_REGISTRY_URL, "<html>");
+        assert_matches!(
+            RegistryLoader::new(&http, cache(&scratch)).load(),
+            Err(NixUriError::InvalidValue { .. })
+        );
+        assert!(!cache(&scratch).exists());
+    }
+
+    #[test]
+    fn local_registry_file_is_read_directly() {
+        let scratch = Scratch::new("registry-local");
+        let file = scratch.write("registry.json", GLOBAL);
+        let http = Recorded::default();
+        let loader = RegistryLoader::new(&http, cache(&scratch))
+            .with_url(format!("file://{}", file.display()));
+        assert_eq!(loader.load().unwrap().entries.len(), 4);
+        assert!(http.requests().is_empty());
+    }
+}
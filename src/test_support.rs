@@ -0,0 +1,59 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::{Path, PathBuf};
+
+/// A scratch directory under the system temp dir, removed on drop.
+#[derive(Debug)]
+pub(crate) struct Scratch(PathBuf);
+
+impl Scratch {
+    /// A fresh, empty `nix-uri-<name>-<pid>` directory. Its path is
+    /// canonical (the temp dir's own symlinks, e.g. macOS `/var`, are
+    /// resolved) so expected strings match what filesystem scans report.
+    pub(crate) fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("nix-uri-{name}-{}", std::process::id()));
+        if root.exists() {
+            std::fs::remove_dir_all(&root).unwrap();
+        }
+        std::fs::create_dir_all(&root).unwrap();
+        Self(std::fs::canonicalize(root).unwrap())
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn join(&self, rel: &str) -> PathBuf {
+        self.0.join(rel)
+    }
+
+    pub(crate) fn mkdir(&self, rel: &str) -> PathBuf {
+        let dir = self.join(rel);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write `contents` to `rel`, creating its parent directories.
+    pub(crate) fn write(&self, rel: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let file = self.join(rel);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, contents).unwrap();
+        file
+    }
+
+    pub(crate) fn touch(&self, rel: &str) {
+        self.write(rel, "");
+    }
+
+    pub(crate) fn display(&self) -> String {
+        self.0.to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        // Cleanup is best effort: panicking here would abort a test that
+        // is already unwinding from a failure.
+        let _cleanup = std::fs::remove_dir_all(&self.0);
+    }
+}
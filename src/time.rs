@@ -0,0 +1,51 @@
+//! Timestamp parsing for forge API responses, and date rendering for
+//! reports.
+
+#[cfg(feature = "forge-api")]
+mod rfc3339;
+#[cfg(feature = "forge-api")]
+pub(crate) use rfc3339::parse_rfc3339;
+
+/// `YYYY-MM-DD` (UTC) for seconds since the epoch, as Nix prints
+/// `lastModified`.
+pub(crate) fn format_date(seconds: u64) -> String {
+    let days = i64::try_from(seconds / 86_400).unwrap_or(i64::MAX);
+    let (year, month, day) = civil_from_days(days);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// The proleptic Gregorian date `days` after 1970-01-01 (Howard
+/// Hinnant's `civil_from_days`).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(0, "1970-01-01")]
+    #[case(1_700_000_000, "2023-11-14")]
+    #[case(1_709_208_000, "2024-02-29")]
+    #[case(951_868_799, "2000-02-29")]
+    fn formats(#[case] seconds: u64, #[case] expected: &str) {
+        assert_eq!(format_date(seconds), expected);
+    }
+}
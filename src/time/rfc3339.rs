@@ -0,0 +1,121 @@
+//! RFC 3339 timestamps, as forge APIs emit them.
+
+/// Seconds since the epoch for an RFC 3339 timestamp as forge APIs emit
+/// them: `2024-01-02T03:04:05Z`, `2024-01-02T03:04:05.123+02:00`.
+/// Fractional seconds are dropped. `None` for anything else, including
+/// instants before 1970.
+pub(crate) fn parse_rfc3339(input: &str) -> Option<u64> {
+    let bytes = input.as_bytes();
+    if !input.is_ascii()
+        || bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || bytes[13] != b':'
+    {
+        return None;
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') || bytes[16] != b':' {
+        return None;
+    }
+    let year = digits(&input[0..4])?;
+    let month = digits(&input[5..7])?;
+    let day = digits(&input[8..10])?;
+    let hour = digits(&input[11..13])?;
+    let minute = digits(&input[14..16])?;
+    let second = digits(&input[17..19])?;
+    if !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &input[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let end = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        if end == 0 {
+            return None;
+        }
+        rest = &fraction[end..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (h, m) = rest[1..].split_once(':')?;
+            if h.len() != 2 || m.len() != 2 {
+                return None;
+            }
+            let (h, m) = (digits(h)?, digits(m)?);
+            if h > 23 || m > 59 {
+                return None;
+            }
+            sign * (h * 3600 + m * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let local = days * 86_400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(local - offset).ok()
+}
+
+fn digits(s: &str) -> Option<i64> {
+    if s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard
+/// Hinnant's `days_from_civil`).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("1970-01-01T00:00:00Z", Some(0))]
+    #[case("2023-11-14T22:13:20Z", Some(1_700_000_000))]
+    #[case("2023-11-15T00:13:20+02:00", Some(1_700_000_000))]
+    #[case("2023-11-14T22:13:20.123-00:00", Some(1_700_000_000))]
+    #[case("2024-02-29T12:00:00Z", Some(1_709_208_000))]
+    #[case("2023-02-29T12:00:00Z", None)]
+    #[case("1969-12-31T23:59:59Z", None)]
+    #[case("2023-11-14 22:13:20", None)]
+    #[case("2023-11-14T22:13:20.Z", None)]
+    #[case("not a date at all....", None)]
+    #[case("2023-11-14T22:13:2éZ", None)]
+    fn parses(#[case] input: &str, #[case] expected: Option<u64>) {
+        assert_eq!(parse_rfc3339(input), expected);
+    }
+}